@@ -1,34 +1,383 @@
+mod blip;
+mod envelope;
+mod length;
+mod mixer;
+mod noise;
+mod square;
+mod wave;
+
+use crate::bus;
+use crate::model;
+use crate::state;
+
+// The channels run once per M-cycle, so their timers move in steps of this
+// many T-cycles.
+const TICK: u32 = 4;
+
+// Receives the resampled stereo output of the APU, as interleaved left and
+// right samples.
+pub trait AudioSink {
+    fn push_samples(&mut self, samples: &[i16]);
+
+    // Sinks that return true here also get each channel's output on its own,
+    // panned and scaled exactly as it is in the mix.
+    fn wants_channels(&self) -> bool {
+        false
+    }
+
+    fn push_channel_samples(&mut self, _channel: usize, _samples: &[i16]) {}
+}
+
+// Receives every write to the sound registers, stamped with the number of
+// T-cycles the APU had run for when it happened.
+pub trait RegisterLog {
+    fn log_write(&mut self, cycle: u64, address: u16, value: u8);
+
+    // Called with the APU's cycle count when the log is detached, so the
+    // time since the last write can be accounted for.
+    fn finish(&mut self, _cycle: u64) {}
+}
+
 pub struct Apu {
-    ch1_len_and_duty: u8,
-    ch1_vol_envelope: u8,
+    ch1: square::Square,
+    ch2: square::Square,
+
+    ch3: wave::Wave,
+
+    ch4: noise::Noise,
+
+    // Next frame sequencer step, 0-7.
+    frame_step: u8,
+
     enabled: u8,
     pan: u8,
     volume: u8,
+
+    // T-cycles run since power on.
+    cycles: u64,
+
+    mixer: Option<mixer::Mixer>,
+    register_log: Option<Box<dyn RegisterLog>>,
+}
+
+// Bits that read back as 1 regardless of what was written, indexed from
+// 0xFF10. Write-only bits and unmapped registers read as set.
+const READ_MASKS: [u8; 0x20] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+
+impl Default for Apu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Apu {
     pub fn new() -> Self {
         Apu {
-            ch1_len_and_duty: 0,
-            ch1_vol_envelope: 0,
+            ch1: square::Square::new(true),
+            ch2: square::Square::new(false),
+            ch3: wave::Wave::new(),
+            ch4: noise::Noise::new(),
+            frame_step: 0,
             enabled: 0,
             pan: 0,
             volume: 0,
+            cycles: 0,
+            mixer: None,
+            register_log: None,
         }
     }
 
-    // pub fn read(&self, address: u16) -> u8 {
-    //     return 0;
-    // }
+    // Only wave RAM access differs between the models emulated.
+    pub fn set_model(&mut self, model: model::Model) {
+        self.ch3.set_dmg(!model.is_cgb());
+    }
 
-    pub fn write(&mut self, address: u16, value: u8) {
+    // Starts delivering audio to `sink`, resampled to `sample_rate`.
+    pub fn set_sink(&mut self, sink: Box<dyn AudioSink>, sample_rate: u32) {
+        self.mixer = Some(mixer::Mixer::new(sink, sample_rate));
+    }
+
+    // Passes any audio the mixer is holding on to to the sink.
+    pub fn flush_audio(&mut self) {
+        if let Some(mixer) = &mut self.mixer {
+            mixer.flush();
+        }
+    }
+
+    pub fn set_register_log(&mut self, log: Box<dyn RegisterLog>) {
+        self.finish_register_log();
+        self.register_log = Some(log);
+    }
+
+    // Detaches the sink, once the mixer has handed it everything, and the
+    // register log, once it has been told how long the APU ran for.
+    // Recorders finish their files when they're dropped.
+    pub fn stop_recording(&mut self) {
+        self.mixer = None;
+        self.finish_register_log();
+        self.register_log = None;
+    }
+
+    fn finish_register_log(&mut self) {
+        if let Some(log) = &mut self.register_log {
+            log.finish(self.cycles);
+        }
+    }
+
+    // Runs `cycles` M-cycles at once. The mixer samples the channels every
+    // M-cycle, so with a sink attached they still run one at a time.
+    // Otherwise only the last one does, since the wave channel remembers
+    // whether it fetched a sample during it.
+    pub fn advance(&mut self, cycles: u64) {
+        if self.mixer.is_some() {
+            for _ in 0..cycles {
+                self.tick(TICK);
+            }
+        } else if cycles > 0 {
+            if cycles > 1 {
+                self.tick((cycles as u32 - 1) * TICK);
+            }
+            self.tick(TICK);
+        }
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.cycles += cycles as u64;
+        if self.enabled != 0 {
+            self.ch1.tick(cycles);
+            self.ch2.tick(cycles);
+            self.ch3.tick(cycles);
+            self.ch4.tick(cycles);
+        }
+
+        if self.mixer.is_some() {
+            let levels = self.output_levels();
+            if let Some(mixer) = &mut self.mixer {
+                mixer.update(cycles, levels);
+            }
+        }
+    }
+
+    fn output_levels(&self) -> [[f32; 2]; 4] {
+        let digital = self.channel_outputs();
+        let analog = [
+            mixer::dac(digital[0], self.ch1.dac_enabled()),
+            mixer::dac(digital[1], self.ch2.dac_enabled()),
+            mixer::dac(digital[2], self.ch3.dac_enabled()),
+            mixer::dac(digital[3], self.ch4.dac_enabled()),
+        ];
+        mixer::pan(analog, self.pan, self.volume)
+    }
+
+    // Called at 512 Hz, on the falling edge of DIV bit 4.
+    pub fn clock_frame_sequencer(&mut self) {
+        if self.enabled == 0 {
+            return;
+        }
+
+        if self.frame_step & 1 == 0 {
+            self.ch1.clock_length();
+            self.ch2.clock_length();
+            self.ch3.clock_length();
+            self.ch4.clock_length();
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.ch1.clock_sweep();
+        }
+        if self.frame_step == 7 {
+            self.ch1.clock_envelope();
+            self.ch2.clock_envelope();
+            self.ch4.clock_envelope();
+        }
+        self.frame_step = (self.frame_step + 1) % 8;
+    }
+
+    // Current 4-bit output of each channel, before the DACs.
+    pub fn channel_outputs(&self) -> [u8; 4] {
+        [
+            self.ch1.output(),
+            self.ch2.output(),
+            self.ch3.output(),
+            self.ch4.output(),
+        ]
+    }
+
+    pub fn read(&self, address: u16) -> Result<u8, bus::BusError> {
+        let value = match address {
+            0xFF10..=0xFF14 => self.ch1.read(address - 0xFF10),
+            0xFF16..=0xFF19 => self.ch2.read(address - 0xFF15),
+            0xFF1A..=0xFF1E => self.ch3.read(address - 0xFF1A),
+            0xFF20..=0xFF23 => self.ch4.read(address - 0xFF1F),
+            0xFF24 => self.volume,
+            0xFF25 => self.pan,
+            0xFF26 => self.enabled | self.channels_active(),
+            0xFF30..=0xFF3F => return Ok(self.ch3.read_ram(address - 0xFF30)),
+            0xFF15 | 0xFF1F | 0xFF27..=0xFF2F => 0xFF,
+            _ => return Err(bus::BusError::Read(address)),
+        };
+        Ok(value | READ_MASKS[(address - 0xFF10) as usize])
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), bus::BusError> {
+        if !(0xFF10..=0xFF3F).contains(&address) {
+            return Err(bus::BusError::Write(address, value));
+        }
+        if let Some(log) = &mut self.register_log {
+            log.log_write(self.cycles, address, value);
+        }
+
+        // While powered off only NR52, wave RAM and the length counters
+        // accept writes.
+        if self.enabled == 0 {
+            match address {
+                0xFF11 => self.ch1.write_length(value),
+                0xFF16 => self.ch2.write_length(value),
+                0xFF1B => self.ch3.write_length(value),
+                0xFF20 => self.ch4.write_length(value),
+                0xFF26 => self.write_enabled(value),
+                0xFF30..=0xFF3F => self.ch3.write_ram(address - 0xFF30, value),
+                _ => (),
+            }
+            return Ok(());
+        }
+
+        let next_step_clocks_length = self.frame_step & 1 == 0;
         match address {
-            0xFF11 => self.ch1_len_and_duty = value,
-            0xFF12 => self.ch1_vol_envelope = value,
+            0xFF10..=0xFF14 => self
+                .ch1
+                .write(address - 0xFF10, value, next_step_clocks_length),
+            0xFF16..=0xFF19 => self
+                .ch2
+                .write(address - 0xFF15, value, next_step_clocks_length),
+            0xFF1A..=0xFF1E => self
+                .ch3
+                .write(address - 0xFF1A, value, next_step_clocks_length),
+            0xFF20..=0xFF23 => self
+                .ch4
+                .write(address - 0xFF1F, value, next_step_clocks_length),
             0xFF24 => self.volume = value,
             0xFF25 => self.pan = value,
-            0xFF26 => self.enabled = value & 0x80,
-            _ => panic!("apu: invalid write address {:#x}", address),
+            0xFF26 => self.write_enabled(value),
+            0xFF30..=0xFF3F => self.ch3.write_ram(address - 0xFF30, value),
+            _ => (),
+        }
+        Ok(())
+    }
+
+    fn channels_active(&self) -> u8 {
+        (self.ch1.enabled as u8)
+            | (self.ch2.enabled as u8) << 1
+            | (self.ch3.enabled as u8) << 2
+            | (self.ch4.enabled as u8) << 3
+    }
+
+    fn write_enabled(&mut self, value: u8) {
+        if value & 0x80 == 0 && self.enabled != 0 {
+            // Powering off clears every register but leaves wave RAM and, on
+            // DMG, the length counters alone.
+            self.ch1.power_off();
+            self.ch2.power_off();
+            self.ch3.power_off();
+            self.ch4.power_off();
+            self.pan = 0;
+            self.volume = 0;
+        }
+        if value & 0x80 != 0 && self.enabled == 0 {
+            self.frame_step = 0;
         }
+        self.enabled = value & 0x80;
+    }
+
+    // The sink and register log belong to whoever is listening, so they
+    // aren't part of the state.
+    pub fn save_state(&self, state: &mut state::Writer) {
+        self.ch1.save_state(state);
+        self.ch2.save_state(state);
+        self.ch3.save_state(state);
+        self.ch4.save_state(state);
+        state.u8(self.frame_step);
+        state.u8(self.enabled);
+        state.u8(self.pan);
+        state.u8(self.volume);
+        state.u64(self.cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.ch1.load_state(state)?;
+        self.ch2.load_state(state)?;
+        self.ch3.load_state(state)?;
+        self.ch4.load_state(state)?;
+        self.frame_step = state.u8()?;
+        self.enabled = state.u8()?;
+        self.pan = state.u8()?;
+        self.volume = state.u8()?;
+        self.cycles = state.u64()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn powered() -> Apu {
+        let mut apu = Apu::new();
+        apu.write(0xFF26, 0x80).unwrap();
+        apu
+    }
+
+    #[test]
+    fn reads_or_in_the_unused_bits() {
+        let mut apu = powered();
+        for address in 0xFF10..=0xFF25 {
+            apu.write(address, 0x00).unwrap();
+        }
+        #[rustfmt::skip]
+        let expected = [
+            0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+            0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+            0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+            0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+            0x00, 0x00, 0xF0,             // NR50-NR52
+        ];
+        for (address, expected) in (0xFF10..).zip(expected) {
+            assert_eq!(apu.read(address), Ok(expected), "{:#06x}", address);
+        }
+        for address in 0xFF27..=0xFF2F {
+            assert_eq!(apu.read(address), Ok(0xFF), "{:#06x}", address);
+        }
+    }
+
+    #[test]
+    fn written_bits_read_back() {
+        let mut apu = powered();
+        for address in 0xFF10..=0xFF25 {
+            apu.write(address, 0xFF).unwrap();
+        }
+        for address in 0xFF10..=0xFF25 {
+            assert_eq!(apu.read(address), Ok(0xFF), "{:#06x}", address);
+        }
+        // The triggers started every channel.
+        assert_eq!(apu.read(0xFF26), Ok(0xFF));
+    }
+
+    #[test]
+    fn nr52_shows_power_and_active_channels() {
+        let mut apu = Apu::new();
+        assert_eq!(apu.read(0xFF26), Ok(0x70));
+        apu.write(0xFF26, 0x80).unwrap();
+        apu.write(0xFF17, 0xF0).unwrap();
+        apu.write(0xFF19, 0x80).unwrap();
+        assert_eq!(apu.read(0xFF26), Ok(0xF2));
+        // Wave RAM isn't masked.
+        apu.write(0xFF30, 0x12).unwrap();
+        assert_eq!(apu.read(0xFF30), Ok(0x12));
     }
 }
@@ -0,0 +1,100 @@
+// Band-limited resampler for the APU output. Channel output is a sum of
+// steps, so rather than filtering every input sample, each change in
+// amplitude is added to the output buffer as a band-limited impulse, and the
+// buffer is integrated when samples are read out. Works along the lines of
+// blargg's blip_buf.
+
+const FRAC_BITS: u32 = 32;
+const PHASE_BITS: u32 = 5;
+const PHASES: usize = 1 << PHASE_BITS;
+const WIDTH: usize = 16;
+
+pub struct Blip {
+    factor: u64,
+    offset: u64,
+    buffer: Vec<f32>,
+    integrator: f32,
+    kernel: Vec<[f32; WIDTH]>,
+}
+
+impl Blip {
+    pub fn new(clock_rate: u32, sample_rate: u32) -> Self {
+        let factor = ((sample_rate as u64) << FRAC_BITS) / clock_rate as u64;
+        Blip {
+            factor,
+            offset: 0,
+            buffer: vec![0.0; WIDTH * 2],
+            integrator: 0.0,
+            kernel: kernel(),
+        }
+    }
+
+    // Adds a change in amplitude `clock` input clocks into the current frame.
+    pub fn add_delta(&mut self, clock: u32, delta: f32) {
+        let position = self.offset + clock as u64 * self.factor;
+        let index = (position >> FRAC_BITS) as usize;
+        let phase = ((position >> (FRAC_BITS - PHASE_BITS)) & (PHASES as u64 - 1)) as usize;
+
+        if self.buffer.len() < index + WIDTH {
+            self.buffer.resize(index + WIDTH, 0.0);
+        }
+        for (sample, tap) in self.buffer[index..index + WIDTH]
+            .iter_mut()
+            .zip(&self.kernel[phase])
+        {
+            *sample += delta * tap;
+        }
+    }
+
+    // Ends the current frame after `clocks` input clocks; the samples it
+    // completed become available to `read_samples`.
+    pub fn end_frame(&mut self, clocks: u32) {
+        self.offset += clocks as u64 * self.factor;
+    }
+
+    pub fn samples_available(&self) -> usize {
+        (self.offset >> FRAC_BITS) as usize
+    }
+
+    pub fn read_samples(&mut self, out: &mut Vec<f32>) {
+        let count = self.samples_available();
+        if self.buffer.len() < count + WIDTH {
+            self.buffer.resize(count + WIDTH, 0.0);
+        }
+
+        for &delta in &self.buffer[..count] {
+            self.integrator += delta;
+            out.push(self.integrator);
+        }
+
+        self.buffer.drain(..count);
+        self.buffer.resize(self.buffer.len() + count, 0.0);
+        self.offset -= (count as u64) << FRAC_BITS;
+    }
+}
+
+// Windowed sinc impulses, one per sub-sample phase, each normalized so that a
+// step always settles at exactly its full height.
+fn kernel() -> Vec<[f32; WIDTH]> {
+    let cutoff = 0.9;
+    (0..PHASES)
+        .map(|phase| {
+            let mut taps = [0.0f32; WIDTH];
+            let shift = phase as f64 / PHASES as f64;
+            for (i, tap) in taps.iter_mut().enumerate() {
+                let x = i as f64 - (WIDTH / 2) as f64 + 1.0 - shift;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    let t = std::f64::consts::PI * x * cutoff;
+                    t.sin() / t
+                };
+                let window = 0.5 + 0.5 * (std::f64::consts::PI * x / (WIDTH / 2) as f64).cos();
+                *tap = (sinc * window) as f32;
+            }
+            let sum: f32 = taps.iter().sum();
+            taps.iter_mut().for_each(|tap| *tap /= sum);
+            taps
+        })
+        .collect()
+}
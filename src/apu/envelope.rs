@@ -0,0 +1,172 @@
+use crate::state;
+
+pub struct Envelope {
+    register: u8,
+    volume: u8,
+    timer: u8,
+    // Set once the volume can't go any further in its direction.
+    stopped: bool,
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Envelope {
+            register: 0,
+            volume: 0,
+            timer: 0,
+            stopped: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    // Writes while the channel plays nudge the volume ("zombie mode"), which
+    // some games use to change it without retriggering.
+    pub fn write(&mut self, value: u8, playing: bool) {
+        if playing {
+            if self.period() == 0 && !self.stopped {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 {
+                self.volume += 2;
+            }
+            if (self.register ^ value) & 0x08 != 0 {
+                self.volume = 16u8.wrapping_sub(self.volume);
+            }
+            self.volume &= 0x0F;
+        }
+        self.register = value;
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn volume(&self) -> u8 {
+        self.volume
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+        self.stopped = false;
+    }
+
+    pub fn clock(&mut self) {
+        let period = self.period();
+        if period == 0 {
+            return;
+        }
+
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = period;
+            if self.register & 0x08 != 0 && self.volume < 15 {
+                self.volume += 1;
+            } else if self.register & 0x08 == 0 && self.volume > 0 {
+                self.volume -= 1;
+            } else {
+                self.stopped = true;
+            }
+        }
+    }
+
+    fn period(&self) -> u8 {
+        self.register & 0x07
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u8(self.register);
+        state.u8(self.volume);
+        state.u8(self.timer);
+        state.bool(self.stopped);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.register = state.u8()?;
+        self.volume = state.u8()?;
+        self.timer = state.u8()?;
+        self.stopped = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triggered(register: u8) -> Envelope {
+        let mut envelope = Envelope::new();
+        envelope.write(register, false);
+        envelope.trigger();
+        envelope
+    }
+
+    #[test]
+    fn clocks_toward_the_end_and_stops() {
+        let mut envelope = triggered(0x21);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 1);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 0);
+        assert!(!envelope.stopped);
+        envelope.clock();
+        assert!(envelope.stopped);
+
+        let mut envelope = triggered(0xE9);
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        envelope.clock();
+        assert!(envelope.stopped);
+    }
+
+    #[test]
+    fn writes_while_silent_leave_the_volume() {
+        let mut envelope = triggered(0x50);
+        envelope.write(0x38, false);
+        assert_eq!(envelope.volume(), 5);
+    }
+
+    #[test]
+    fn zombie_writes() {
+        // A period of 0 with the envelope still running adds one.
+        let mut envelope = triggered(0x58);
+        envelope.write(0x58, true);
+        assert_eq!(envelope.volume(), 6);
+
+        // Otherwise decreasing mode adds two, and increasing adds nothing.
+        let mut envelope = triggered(0x51);
+        envelope.write(0x51, true);
+        assert_eq!(envelope.volume(), 7);
+        let mut envelope = triggered(0x59);
+        envelope.write(0x59, true);
+        assert_eq!(envelope.volume(), 5);
+
+        // Switching direction mirrors the volume.
+        let mut envelope = triggered(0x51);
+        envelope.write(0x59, true);
+        assert_eq!(envelope.volume(), 9);
+        let mut envelope = triggered(0x59);
+        envelope.write(0x51, true);
+        assert_eq!(envelope.volume(), 11);
+
+        // Only 4 bits are kept.
+        let mut envelope = triggered(0xF1);
+        envelope.write(0xF1, true);
+        assert_eq!(envelope.volume(), 1);
+    }
+
+    #[test]
+    fn zombie_write_after_stopping() {
+        let mut envelope = triggered(0x11);
+        envelope.clock();
+        envelope.clock();
+        assert!(envelope.stopped);
+        envelope.write(0x10, true);
+        assert_eq!(envelope.volume(), 2);
+        // Period 0 only adds one while the envelope is still running.
+        envelope.write(0x10, true);
+        assert_eq!(envelope.volume(), 4);
+    }
+}
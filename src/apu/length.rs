@@ -0,0 +1,120 @@
+use crate::state;
+
+pub struct Length {
+    max: u16,
+    counter: u16,
+    pub enabled: bool,
+}
+
+impl Length {
+    pub fn new(max: u16) -> Self {
+        Length {
+            max,
+            counter: 0,
+            enabled: false,
+        }
+    }
+
+    pub fn load(&mut self, value: u8) {
+        self.counter = self.max - value as u16;
+    }
+
+    // Returns true when the counter runs out and the channel must stop.
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            return self.counter == 0;
+        }
+        false
+    }
+
+    // Handles the length enable bit and trigger bit of an NRx4 write.
+    // `next_step_clocks` says whether the frame sequencer's next step will
+    // clock length counters; if it won't, enabling the counter clocks it once
+    // straight away, and a triggered counter that was reloaded from zero
+    // loses one count. Returns true if the extra clock disabled the channel.
+    pub fn write_control(&mut self, value: u8, next_step_clocks: bool) -> bool {
+        let was_enabled = self.enabled;
+        self.enabled = value & 0x40 != 0;
+        let trigger = value & 0x80 != 0;
+
+        let mut disable = false;
+        if !was_enabled && self.enabled && !next_step_clocks && self.counter > 0 {
+            self.counter -= 1;
+            disable = self.counter == 0 && !trigger;
+        }
+
+        if trigger && self.counter == 0 {
+            self.counter = self.max;
+            if self.enabled && !next_step_clocks {
+                self.counter -= 1;
+            }
+        }
+        disable
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u16(self.counter);
+        state.bool(self.enabled);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.counter = state.u16()?;
+        self.enabled = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loaded(value: u8) -> Length {
+        let mut length = Length::new(64);
+        length.load(value);
+        length
+    }
+
+    #[test]
+    fn enabling_in_the_first_half_clocks_once() {
+        let mut length = loaded(60);
+        assert!(!length.write_control(0x40, false));
+        assert_eq!(length.counter, 3);
+
+        let mut length = loaded(60);
+        assert!(!length.write_control(0x40, true));
+        assert_eq!(length.counter, 4);
+
+        // Only going from disabled to enabled counts.
+        let mut length = loaded(60);
+        length.write_control(0x40, true);
+        length.write_control(0x40, false);
+        assert_eq!(length.counter, 4);
+    }
+
+    #[test]
+    fn extra_clock_to_zero_disables() {
+        let mut length = loaded(63);
+        assert!(length.write_control(0x40, false));
+        assert_eq!(length.counter, 0);
+    }
+
+    #[test]
+    fn trigger_reloads_a_count_short() {
+        // With the extra clock taking the counter to 0 in the same write,
+        // the channel keeps playing with a full length less one.
+        let mut length = loaded(63);
+        assert!(!length.write_control(0xC0, false));
+        assert_eq!(length.counter, 63);
+
+        let mut length = loaded(64);
+        length.write_control(0xC0, false);
+        assert_eq!(length.counter, 63);
+        let mut length = loaded(64);
+        length.write_control(0xC0, true);
+        assert_eq!(length.counter, 64);
+        let mut length = loaded(64);
+        length.write_control(0x80, false);
+        assert_eq!(length.counter, 64);
+    }
+}
@@ -0,0 +1,256 @@
+use super::blip::Blip;
+use super::AudioSink;
+
+pub const CLOCK_RATE: u32 = 4_194_304;
+
+// How many T-cycles of output are gathered before samples are handed to the
+// sink.
+const FRAME_CLOCKS: u32 = 1 << 14;
+
+// One resampled stereo signal: either the full mix or a single channel.
+struct Track {
+    blips: [Blip; 2],
+    last: [f32; 2],
+
+    // State of the DMG's output coupling capacitors, which act as a
+    // high-pass filter and remove the DC offset the DACs produce.
+    capacitor: [f32; 2],
+}
+
+impl Track {
+    fn new(sample_rate: u32) -> Self {
+        Track {
+            blips: [
+                Blip::new(CLOCK_RATE, sample_rate),
+                Blip::new(CLOCK_RATE, sample_rate),
+            ],
+            last: [0.0; 2],
+            capacitor: [0.0; 2],
+        }
+    }
+
+    fn update(&mut self, clock: u32, level: [f32; 2]) {
+        for ((blip, &new), &old) in self.blips.iter_mut().zip(&level).zip(&self.last) {
+            if new != old {
+                blip.add_delta(clock, new - old);
+            }
+        }
+        self.last = level;
+    }
+
+    fn read(
+        &mut self,
+        clocks: u32,
+        charge_factor: f32,
+        scratch: &mut [Vec<f32>; 2],
+        out: &mut Vec<i16>,
+    ) {
+        for (blip, samples) in self.blips.iter_mut().zip(scratch.iter_mut()) {
+            blip.end_frame(clocks);
+            samples.clear();
+            blip.read_samples(samples);
+        }
+
+        out.clear();
+        for (&l, &r) in scratch[0].iter().zip(scratch[1].iter()) {
+            for (side, input) in [l, r].into_iter().enumerate() {
+                let output = input - self.capacitor[side];
+                self.capacitor[side] = input - output * charge_factor;
+                out.push((output.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+            }
+        }
+    }
+}
+
+pub struct Mixer {
+    sink: Box<dyn AudioSink>,
+    mix: Track,
+    // Only kept when the sink asks for per-channel output.
+    channels: Vec<Track>,
+    clock: u32,
+    charge_factor: f32,
+
+    scratch: [Vec<f32>; 2],
+    out: Vec<i16>,
+}
+
+impl Mixer {
+    pub fn new(sink: Box<dyn AudioSink>, sample_rate: u32) -> Self {
+        let channels = if sink.wants_channels() {
+            (0..4).map(|_| Track::new(sample_rate)).collect()
+        } else {
+            Vec::new()
+        };
+
+        Mixer {
+            sink,
+            mix: Track::new(sample_rate),
+            channels,
+            clock: 0,
+            charge_factor: 0.999958f32.powf(CLOCK_RATE as f32 / sample_rate as f32),
+            scratch: [Vec::new(), Vec::new()],
+            out: Vec::new(),
+        }
+    }
+
+    // Records the stereo level of each channel after `cycles` more T-cycles
+    // of output.
+    pub fn update(&mut self, cycles: u32, levels: [[f32; 2]; 4]) {
+        self.clock += cycles;
+
+        let mut mix = [0.0; 2];
+        for level in levels {
+            mix[0] += level[0];
+            mix[1] += level[1];
+        }
+        self.mix.update(self.clock, mix);
+        for (track, level) in self.channels.iter_mut().zip(levels) {
+            track.update(self.clock, level);
+        }
+
+        if self.clock >= FRAME_CLOCKS {
+            self.flush();
+        }
+    }
+
+    // Hands the sink everything mixed so far instead of waiting for the
+    // frame to fill up, such as before the emulator exits.
+    pub fn flush(&mut self) {
+        if self.clock == 0 {
+            return;
+        }
+        let clocks = std::mem::take(&mut self.clock);
+
+        self.mix
+            .read(clocks, self.charge_factor, &mut self.scratch, &mut self.out);
+        self.sink.push_samples(&self.out);
+
+        for (i, track) in self.channels.iter_mut().enumerate() {
+            track.read(clocks, self.charge_factor, &mut self.scratch, &mut self.out);
+            self.sink.push_channel_samples(i, &self.out);
+        }
+    }
+}
+
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// Converts a channel's 4-bit output to an analog level between -1 and 1. A
+// DAC that is switched off outputs nothing at all.
+pub fn dac(digital: u8, enabled: bool) -> f32 {
+    if enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+// Pans each channel according to NR51 and scales each side by NR50. The
+// levels of all four channels add up to at most 1.
+pub fn pan(analog: [f32; 4], pan: u8, volume: u8) -> [[f32; 2]; 4] {
+    let left_volume = ((volume >> 4) & 0x07) as f32 + 1.0;
+    let right_volume = (volume & 0x07) as f32 + 1.0;
+
+    let mut levels = [[0.0; 2]; 4];
+    for (i, (&value, level)) in analog.iter().zip(levels.iter_mut()).enumerate() {
+        if pan & (0x10 << i) != 0 {
+            level[0] = value * left_volume / 32.0;
+        }
+        if pan & (0x01 << i) != 0 {
+            level[1] = value * right_volume / 32.0;
+        }
+    }
+    levels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Collect {
+        mix: Rc<RefCell<Vec<i16>>>,
+        channels: Rc<RefCell<[Vec<i16>; 4]>>,
+        wants_channels: bool,
+    }
+
+    impl AudioSink for Collect {
+        fn push_samples(&mut self, samples: &[i16]) {
+            self.mix.borrow_mut().extend_from_slice(samples);
+        }
+
+        fn wants_channels(&self) -> bool {
+            self.wants_channels
+        }
+
+        fn push_channel_samples(&mut self, channel: usize, samples: &[i16]) {
+            self.channels.borrow_mut()[channel].extend_from_slice(samples);
+        }
+    }
+
+    // Runs the mixer for `seconds` at 44.1 kHz, with every channel at
+    // `level` on both sides, and returns what the sink got.
+    fn mix(sink: Collect, seconds: u32, level: f32) -> Collect {
+        let mut mixer = Mixer::new(Box::new(sink.clone()), 44100);
+        for _ in 0..seconds * CLOCK_RATE / 4 {
+            mixer.update(4, [[level; 2]; 4]);
+        }
+        mixer.flush();
+        sink
+    }
+
+    #[test]
+    fn sample_count_follows_the_rate() {
+        let sink = mix(Collect::default(), 1, 0.0);
+        let frames = sink.mix.borrow().len() / 2;
+        assert!((44090..=44100).contains(&frames), "{}", frames);
+        assert_eq!(sink.mix.borrow().len() % 2, 0);
+    }
+
+    #[test]
+    fn channels_get_as_many_samples_as_the_mix() {
+        let sink = mix(
+            Collect {
+                wants_channels: true,
+                ..Collect::default()
+            },
+            1,
+            0.1,
+        );
+        let len = sink.mix.borrow().len();
+        for channel in sink.channels.borrow().iter() {
+            assert_eq!(channel.len(), len);
+        }
+    }
+
+    #[test]
+    fn dc_offset_decays() {
+        let sink = mix(Collect::default(), 2, 0.2);
+        let mix = sink.mix.borrow();
+        let peak = mix.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!(peak > 20000, "{}", peak);
+        let tail = mix[mix.len() - 200..]
+            .iter()
+            .map(|s| s.unsigned_abs())
+            .max();
+        assert!(tail.unwrap() < 100, "{:?}", tail);
+    }
+
+    #[test]
+    fn dac_and_panning() {
+        assert_eq!(dac(0, true), -1.0);
+        assert_eq!(dac(15, true), 1.0);
+        assert_eq!(dac(15, false), 0.0);
+        // Channel 1 left only, channel 4 right only, at full and minimum
+        // master volume.
+        let levels = pan([1.0; 4], 0x18, 0x70);
+        assert_eq!(levels[0], [0.25, 0.0]);
+        assert_eq!(levels[3], [0.0, 1.0 / 32.0]);
+        assert_eq!(levels[1], [0.0, 0.0]);
+    }
+}
@@ -0,0 +1,248 @@
+use super::envelope::Envelope;
+use super::length::Length;
+use crate::state;
+
+const LFSR_15_PERIOD: u32 = 0x7FFF;
+const LFSR_7_PERIOD: u32 = 0x7F;
+
+pub struct Noise {
+    pub enabled: bool,
+
+    poly: u8,
+    lfsr: u16,
+    timer: i32,
+
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Noise {
+            enabled: false,
+            poly: 0,
+            lfsr: 0x7FFF,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    // Registers are numbered from NR40, so NR41 is 1 and NR44 is 4.
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            2 => self.envelope.read(),
+            3 => self.poly,
+            4 if self.length.enabled => 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            1 => self.length.load(value & 0x3F),
+            2 => {
+                self.envelope.write(value, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.poly = value,
+            4 => {
+                if self.length.write_control(value, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Noise::new();
+        self.length = length;
+        self.length.enabled = false;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 1 != 0 {
+            return 0;
+        }
+        self.envelope.volume()
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if !self.enabled {
+            return;
+        }
+        // Shifts of 14 and 15 stop the LFSR altogether.
+        let Some(period) = self.period() else {
+            return;
+        };
+
+        self.timer -= cycles as i32;
+        if self.timer > 0 {
+            return;
+        }
+        let steps = 1 + (-self.timer) as u32 / period as u32;
+        self.timer += steps as i32 * period;
+        self.step_lfsr(steps);
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    // Advances the LFSR by `steps` shifts. The sequence repeats every
+    // 32767 shifts in 15-bit mode and, once the upper bits have been
+    // flushed, every 127 shifts in 7-bit mode, so long runs are cut down to
+    // the equivalent short one first.
+    pub fn step_lfsr(&mut self, steps: u32) {
+        let steps = if self.narrow() {
+            if steps > 15 + LFSR_7_PERIOD {
+                15 + (steps - 15) % LFSR_7_PERIOD
+            } else {
+                steps
+            }
+        } else {
+            steps % LFSR_15_PERIOD
+        };
+
+        for _ in 0..steps {
+            let feedback = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (feedback << 14);
+            if self.narrow() {
+                self.lfsr = (self.lfsr & !0x40) | (feedback << 6);
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        if self.envelope.dac_enabled() {
+            self.enabled = true;
+        }
+        self.lfsr = 0x7FFF;
+        self.timer = self.period().unwrap_or(0);
+        self.envelope.trigger();
+    }
+
+    fn narrow(&self) -> bool {
+        self.poly & 0x08 != 0
+    }
+
+    fn period(&self) -> Option<i32> {
+        let shift = self.poly >> 4;
+        if shift >= 14 {
+            return None;
+        }
+        let divisor = match self.poly & 0x07 {
+            0 => 8,
+            code => code as i32 * 16,
+        };
+        Some(divisor << shift)
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.bool(self.enabled);
+        state.u8(self.poly);
+        state.u16(self.lfsr);
+        state.i32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.enabled = state.bool()?;
+        self.poly = state.u8()?;
+        self.lfsr = state.u16()?;
+        self.timer = state.i32()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(poly: u8, lfsr: u16) -> Noise {
+        let mut noise = Noise::new();
+        noise.poly = poly;
+        noise.lfsr = lfsr;
+        noise
+    }
+
+    #[test]
+    fn bulk_steps_match_single_steps() {
+        for poly in [0x00, 0x08] {
+            for start in [0x7FFF, 0x1234, 0x0001] {
+                for steps in [0, 1, 15, 16, 127, 142, 143, 1000, 32767, 32768, 70000] {
+                    let mut bulk = noise(poly, start);
+                    bulk.step_lfsr(steps);
+                    let mut single = noise(poly, start);
+                    for _ in 0..steps {
+                        single.step_lfsr(1);
+                    }
+                    assert_eq!(
+                        bulk.lfsr, single.lfsr,
+                        "poly {:#04x}, start {:#06x}, {} steps",
+                        poly, start, steps
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lfsr_periods() {
+        let mut wide = noise(0x00, 0x7FFF);
+        for _ in 0..LFSR_15_PERIOD {
+            wide.step_lfsr(1);
+        }
+        assert_eq!(wide.lfsr, 0x7FFF);
+
+        let mut narrow = noise(0x08, 0x7FFF);
+        narrow.step_lfsr(15);
+        let start = narrow.lfsr;
+        for _ in 0..LFSR_7_PERIOD {
+            narrow.step_lfsr(1);
+        }
+        assert_eq!(narrow.lfsr, start);
+    }
+
+    #[test]
+    fn long_ticks_match_short_ones() {
+        // A shift every 8 T-cycles in either width, and every 64.
+        for poly in [0x00, 0x08, 0x21] {
+            let mut long = noise(poly, 0x7FFF);
+            long.enabled = true;
+            long.timer = 5;
+            long.tick(4 * 1000);
+            let mut short = noise(poly, 0x7FFF);
+            short.enabled = true;
+            short.timer = 5;
+            for _ in 0..1000 {
+                short.tick(4);
+            }
+            assert_eq!(long.lfsr, short.lfsr);
+            assert_eq!(long.timer, short.timer);
+        }
+    }
+}
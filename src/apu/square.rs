@@ -0,0 +1,301 @@
+use super::envelope::Envelope;
+use super::length::Length;
+use crate::state;
+
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+pub struct Square {
+    has_sweep: bool,
+    pub enabled: bool,
+
+    duty: u8,
+    duty_step: u8,
+    frequency: u16,
+    timer: i32,
+
+    length: Length,
+    envelope: Envelope,
+
+    sweep_register: u8,
+    sweep_enabled: bool,
+    sweep_timer: u8,
+    sweep_shadow: u16,
+    sweep_negated: bool,
+}
+
+impl Square {
+    pub fn new(has_sweep: bool) -> Self {
+        Square {
+            has_sweep,
+            enabled: false,
+            duty: 0,
+            duty_step: 0,
+            frequency: 0,
+            timer: 0,
+            length: Length::new(64),
+            envelope: Envelope::new(),
+            sweep_register: 0,
+            sweep_enabled: false,
+            sweep_timer: 0,
+            sweep_shadow: 0,
+            sweep_negated: false,
+        }
+    }
+
+    // Registers are numbered from NRx0, so NR10 is 0 and NR24 is 4.
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 if self.has_sweep => self.sweep_register,
+            1 => self.duty << 6,
+            2 => self.envelope.read(),
+            4 if self.length.enabled => 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            0 if self.has_sweep => self.write_sweep(value),
+            0 => (),
+            1 => {
+                self.duty = value >> 6;
+                self.length.load(value & 0x3F);
+            }
+            2 => {
+                self.envelope.write(value, self.enabled);
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            }
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.length.write_control(value, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // Length is the only state that survives powering the APU off on DMG.
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value & 0x3F);
+    }
+
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(64));
+        *self = Square::new(self.has_sweep);
+        self.length = length;
+        self.length.enabled = false;
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        let high = DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step) & 1;
+        high * self.envelope.volume()
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.timer -= cycles as i32;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.duty_step = (self.duty_step + 1) % 8;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        if !self.has_sweep {
+            return;
+        }
+
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer > 0 {
+            return;
+        }
+        self.sweep_timer = self.sweep_period();
+
+        if self.sweep_enabled && self.sweep_register & 0x70 != 0 {
+            let frequency = self.next_sweep_frequency();
+            if frequency <= 0x07FF && self.sweep_shift() != 0 {
+                self.sweep_shadow = frequency;
+                self.frequency = frequency;
+                // The new frequency is checked for overflow again right away,
+                // but the result is thrown away.
+                self.next_sweep_frequency();
+            }
+        }
+    }
+
+    fn trigger(&mut self) {
+        if self.envelope.dac_enabled() {
+            self.enabled = true;
+        }
+        self.timer = self.period();
+        self.envelope.trigger();
+
+        if self.has_sweep {
+            self.sweep_shadow = self.frequency;
+            self.sweep_timer = self.sweep_period();
+            self.sweep_negated = false;
+            self.sweep_enabled = self.sweep_register & 0x77 != 0;
+            if self.sweep_shift() != 0 {
+                self.next_sweep_frequency();
+            }
+        }
+    }
+
+    fn write_sweep(&mut self, value: u8) {
+        // Leaving negate mode after a negated calculation since the last
+        // trigger disables the channel.
+        if self.sweep_negated && self.sweep_register & 0x08 != 0 && value & 0x08 == 0 {
+            self.enabled = false;
+        }
+        self.sweep_register = value & 0x7F;
+    }
+
+    fn next_sweep_frequency(&mut self) -> u16 {
+        let delta = self.sweep_shadow >> self.sweep_shift();
+        let frequency = if self.sweep_register & 0x08 != 0 {
+            self.sweep_negated = true;
+            self.sweep_shadow - delta
+        } else {
+            self.sweep_shadow + delta
+        };
+
+        if frequency > 0x07FF {
+            self.enabled = false;
+        }
+        frequency
+    }
+
+    fn sweep_shift(&self) -> u8 {
+        self.sweep_register & 0x07
+    }
+
+    // A period of 0 reloads the sweep timer with 8.
+    fn sweep_period(&self) -> u8 {
+        match (self.sweep_register >> 4) & 0x07 {
+            0 => 8,
+            period => period,
+        }
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 4
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.bool(self.enabled);
+        state.u8(self.duty);
+        state.u8(self.duty_step);
+        state.u16(self.frequency);
+        state.i32(self.timer);
+        self.length.save_state(state);
+        self.envelope.save_state(state);
+        state.u8(self.sweep_register);
+        state.bool(self.sweep_enabled);
+        state.u8(self.sweep_timer);
+        state.u16(self.sweep_shadow);
+        state.bool(self.sweep_negated);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.enabled = state.bool()?;
+        self.duty = state.u8()?;
+        self.duty_step = state.u8()?;
+        self.frequency = state.u16()?;
+        self.timer = state.i32()?;
+        self.length.load_state(state)?;
+        self.envelope.load_state(state)?;
+        self.sweep_register = state.u8()?;
+        self.sweep_enabled = state.bool()?;
+        self.sweep_timer = state.u8()?;
+        self.sweep_shadow = state.u16()?;
+        self.sweep_negated = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Channel 1 triggered at full volume with the given sweep.
+    fn channel1(sweep: u8, frequency: u16) -> Square {
+        let mut square = Square::new(true);
+        square.write(0, sweep, false);
+        square.write(2, 0xF0, false);
+        square.write(3, frequency as u8, false);
+        square.write(4, 0x80 | (frequency >> 8) as u8, false);
+        square
+    }
+
+    #[test]
+    fn sweep_overflow_on_trigger_disables() {
+        assert!(!channel1(0x11, 0x7FF).enabled);
+        assert!(channel1(0x11, 0x500).enabled);
+        // Negating can't overflow.
+        assert!(channel1(0x19, 0x7FF).enabled);
+    }
+
+    #[test]
+    fn sweep_overflow_after_an_update_disables() {
+        let mut square = channel1(0x11, 0x500);
+        // 0x500 + 0x280 is written back, and the check that follows, for
+        // 0x780 + 0x3C0, overflows.
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x780);
+        assert!(!square.enabled);
+    }
+
+    #[test]
+    fn sweep_updates_frequency() {
+        let mut square = channel1(0x21, 0x100);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x100);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x180);
+        assert!(square.enabled);
+        let mut square = channel1(0x19, 0x100);
+        square.clock_sweep();
+        assert_eq!(square.frequency, 0x080);
+    }
+
+    #[test]
+    fn clearing_negate_after_a_negated_calculation_disables() {
+        let mut square = channel1(0x19, 0x400);
+        square.write(0, 0x11, false);
+        assert!(!square.enabled);
+
+        // Without a calculation since the trigger, nothing happens.
+        let mut square = channel1(0x18, 0x400);
+        square.write(0, 0x10, false);
+        assert!(square.enabled);
+
+        // Nor when the calculation added.
+        let mut square = channel1(0x11, 0x400);
+        square.write(0, 0x19, false);
+        square.write(0, 0x11, false);
+        assert!(square.enabled);
+    }
+}
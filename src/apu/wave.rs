@@ -0,0 +1,308 @@
+use super::length::Length;
+use super::TICK;
+use crate::state;
+
+pub struct Wave {
+    pub enabled: bool,
+    dac_enabled: bool,
+
+    level: u8,
+    frequency: u16,
+    timer: i32,
+    position: u8,
+    sample: u8,
+    // Set for the tick in which the channel fetched a byte from wave RAM;
+    // that is the only time the CPU can reach wave RAM on DMG while the
+    // channel is playing.
+    fetched: bool,
+    // The wave RAM quirks below are those of the DMG, MGB and SGB. On CGB
+    // the playing byte can always be reached and retriggering is harmless.
+    dmg: bool,
+
+    length: Length,
+    ram: [u8; 16],
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Wave {
+            enabled: false,
+            dac_enabled: false,
+            level: 0,
+            frequency: 0,
+            timer: 0,
+            position: 0,
+            sample: 0,
+            fetched: false,
+            dmg: true,
+            length: Length::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    // Registers are numbered from NR30, so NR30 is 0 and NR34 is 4.
+    pub fn read(&self, register: u16) -> u8 {
+        match register {
+            0 if self.dac_enabled => 0x80,
+            2 => self.level << 5,
+            4 if self.length.enabled => 0x40,
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, register: u16, value: u8, next_step_clocks_length: bool) {
+        match register {
+            0 => {
+                self.dac_enabled = value & 0x80 != 0;
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            }
+            1 => self.length.load(value),
+            2 => self.level = (value >> 5) & 0x03,
+            3 => self.frequency = (self.frequency & 0x0700) | value as u16,
+            4 => {
+                self.frequency = (self.frequency & 0x00FF) | ((value as u16 & 0x07) << 8);
+                if self.length.write_control(value, next_step_clocks_length) {
+                    self.enabled = false;
+                }
+                if value & 0x80 != 0 {
+                    self.trigger();
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_dmg(&mut self, dmg: bool) {
+        self.dmg = dmg;
+    }
+
+    pub fn write_length(&mut self, value: u8) {
+        self.length.load(value);
+    }
+
+    pub fn power_off(&mut self) {
+        let length = std::mem::replace(&mut self.length, Length::new(256));
+        let ram = self.ram;
+        let dmg = self.dmg;
+        *self = Wave::new();
+        self.dmg = dmg;
+        self.length = length;
+        self.length.enabled = false;
+        self.ram = ram;
+    }
+
+    pub fn read_ram(&self, offset: u16) -> u8 {
+        if !self.enabled {
+            return self.ram[offset as usize];
+        }
+        if self.fetched || !self.dmg {
+            self.ram[self.position as usize / 2]
+        } else {
+            0xFF
+        }
+    }
+
+    pub fn write_ram(&mut self, offset: u16, value: u8) {
+        if !self.enabled {
+            self.ram[offset as usize] = value;
+        } else if self.fetched || !self.dmg {
+            self.ram[self.position as usize / 2] = value;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.level {
+            0 => 0,
+            level => self.sample >> (level - 1),
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        self.fetched = false;
+        if !self.enabled {
+            return;
+        }
+
+        self.timer -= cycles as i32;
+        while self.timer <= 0 {
+            self.timer += self.period();
+            self.position = (self.position + 1) % 32;
+            let byte = self.ram[self.position as usize / 2];
+            self.sample = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+            self.fetched = true;
+        }
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    fn trigger(&mut self) {
+        // On DMG, retriggering just as the channel fetches a sample corrupts
+        // the first bytes of wave RAM with the ones about to be read. The
+        // timer only moves a tick at a time, so that's when the fetch comes
+        // in the next one.
+        if self.dmg && self.enabled && self.timer <= TICK as i32 {
+            let next = ((self.position as usize + 1) % 32) / 2;
+            if next < 4 {
+                self.ram[0] = self.ram[next];
+            } else {
+                let block = next & !0x03;
+                self.ram.copy_within(block..block + 4, 0);
+            }
+        }
+
+        if self.dac_enabled {
+            self.enabled = true;
+        }
+        // The first sample is played after a short extra delay, and the
+        // sample buffer is not refreshed until then.
+        self.timer = self.period() + 6;
+        self.position = 0;
+    }
+
+    fn period(&self) -> i32 {
+        (2048 - self.frequency as i32) * 2
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.bool(self.enabled);
+        state.bool(self.dac_enabled);
+        state.u8(self.level);
+        state.u16(self.frequency);
+        state.i32(self.timer);
+        state.u8(self.position);
+        state.u8(self.sample);
+        state.bool(self.fetched);
+        self.length.save_state(state);
+        state.bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.enabled = state.bool()?;
+        self.dac_enabled = state.bool()?;
+        self.level = state.u8()?;
+        self.frequency = state.u16()?;
+        self.timer = state.i32()?;
+        self.position = state.u8()?;
+        self.sample = state.u8()?;
+        self.fetched = state.bool()?;
+        self.length.load_state(state)?;
+        state.bytes(&mut self.ram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Playing with a period of 16 T-cycles, so the first sample is fetched
+    // 22 T-cycles after the trigger and the rest every 4 ticks.
+    fn playing(dmg: bool) -> Wave {
+        let mut wave = Wave::new();
+        wave.set_dmg(dmg);
+        for (i, byte) in wave.ram.iter_mut().enumerate() {
+            *byte = 0xA0 | i as u8;
+        }
+        wave.write(0, 0x80, false);
+        wave.write(3, 0xF8, false);
+        wave.write(4, 0x87, false);
+        wave
+    }
+
+    #[test]
+    fn stopped_channel_ram_is_plain_memory() {
+        let mut wave = playing(true);
+        wave.write(0, 0x00, false);
+        wave.write_ram(5, 0x12);
+        assert_eq!(wave.read_ram(5), 0x12);
+    }
+
+    #[test]
+    fn dmg_reaches_ram_only_as_a_sample_is_fetched() {
+        let mut wave = playing(true);
+        for _ in 0..5 {
+            wave.tick(TICK);
+            assert_eq!(wave.read_ram(7), 0xFF);
+            wave.write_ram(7, 0x00);
+        }
+        // Fetches position 1, from the first byte, whatever the offset.
+        wave.tick(TICK);
+        assert_eq!(wave.read_ram(7), 0xA0);
+        wave.write_ram(7, 0x55);
+        assert_eq!(wave.ram[0], 0x55);
+        assert_eq!(wave.ram[7], 0xA7);
+        wave.tick(TICK);
+        assert_eq!(wave.read_ram(0), 0xFF);
+    }
+
+    #[test]
+    fn cgb_always_reaches_the_playing_byte() {
+        let mut wave = playing(false);
+        for _ in 0..10 {
+            wave.tick(TICK);
+            assert_eq!(wave.read_ram(7), wave.ram[wave.position as usize / 2]);
+        }
+    }
+
+    #[test]
+    fn retrigger_as_a_fetch_is_due_corrupts_ram() {
+        // The next sample is in the first four bytes: only byte 0 changes.
+        let mut wave = playing(true);
+        wave.position = 3;
+        wave.timer = 3;
+        wave.write(4, 0x87, false);
+        assert_eq!(wave.ram[..4], [0xA2, 0xA1, 0xA2, 0xA3]);
+
+        // Otherwise the block of four it's in is copied over them.
+        for timer in 1..=TICK as i32 {
+            let mut wave = playing(true);
+            wave.position = 9;
+            wave.timer = timer;
+            wave.write(4, 0x87, false);
+            assert_eq!(
+                wave.ram[..8],
+                [0xA4, 0xA5, 0xA6, 0xA7, 0xA4, 0xA5, 0xA6, 0xA7]
+            );
+        }
+    }
+
+    #[test]
+    fn retrigger_leaves_ram_alone_otherwise() {
+        let untouched: Vec<u8> = (0..16).map(|i| 0xA0 | i).collect();
+        // A fetch isn't due until a later tick.
+        let mut wave = playing(true);
+        wave.position = 9;
+        wave.timer = TICK as i32 + 1;
+        wave.write(4, 0x87, false);
+        assert_eq!(wave.ram[..], untouched[..]);
+        // CGB.
+        let mut wave = playing(false);
+        wave.position = 9;
+        wave.timer = 2;
+        wave.write(4, 0x87, false);
+        assert_eq!(wave.ram[..], untouched[..]);
+        // The channel wasn't playing.
+        let mut wave = playing(true);
+        wave.enabled = false;
+        wave.position = 9;
+        wave.timer = 2;
+        wave.write(4, 0x87, false);
+        assert_eq!(wave.ram[..], untouched[..]);
+    }
+}
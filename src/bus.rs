@@ -1,9 +1,65 @@
 use crate::apu;
+use crate::cart;
+use crate::cpu;
+use crate::joypad;
 use crate::ppu;
+use crate::scheduler::{Event, Scheduler};
+use crate::serial;
+use crate::sgb;
+use crate::state;
+use crate::timer;
+use std::fmt;
 
-pub struct Bus<'a> {
-    apu: &'a mut apu::Apu,
-    ppu: &'a mut ppu::Ppu,
+pub const INT_VBLANK: u8 = 1 << 0;
+pub const INT_STAT: u8 = 1 << 1;
+pub const INT_TIMER: u8 = 1 << 2;
+pub const INT_SERIAL: u8 = 1 << 3;
+pub const INT_JOYPAD: u8 = 1 << 4;
+
+// An access to an address nothing on the bus answers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BusError {
+    Read(u16),
+    Write(u16, u8),
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BusError::Read(address) => write!(f, "read from unmapped {:#06x}", address),
+            BusError::Write(address, value) => {
+                write!(f, "write of {:#04x} to unmapped {:#06x}", value, address)
+            }
+        }
+    }
+}
+
+pub struct Bus {
+    apu: apu::Apu,
+    ppu: ppu::Ppu,
+    cart: Option<cart::Cart>,
+    sgb: Option<sgb::Sgb>,
+    timer: timer::Timer,
+    serial: serial::Serial,
+
+    wram: [u8; 0x2000],
+    hram: [u8; 0x7F],
+    boot_rom: Vec<u8>,
+    boot_rom_enabled: bool,
+    joypad: joypad::Joypad,
+
+    interrupt_flag: u8,
+    interrupt_enable: u8,
+    frames: u64,
+    // The page an OAM DMA transfer copies from and how many bytes it has
+    // copied so far.
+    dma: Option<(u16, u8)>,
+
+    scheduler: Scheduler,
+    // M-cycle each lazily run component has been caught up to.
+    timer_synced: u64,
+    ppu_synced: u64,
+    apu_synced: u64,
 }
 
 pub const BOOT_ROM: [u8; 48] = [
@@ -12,30 +68,450 @@ pub const BOOT_ROM: [u8; 48] = [
     0x47, 0x11, 0x04, 0x01, 0x21, 0x10, 0x80, 0x1A, 0xCD, 0x95, 0x00, 0xCD, 0x96, 0x00, 0x13, 0x7B,
 ];
 
-impl<'a> Bus<'a> {
-    pub fn new(apu: &'a mut apu::Apu, ppu: &'a mut ppu::Ppu) -> Self {
-        Self { apu, ppu }
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus {
+    pub fn new() -> Self {
+        let mut bus = Self {
+            apu: apu::Apu::new(),
+            ppu: ppu::Ppu::new(),
+            cart: None,
+            sgb: None,
+            timer: timer::Timer::new(),
+            serial: serial::Serial::new(),
+            wram: [0; 0x2000],
+            hram: [0; 0x7F],
+            boot_rom: BOOT_ROM.to_vec(),
+            boot_rom_enabled: true,
+            joypad: joypad::Joypad::new(),
+            interrupt_flag: 0,
+            interrupt_enable: 0,
+            frames: 0,
+            dma: None,
+            scheduler: Scheduler::new(),
+            timer_synced: 0,
+            ppu_synced: 0,
+            apu_synced: 0,
+        };
+        bus.schedule_timer();
+        bus
+    }
+
+    pub fn load_cart(&mut self, cart: cart::Cart) {
+        self.cart = Some(cart);
+    }
+
+    pub fn cart(&self) -> Option<&cart::Cart> {
+        self.cart.as_ref()
+    }
+
+    // Replaces the built-in boot ROM fragment with a full dump. CGB boot ROMs
+    // are 2304 bytes and also cover 0x0200-0x08FF, around the cart header.
+    pub fn load_boot_rom(&mut self, rom: Vec<u8>) {
+        self.boot_rom = rom;
+    }
+
+    // Maps the cart over the boot ROM, as the boot ROM does itself by
+    // writing to 0xFF50 once it is done.
+    pub fn disable_boot_rom(&mut self) {
+        self.boot_rom_enabled = false;
+    }
+
+    // Like `read`, the timer, PPU and APU are as of the last sync; the
+    // `_mut` accessors catch up first.
+    pub fn apu(&self) -> &apu::Apu {
+        &self.apu
+    }
+
+    pub fn apu_mut(&mut self) -> &mut apu::Apu {
+        self.sync_apu();
+        &mut self.apu
+    }
+
+    pub fn ppu(&self) -> &ppu::Ppu {
+        &self.ppu
+    }
+
+    pub fn ppu_mut(&mut self) -> &mut ppu::Ppu {
+        self.sync_ppu();
+        &mut self.ppu
+    }
+
+    pub fn timer(&self) -> &timer::Timer {
+        &self.timer
+    }
+
+    pub fn joypad(&self) -> &joypad::Joypad {
+        &self.joypad
+    }
+
+    pub fn joypad_mut(&mut self) -> &mut joypad::Joypad {
+        &mut self.joypad
+    }
+
+    pub fn serial_mut(&mut self) -> &mut serial::Serial {
+        &mut self.serial
+    }
+
+    pub fn enable_sgb(&mut self) {
+        self.sgb = Some(sgb::Sgb::new());
+    }
+
+    pub fn sgb(&self) -> Option<&sgb::Sgb> {
+        self.sgb.as_ref()
+    }
+
+    // Runs one M-cycle. The timer, PPU and APU only run when one of their
+    // events comes due or their registers are accessed.
+    pub fn tick(&mut self) {
+        self.scheduler.advance();
+        while let Some((time, event)) = self.scheduler.pop_due() {
+            match event {
+                Event::Timer => {
+                    self.sync_timer();
+                    self.schedule_timer();
+                }
+                Event::Ppu => {
+                    self.sync_ppu();
+                    self.schedule_ppu(false);
+                }
+                Event::FrameSequencer => {
+                    // The edge clocks the frame sequencer before the APU
+                    // runs the cycle it happens in.
+                    self.apu.advance(time - 1 - self.apu_synced);
+                    self.apu_synced = time - 1;
+                    self.apu.clock_frame_sequencer();
+                    self.sync_timer();
+                    self.schedule_timer();
+                }
+            }
+        }
+        self.interrupt_flag |= self.joypad.tick();
+        self.interrupt_flag |= self.serial.tick();
+        self.step_dma();
+    }
+
+    // Copies one byte to OAM. Sources from 0xE000 up read the work RAM
+    // behind them, the same way echo RAM does.
+    fn step_dma(&mut self) {
+        let Some((page, index)) = self.dma else {
+            return;
+        };
+        let source = match page | index as u16 {
+            address @ 0xE000..=0xFFFF => address - 0x2000,
+            address => address,
+        };
+        let value = self.read(source).unwrap_or(0xFF);
+        // The PPU may be reading OAM for the line it's drawing.
+        self.sync_ppu();
+        self.ppu.write_oam(index, value);
+        self.dma = (index < 0x9F).then_some((page, index + 1));
+    }
+
+    // Runs up to the next M-cycle that might request an interrupt, and
+    // returns how many ran. The joypad, serial port and OAM DMA aren't
+    // scheduled, so there's nothing to skip while they might need polling.
+    pub fn idle(&mut self) -> u64 {
+        let cycles = if self.joypad.interrupt_pending()
+            || self.serial.transferring()
+            || self.dma.is_some()
+        {
+            1
+        } else {
+            self.scheduler.cycles_to_next().unwrap_or(1)
+        };
+        self.scheduler.skip(cycles - 1);
+        self.tick();
+        cycles
+    }
+
+    // Catches every component up, for looking at them from outside.
+    pub fn sync(&mut self) {
+        self.sync_timer();
+        self.sync_ppu();
+        self.sync_apu();
+    }
+
+    fn sync_timer(&mut self) {
+        let now = self.scheduler.now();
+        self.interrupt_flag |= self.timer.advance(now - self.timer_synced);
+        self.timer_synced = now;
+    }
+
+    fn sync_ppu(&mut self) {
+        let now = self.scheduler.now();
+        let interrupts = self.ppu.advance(now - self.ppu_synced);
+        self.ppu_synced = now;
+        if interrupts & INT_VBLANK != 0 {
+            self.frames += 1;
+            if let Some(sgb) = &mut self.sgb {
+                sgb.end_frame(&self.ppu);
+            }
+        }
+        self.interrupt_flag |= interrupts;
+    }
+
+    fn sync_apu(&mut self) {
+        let now = self.scheduler.now();
+        self.apu.advance(now - self.apu_synced);
+        self.apu_synced = now;
+    }
+
+    // Expects the timer to be caught up.
+    fn schedule_timer(&mut self) {
+        let now = self.scheduler.now();
+        match self.timer.cycles_to_interrupt() {
+            Some(cycles) => self.scheduler.schedule(Event::Timer, now + cycles),
+            None => self.scheduler.cancel(Event::Timer),
+        }
+        let edge = now + self.timer.cycles_to_apu_edge();
+        self.scheduler.schedule(Event::FrameSequencer, edge);
+    }
+
+    // Expects the PPU to be caught up. After a register write the STAT
+    // line has to be looked at again in the next cycle, whatever the mode.
+    fn schedule_ppu(&mut self, written: bool) {
+        let now = self.scheduler.now();
+        match self.ppu.cycles_to_event() {
+            Some(_) if written => self.scheduler.schedule(Event::Ppu, now + 1),
+            Some(cycles) => self.scheduler.schedule(Event::Ppu, now + cycles),
+            None => self.scheduler.cancel(Event::Ppu),
+        }
     }
 
-    pub fn read(&self, address: u16) -> u8 {
+    // Brings the component behind a register up to date before the CPU
+    // accesses it.
+    fn sync_register(&mut self, address: u16) {
         match address {
-            // TODO: allow disabling boot rom
-            0x0000..=0x00FF => self.read_boot_rom(address - 0x0000),
-            0x8000..=0x9FFF => self.ppu.read(address - 0x8000),
-            _ => panic!("bus: invalid read access"),
+            0xFF04..=0xFF07 => self.sync_timer(),
+            0xFF10..=0xFF3F => self.sync_apu(),
+            0xFF40..=0xFF4B => self.sync_ppu(),
+            _ => (),
         }
     }
 
-    pub fn write(&mut self, address: u16, value: u8) {
+    // Interrupts both requested and enabled.
+    pub fn pending_interrupts(&self) -> u8 {
+        self.interrupt_enable & self.interrupt_flag & 0x1F
+    }
+
+    // Clears the request once the CPU has dispatched it.
+    pub fn acknowledge_interrupt(&mut self, mask: u8) {
+        self.interrupt_flag &= !mask;
+    }
+
+    // Reads without catching anything up first, so the timer, PPU and APU
+    // registers show their state as of the last sync.
+    pub fn read(&self, address: u16) -> Result<u8, BusError> {
+        let value = match address {
+            0x0000..=0x00FF if self.boot_rom_enabled => self.read_boot_rom(address)?,
+            0x0200..=0x08FF if self.boot_rom_enabled && self.boot_rom.len() > 0x100 => {
+                self.read_boot_rom(address)?
+            }
+            0x0000..=0x7FFF => self.read_cart(address),
+            0x8000..=0x9FFF => self.ppu.read(address)?,
+            0xA000..=0xBFFF => self.read_cart(address),
+            0xC000..=0xFDFF => self.wram[address as usize & 0x1FFF],
+            // OAM is busy while a DMA transfer writes to it.
+            0xFE00..=0xFE9F if self.dma.is_some() => 0xFF,
+            0xFE00..=0xFE9F => self.ppu.read(address)?,
+            0xFF00 => self.read_joypad(),
+            0xFF01..=0xFF02 => self.serial.read(address)?,
+            0xFF04..=0xFF07 => self.timer.read(address)?,
+            0xFF0F => 0xE0 | self.interrupt_flag,
+            0xFF10..=0xFF3F => self.apu.read(address)?,
+            0xFF40..=0xFF4B => self.ppu.read(address)?,
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80],
+            0xFFFF => self.interrupt_enable,
+            // Nothing drives the bus for the unused OAM area, the gaps
+            // between I/O registers or the CGB-only ones, so they read high.
+            0xFEA0..=0xFEFF | 0xFF03 | 0xFF08..=0xFF0E | 0xFF4C..=0xFF7F => 0xFF,
+        };
+        Ok(value)
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        self.sync_register(address);
         match address {
-            0x8000..=0x9FFF => self.ppu.write(address, value),
-            0xFF10..=0xFF3F => self.apu.write(address, value),
-            0xFF40..=0xFF4B => self.ppu.write(address, value),
-            _ => panic!("bus: invalid write access"),
+            0x0000..=0x7FFF => self.write_cart(address, value),
+            0x8000..=0x9FFF => self.ppu.write(address, value)?,
+            0xA000..=0xBFFF => self.write_cart(address, value),
+            0xC000..=0xFDFF => self.wram[address as usize & 0x1FFF] = value,
+            0xFE00..=0xFE9F if self.dma.is_some() => (),
+            0xFE00..=0xFE9F => self.ppu.write(address, value)?,
+            0xFF00 => self.write_joypad(value),
+            0xFF01..=0xFF02 => self.serial.write(address, value)?,
+            0xFF04..=0xFF07 => {
+                if self.timer.write(address, value)? {
+                    self.sync_apu();
+                    self.apu.clock_frame_sequencer();
+                }
+                self.schedule_timer();
+            }
+            0xFF0F => self.interrupt_flag = value & 0x1F,
+            0xFF10..=0xFF3F => self.apu.write(address, value)?,
+            0xFF40..=0xFF4B => {
+                self.ppu.write(address, value)?;
+                self.schedule_ppu(true);
+                if address == 0xFF46 {
+                    self.dma = Some(((value as u16) << 8, 0));
+                }
+            }
+            0xFF50 => {
+                if value != 0 {
+                    self.disable_boot_rom();
+                }
+            }
+            0xFF80..=0xFFFE => self.hram[address as usize - 0xFF80] = value,
+            0xFFFF => self.interrupt_enable = value,
+            // Writes to the unused areas are dropped.
+            0xFEA0..=0xFEFF | 0xFF03 | 0xFF08..=0xFF0E | 0xFF4C..=0xFF4F | 0xFF51..=0xFF7F => (),
+        }
+        Ok(())
+    }
+
+    // Only the start of the boot ROM is included; the rest reads as
+    // unmapped.
+    fn read_boot_rom(&self, address: u16) -> Result<u8, BusError> {
+        self.boot_rom
+            .get(address as usize)
+            .copied()
+            .ok_or(BusError::Read(address))
+    }
+
+    fn read_joypad(&self) -> u8 {
+        match &self.sgb {
+            // With neither group selected the SGB reports the controller ID
+            // instead of the buttons.
+            Some(sgb) if self.joypad.select() == 0x30 => 0xF0 | sgb.joypad_id(),
+            _ => self.joypad.read(),
+        }
+    }
+
+    fn write_joypad(&mut self, value: u8) {
+        self.joypad.write(value);
+        if let Some(sgb) = &mut self.sgb {
+            sgb.write_joypad(value);
+            self.joypad.set_port(sgb.player());
+        }
+    }
+
+    fn read_cart(&self, address: u16) -> u8 {
+        match &self.cart {
+            Some(cart) => cart.read(address),
+            None => 0xFF,
+        }
+    }
+
+    fn write_cart(&mut self, address: u16, value: u8) {
+        if let Some(cart) = &mut self.cart {
+            cart.write(address, value);
+        }
+    }
+
+    // Frames the PPU has finished since power on.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // The cart and SGB have to already be in place: a state only holds what
+    // they change while running. Everything is caught up first, so no
+    // scheduler state needs saving.
+    pub fn save_state(&mut self, state: &mut state::Writer) {
+        self.sync();
+        state.bool(self.boot_rom_enabled);
+        state.bytes(&self.wram);
+        state.bytes(&self.hram);
+        state.u8(self.interrupt_flag);
+        state.u8(self.interrupt_enable);
+        state.u64(self.frames);
+        state.bool(self.dma.is_some());
+        let (page, index) = self.dma.unwrap_or_default();
+        state.u16(page);
+        state.u8(index);
+        state.bool(self.cart.is_some());
+        if let Some(cart) = &self.cart {
+            cart.save_state(state);
+        }
+        state.bool(self.sgb.is_some());
+        if let Some(sgb) = &self.sgb {
+            sgb.save_state(state);
+        }
+        self.timer.save_state(state);
+        self.serial.save_state(state);
+        self.joypad.save_state(state);
+        self.ppu.save_state(state);
+        self.apu.save_state(state);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.boot_rom_enabled = state.bool()?;
+        state.bytes(&mut self.wram)?;
+        state.bytes(&mut self.hram)?;
+        self.interrupt_flag = state.u8()?;
+        self.interrupt_enable = state.u8()?;
+        self.frames = state.u64()?;
+        let dma = state.bool()?;
+        let (page, index) = (state.u16()?, state.u8()?);
+        if page & 0xFF != 0 || index >= 0xA0 {
+            return Err(state::StateError::Invalid("OAM DMA"));
+        }
+        self.dma = dma.then_some((page, index));
+        if state.bool()? != self.cart.is_some() {
+            return Err(state::StateError::Invalid("cartridge"));
+        }
+        if let Some(cart) = &mut self.cart {
+            cart.load_state(state)?;
+        }
+        if state.bool()? != self.sgb.is_some() {
+            return Err(state::StateError::Invalid("SGB"));
         }
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load_state(state)?;
+        }
+        self.timer.load_state(state)?;
+        self.serial.load_state(state)?;
+        self.joypad.load_state(state)?;
+        self.ppu.load_state(state)?;
+        self.apu.load_state(state)?;
+
+        let now = self.scheduler.now();
+        self.timer_synced = now;
+        self.ppu_synced = now;
+        self.apu_synced = now;
+        self.schedule_timer();
+        self.schedule_ppu(true);
+        Ok(())
+    }
+}
+
+impl cpu::MemoryBus for Bus {
+    fn read(&mut self, address: u16) -> Result<u8, BusError> {
+        self.sync_register(address);
+        Bus::read(self, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        Bus::write(self, address, value)
+    }
+
+    fn tick(&mut self) {
+        Bus::tick(self)
+    }
+
+    fn idle(&mut self) -> u64 {
+        Bus::idle(self)
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        Bus::pending_interrupts(self)
     }
 
-    fn read_boot_rom(&self, address: u16) -> u8 {
-        BOOT_ROM[address as usize]
+    fn acknowledge_interrupt(&mut self, mask: u8) {
+        Bus::acknowledge_interrupt(self, mask)
     }
 }
@@ -1 +1,254 @@
-pub struct Cart {}
+use crate::state;
+
+const TITLE_START: usize = 0x0134;
+const CGB_FLAG: usize = 0x0143;
+const NEW_LICENSEE: usize = 0x0144;
+const OLD_LICENSEE: usize = 0x014B;
+const GLOBAL_CHECKSUM: u16 = 0x014E;
+
+const CART_TYPE: usize = 0x0147;
+const RAM_SIZE_CODE: usize = 0x0149;
+
+const ROM_BANK_SIZE: usize = 0x4000;
+const RAM_BANK_SIZE: usize = 0x2000;
+
+// How the cart maps its ROM and RAM into the address space. Carts with any
+// other controller, such as the MBC2 or HuC1, only see their first 32 KiB
+// of ROM and have no RAM.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Mapper {
+    None,
+    // A 5 bit ROM bank register, and a 2 bit one that either extends it or
+    // selects the RAM bank, depending on the banking mode.
+    Mbc1,
+    // 7 bit ROM banks, and RAM banks or clock registers. The clock holds
+    // whatever is written to it but doesn't count.
+    Mbc3,
+    // 9 bit ROM banks, where bank 0 can be mapped at 0x4000 too.
+    Mbc5,
+    // The synthetic cart GBS rips are played from: 0x4000 byte ROM banks
+    // selected with all 8 bits written to 0x2000-0x3FFF, and 8 KiB of RAM.
+    Gbs,
+}
+
+pub struct Cart {
+    rom: Vec<u8>,
+    ram: Vec<u8>,
+    mapper: Mapper,
+    ram_enabled: bool,
+    // As last written, before any masking to the ROM's size.
+    rom_bank: usize,
+    // The MBC1's upper bank bits, or the RAM bank or clock register.
+    ram_bank: usize,
+    // The MBC1's banking mode.
+    advanced_banking: bool,
+    rtc: [u8; 5],
+    rtc_latched: [u8; 5],
+    rtc_latch: u8,
+}
+
+impl Cart {
+    // Picks the controller and RAM size from the header.
+    pub fn new(rom: Vec<u8>) -> Self {
+        let header = |offset: usize| rom.get(offset).copied().unwrap_or(0);
+        let (mapper, has_ram) = match header(CART_TYPE) {
+            0x01 => (Mapper::Mbc1, false),
+            0x02 | 0x03 => (Mapper::Mbc1, true),
+            0x08 | 0x09 => (Mapper::None, true),
+            0x0F | 0x11 => (Mapper::Mbc3, false),
+            0x10 | 0x12 | 0x13 => (Mapper::Mbc3, true),
+            0x19 | 0x1C => (Mapper::Mbc5, false),
+            0x1A | 0x1B | 0x1D | 0x1E => (Mapper::Mbc5, true),
+            _ => (Mapper::None, false),
+        };
+        let ram_len = match header(RAM_SIZE_CODE) {
+            _ if !has_ram => 0,
+            0x01 => 0x800,
+            0x02 => RAM_BANK_SIZE,
+            0x03 => 4 * RAM_BANK_SIZE,
+            0x04 => 16 * RAM_BANK_SIZE,
+            0x05 => 8 * RAM_BANK_SIZE,
+            _ => 0,
+        };
+        Self::with_mapper(rom, mapper, ram_len)
+    }
+
+    pub fn gbs(rom: Vec<u8>) -> Self {
+        Self::with_mapper(rom, Mapper::Gbs, RAM_BANK_SIZE)
+    }
+
+    fn with_mapper(rom: Vec<u8>, mapper: Mapper, ram_len: usize) -> Self {
+        Self {
+            rom,
+            ram: vec![0; ram_len],
+            mapper,
+            // Plain carts and the GBS cart have nothing to enable RAM with.
+            ram_enabled: matches!(mapper, Mapper::None | Mapper::Gbs),
+            rom_bank: 1,
+            ram_bank: 0,
+            advanced_banking: false,
+            rtc: [0; 5],
+            rtc_latched: [0; 5],
+            rtc_latch: 0xFF,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> u8 {
+        match address {
+            0x0000..=0x3FFF => self.read_rom(self.low_bank(), address),
+            0x4000..=0x7FFF => self.read_rom(self.high_bank(), address - 0x4000),
+            0xA000..=0xBFFF if !self.ram_enabled => 0xFF,
+            0xA000..=0xBFFF => match self.mapper {
+                Mapper::Mbc3 if self.ram_bank >= 0x08 => self
+                    .rtc_latched
+                    .get(self.ram_bank - 0x08)
+                    .copied()
+                    .unwrap_or(0xFF),
+                _ => match self.ram_offset(address) {
+                    Some(offset) => self.ram[offset],
+                    None => 0xFF,
+                },
+            },
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) {
+        match (self.mapper, address) {
+            (Mapper::Gbs, 0x2000..=0x3FFF) => self.rom_bank = (value as usize).max(1),
+            (Mapper::Mbc1 | Mapper::Mbc3 | Mapper::Mbc5, 0x0000..=0x1FFF) => {
+                self.ram_enabled = value & 0x0F == 0x0A;
+            }
+            (Mapper::Mbc1, 0x2000..=0x3FFF) => self.rom_bank = (value as usize & 0x1F).max(1),
+            (Mapper::Mbc1, 0x4000..=0x5FFF) => self.ram_bank = value as usize & 0x03,
+            (Mapper::Mbc1, 0x6000..=0x7FFF) => self.advanced_banking = value & 0x01 != 0,
+            (Mapper::Mbc3, 0x2000..=0x3FFF) => self.rom_bank = (value as usize & 0x7F).max(1),
+            (Mapper::Mbc3, 0x4000..=0x5FFF) => self.ram_bank = value as usize & 0x0F,
+            (Mapper::Mbc3, 0x6000..=0x7FFF) => {
+                if self.rtc_latch == 0x00 && value == 0x01 {
+                    self.rtc_latched = self.rtc;
+                }
+                self.rtc_latch = value;
+            }
+            (Mapper::Mbc5, 0x2000..=0x2FFF) => {
+                self.rom_bank = self.rom_bank & 0x100 | value as usize
+            }
+            (Mapper::Mbc5, 0x3000..=0x3FFF) => {
+                self.rom_bank = self.rom_bank & 0xFF | (value as usize & 0x01) << 8;
+            }
+            (Mapper::Mbc5, 0x4000..=0x5FFF) => self.ram_bank = value as usize & 0x0F,
+            (_, 0xA000..=0xBFFF) if self.ram_enabled => match self.mapper {
+                Mapper::Mbc3 if self.ram_bank >= 0x08 => {
+                    if let Some(register) = self.rtc.get_mut(self.ram_bank - 0x08) {
+                        *register = value;
+                    }
+                }
+                _ => {
+                    if let Some(offset) = self.ram_offset(address) {
+                        self.ram[offset] = value;
+                    }
+                }
+            },
+            _ => (),
+        }
+    }
+
+    // The bank at 0x0000, which only the MBC1 can change, in its advanced
+    // banking mode.
+    fn low_bank(&self) -> usize {
+        match self.mapper {
+            Mapper::Mbc1 if self.advanced_banking => self.ram_bank << 5,
+            _ => 0,
+        }
+    }
+
+    fn high_bank(&self) -> usize {
+        match self.mapper {
+            Mapper::None => 1,
+            Mapper::Mbc1 => self.ram_bank << 5 | self.rom_bank,
+            Mapper::Mbc3 | Mapper::Mbc5 | Mapper::Gbs => self.rom_bank,
+        }
+    }
+
+    // Bank numbers past the end of the ROM wrap around, as only as many
+    // address lines as the ROM needs are wired up.
+    fn read_rom(&self, bank: usize, offset: u16) -> u8 {
+        let banks = (self.rom.len() / ROM_BANK_SIZE).next_power_of_two().max(1);
+        let offset = bank % banks * ROM_BANK_SIZE + offset as usize;
+        self.rom.get(offset).copied().unwrap_or(0xFF)
+    }
+
+    fn ram_offset(&self, address: u16) -> Option<usize> {
+        if self.ram.is_empty() {
+            return None;
+        }
+        let bank = match self.mapper {
+            Mapper::Mbc1 if !self.advanced_banking => 0,
+            Mapper::None | Mapper::Gbs => 0,
+            _ => self.ram_bank,
+        };
+        let offset = bank * RAM_BANK_SIZE + (address as usize - 0xA000);
+        Some(offset % self.ram.len())
+    }
+
+    // The full 16 byte title field, including the CGB flag byte the CGB boot
+    // ROM also folds into its checksum.
+    pub fn title(&self) -> [u8; 16] {
+        let mut title = [0; 16];
+        for (i, b) in title.iter_mut().enumerate() {
+            *b = self.read((TITLE_START + i) as u16);
+        }
+        title
+    }
+
+    pub fn title_checksum(&self) -> u8 {
+        self.title().iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+    }
+
+    pub fn supports_cgb(&self) -> bool {
+        self.read(CGB_FLAG as u16) & 0x80 != 0
+    }
+
+    pub fn is_nintendo_licensee(&self) -> bool {
+        match self.read(OLD_LICENSEE as u16) {
+            0x01 => true,
+            0x33 => {
+                self.read(NEW_LICENSEE as u16) == b'0' && self.read(NEW_LICENSEE as u16 + 1) == b'1'
+            }
+            _ => false,
+        }
+    }
+
+    // Only the RAM and banking are saved. The ROM's size and checksum are
+    // kept to catch a state being loaded with a different game.
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.usize(self.rom.len());
+        state.u16(self.global_checksum());
+        state.bool(self.ram_enabled);
+        state.usize(self.rom_bank);
+        state.usize(self.ram_bank);
+        state.bool(self.advanced_banking);
+        state.bytes(&self.rtc);
+        state.bytes(&self.rtc_latched);
+        state.u8(self.rtc_latch);
+        state.bytes(&self.ram);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        if state.usize()? != self.rom.len() || state.u16()? != self.global_checksum() {
+            return Err(state::StateError::Invalid("cartridge"));
+        }
+        self.ram_enabled = state.bool()?;
+        self.rom_bank = state.usize()?;
+        self.ram_bank = state.usize()?;
+        self.advanced_banking = state.bool()?;
+        state.bytes(&mut self.rtc)?;
+        state.bytes(&mut self.rtc_latched)?;
+        self.rtc_latch = state.u8()?;
+        state.bytes(&mut self.ram)
+    }
+
+    fn global_checksum(&self) -> u16 {
+        u16::from_be_bytes([self.read(GLOBAL_CHECKSUM), self.read(GLOBAL_CHECKSUM + 1)])
+    }
+}
@@ -0,0 +1,282 @@
+// Colorization the CGB boot ROM applies when it starts a DMG-only cartridge.
+// The tables below mirror the ones in the boot ROM: a title checksum picks an
+// entry in PALETTE_PER_CHECKSUM, which picks a combination of three palettes
+// out of PALETTES. Colors are stored as RGB555, exactly as they end up in CGB
+// palette RAM.
+use crate::cart;
+use crate::model;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompatPalette {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+pub const GRAYSCALE: CompatPalette = CompatPalette {
+    bg: [0x7FFF, 0x5294, 0x294A, 0x0000],
+    obj0: [0x7FFF, 0x5294, 0x294A, 0x0000],
+    obj1: [0x7FFF, 0x5294, 0x294A, 0x0000],
+};
+
+// Direction + button held while the logo scrolls in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyCombo {
+    Right,
+    Left,
+    Up,
+    Down,
+    RightA,
+    LeftA,
+    UpA,
+    DownA,
+    RightB,
+    LeftB,
+    UpB,
+    DownB,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Selection {
+    // Whatever the boot ROM would pick from the cartridge title.
+    Auto,
+    // Whatever the boot ROM would pick with these keys held.
+    Keys(KeyCombo),
+    // One of the boot ROM's palette combinations, by index.
+    Combination(usize),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownSelection;
+
+impl fmt::Display for UnknownSelection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "expected auto, a direction optionally with +a or +b, or 0-{}",
+            combination_count() - 1
+        )
+    }
+}
+
+// "auto", the keys as in "left+b", or a combination's index.
+impl FromStr for Selection {
+    type Err = UnknownSelection;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        let keys = match name.to_ascii_lowercase().as_str() {
+            "auto" => return Ok(Selection::Auto),
+            "right" => KeyCombo::Right,
+            "left" => KeyCombo::Left,
+            "up" => KeyCombo::Up,
+            "down" => KeyCombo::Down,
+            "right+a" => KeyCombo::RightA,
+            "left+a" => KeyCombo::LeftA,
+            "up+a" => KeyCombo::UpA,
+            "down+a" => KeyCombo::DownA,
+            "right+b" => KeyCombo::RightB,
+            "left+b" => KeyCombo::LeftB,
+            "up+b" => KeyCombo::UpB,
+            "down+b" => KeyCombo::DownB,
+            _ => {
+                return match name.parse() {
+                    Ok(index) if index < combination_count() => Ok(Selection::Combination(index)),
+                    _ => Err(UnknownSelection),
+                }
+            }
+        };
+        Ok(Selection::Keys(keys))
+    }
+}
+
+// Palette the LCD ends up with after the boot ROM has run. Only CGB hardware
+// colorizes, and only cartridges that don't support CGB mode themselves.
+pub fn palette(model: model::Model, cart: &cart::Cart, selection: Selection) -> CompatPalette {
+    if model.is_cgb() && !cart.supports_cgb() {
+        select(cart, selection)
+    } else {
+        GRAYSCALE
+    }
+}
+
+pub fn select(cart: &cart::Cart, selection: Selection) -> CompatPalette {
+    match selection {
+        Selection::Auto => for_cart(cart),
+        Selection::Keys(keys) => for_keys(keys),
+        Selection::Combination(index) => combination(index),
+    }
+}
+
+pub fn for_cart(cart: &cart::Cart) -> CompatPalette {
+    if !cart.is_nintendo_licensee() {
+        return combination(0);
+    }
+
+    let checksum = cart.title_checksum();
+    let fourth_letter = cart.title()[3];
+    let index = TITLE_CHECKSUMS
+        .iter()
+        .enumerate()
+        .position(|(i, &sum)| {
+            sum == checksum
+                && (i < FIRST_DUPLICATE_CHECKSUM
+                    || FOURTH_LETTERS[i - FIRST_DUPLICATE_CHECKSUM] == fourth_letter)
+        })
+        .unwrap_or(0);
+
+    combination(PALETTE_PER_CHECKSUM[index] as usize)
+}
+
+pub fn for_keys(keys: KeyCombo) -> CompatPalette {
+    let index = match keys {
+        KeyCombo::Right => 1,
+        KeyCombo::Left => 48,
+        KeyCombo::Up => 5,
+        KeyCombo::Down => 8,
+        KeyCombo::RightA => 0,
+        KeyCombo::LeftA => 40,
+        KeyCombo::UpA => 43,
+        KeyCombo::DownA => 3,
+        KeyCombo::RightB => 6,
+        KeyCombo::LeftB => 7,
+        KeyCombo::UpB => 28,
+        KeyCombo::DownB => 49,
+    };
+    combination(index)
+}
+
+pub fn combination_count() -> usize {
+    COMBINATIONS.len()
+}
+
+pub fn combination(index: usize) -> CompatPalette {
+    let (obj0, obj1, bg) = COMBINATIONS[index % COMBINATIONS.len()];
+    CompatPalette {
+        bg: colors_at(bg),
+        obj0: colors_at(obj0),
+        obj1: colors_at(obj1),
+    }
+}
+
+// A few combinations start part way into a palette and run on into the next
+// one, so offsets are counted in colors rather than palettes.
+fn colors_at(offset: usize) -> [u16; 4] {
+    let mut colors = [0; 4];
+    for (i, color) in colors.iter_mut().enumerate() {
+        let n = offset + i;
+        *color = PALETTES[n / 4][n % 4];
+    }
+    colors
+}
+
+const FIRST_DUPLICATE_CHECKSUM: usize = 0x41;
+
+const TITLE_CHECKSUMS: [u8; 94] = [
+    0x00, 0x88, 0x16, 0x36, 0xD1, 0xDB, 0xF2, 0x3C, 0x8C, 0x92, 0x3D, 0x5C, 0x58, 0xC9, 0x3E, 0x70,
+    0x1D, 0x59, 0x69, 0x19, 0x35, 0xA8, 0x14, 0xAA, 0x75, 0x95, 0x99, 0x34, 0x6F, 0x15, 0xFF, 0x97,
+    0x4B, 0x90, 0x17, 0x10, 0x39, 0xF7, 0xF6, 0xA2, 0x49, 0x4E, 0x43, 0x68, 0xE0, 0x8B, 0xF0, 0xCE,
+    0x0C, 0x29, 0xE8, 0xB7, 0x86, 0x9A, 0x52, 0x01, 0x9D, 0x71, 0x9C, 0xBD, 0x5D, 0x6D, 0x67, 0x3F,
+    0x6B, // checksums from here on are shared by several titles
+    0xB3, 0x46, 0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3, 0x46,
+    0x28, 0xA5, 0xC6, 0xD3, 0x27, 0x61, 0x18, 0x66, 0x6A, 0xBF, 0x0D, 0xF4, 0xB3,
+];
+
+// Fourth title letter that must also match for the shared checksums.
+const FOURTH_LETTERS: [u8; 29] = *b"BEFAARBEKEK R-URAR INAILICE R";
+
+const PALETTE_PER_CHECKSUM: [u8; 94] = [
+    0, 4, 5, 35, 34, 3, 31, 15, 10, 5, 19, 36, 7, 37, 30, 44, 21, 32, 31, 20, 5, 33, 13, 14, 5, 29,
+    5, 18, 9, 3, 2, 26, 25, 25, 41, 42, 26, 45, 42, 45, 36, 38, 26, 42, 30, 41, 34, 34, 5, 42, 6,
+    5, 33, 25, 42, 42, 40, 2, 16, 25, 42, 42, 5, 0, 39, 36, 22, 25, 6, 32, 12, 36, 11, 39, 18, 39,
+    24, 31, 50, 17, 46, 6, 27, 0, 47, 41, 41, 0, 0, 19, 34, 23, 18, 29,
+];
+
+// (obj0, obj1, bg) offsets into PALETTES, in colors.
+const COMBINATIONS: [(usize, usize, usize); 51] = [
+    (4 * 4, 4 * 4, 29 * 4),
+    (18 * 4, 18 * 4, 18 * 4),
+    (20 * 4, 20 * 4, 20 * 4),
+    (24 * 4, 24 * 4, 24 * 4),
+    (9 * 4, 9 * 4, 9 * 4),
+    (0, 0, 0),
+    (27 * 4, 27 * 4, 27 * 4),
+    (5 * 4, 5 * 4, 5 * 4),
+    (12 * 4, 12 * 4, 12 * 4),
+    (26 * 4, 26 * 4, 26 * 4),
+    (16 * 4, 8 * 4, 8 * 4),
+    (4 * 4, 28 * 4, 28 * 4),
+    (4 * 4, 2 * 4, 2 * 4),
+    (3 * 4, 4 * 4, 4 * 4),
+    (4 * 4, 29 * 4, 29 * 4),
+    (28 * 4, 4 * 4, 28 * 4),
+    (2 * 4, 17 * 4, 2 * 4),
+    (16 * 4, 16 * 4, 8 * 4),
+    (4 * 4, 4 * 4, 7 * 4),
+    (4 * 4, 4 * 4, 18 * 4),
+    (4 * 4, 4 * 4, 20 * 4),
+    (19 * 4, 19 * 4, 9 * 4),
+    (4 * 4 - 1, 4 * 4 - 1, 11 * 4),
+    (17 * 4, 17 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 2 * 4),
+    (4 * 4, 4 * 4, 3 * 4),
+    (28 * 4, 28 * 4, 0),
+    (3 * 4, 3 * 4, 0),
+    (0, 0, 4),
+    (18 * 4, 22 * 4, 18 * 4),
+    (20 * 4, 22 * 4, 20 * 4),
+    (24 * 4, 22 * 4, 24 * 4),
+    (16 * 4, 22 * 4, 8 * 4),
+    (17 * 4, 4 * 4, 13 * 4),
+    (28 * 4 - 1, 0, 14 * 4),
+    (28 * 4 - 1, 4 * 4, 15 * 4),
+    (19 * 4, 22 * 4, 9 * 4),
+    (16 * 4, 28 * 4, 10 * 4),
+    (4 * 4, 23 * 4, 28 * 4),
+    (17 * 4, 22 * 4, 2 * 4),
+    (4 * 4, 0, 2 * 4),
+    (4 * 4, 28 * 4, 3 * 4),
+    (28 * 4, 3 * 4, 0),
+    (3 * 4, 28 * 4, 4 * 4),
+    (21 * 4, 28 * 4, 4 * 4),
+    (3 * 4, 28 * 4, 0),
+    (25 * 4, 3 * 4, 28 * 4),
+    (0, 28 * 4, 8 * 4),
+    (4 * 4, 3 * 4, 28 * 4),
+    (28 * 4, 3 * 4, 6 * 4),
+    (4 * 4, 28 * 4, 29 * 4),
+];
+
+const PALETTES: [[u16; 4]; 30] = [
+    [0x7FFF, 0x32BF, 0x00D0, 0x0000],
+    [0x639F, 0x4279, 0x15B0, 0x04CB],
+    [0x7FFF, 0x6E31, 0x454A, 0x0000],
+    [0x7FFF, 0x1BEF, 0x0200, 0x0000],
+    [0x7FFF, 0x421F, 0x1CF2, 0x0000],
+    [0x7FFF, 0x5294, 0x294A, 0x0000],
+    [0x7FFF, 0x03FF, 0x012F, 0x0000],
+    [0x7FFF, 0x03EF, 0x01D6, 0x0000],
+    [0x7FFF, 0x42B5, 0x3DC8, 0x0000],
+    [0x7E74, 0x03FF, 0x0180, 0x0000],
+    [0x67FF, 0x77AC, 0x1A13, 0x2D6B],
+    [0x7ED6, 0x4BFF, 0x2175, 0x0000],
+    [0x53FF, 0x4A5F, 0x7E52, 0x0000],
+    [0x4FFF, 0x7ED2, 0x3A4C, 0x1CE0],
+    [0x03ED, 0x7FFF, 0x255F, 0x0000],
+    [0x036A, 0x021F, 0x03FF, 0x7FFF],
+    [0x7FFF, 0x01DF, 0x0112, 0x0000],
+    [0x231F, 0x035F, 0x00F2, 0x0009],
+    [0x7FFF, 0x03EA, 0x011F, 0x0000],
+    [0x299F, 0x001A, 0x000C, 0x0000],
+    [0x7FFF, 0x027F, 0x001F, 0x0000],
+    [0x7FFF, 0x03E0, 0x0206, 0x0120],
+    [0x7FFF, 0x7EEB, 0x001F, 0x7C00],
+    [0x7FFF, 0x3FFF, 0x7E00, 0x001F],
+    [0x7FFF, 0x03FF, 0x001F, 0x0000],
+    [0x03FF, 0x001F, 0x000C, 0x0000],
+    [0x7FFF, 0x033F, 0x0193, 0x0000],
+    [0x0000, 0x4200, 0x037F, 0x7FFF],
+    [0x7FFF, 0x7E8C, 0x7C00, 0x0000],
+    [0x7FFF, 0x1BEF, 0x6180, 0x0000],
+];
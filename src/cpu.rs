@@ -1,399 +1,752 @@
 use crate::bus;
+use crate::state;
+use microcode::{Access, Action, Addr, Dst, MicroOp, Pair, Src};
+use std::collections::VecDeque;
+use std::fmt;
 
-#[derive(Debug)]
-enum Reg8 {
-    None,
-    A,
-    B,
-    C,
-    D,
-    E,
-    H,
-    L,
-    Imm8,
-    SPLow,
-    SPHigh,
-}
-
-#[derive(Debug)]
-enum Reg16 {
-    None,
-    BC,
-    DE,
-    HL,
-    SP,
-}
-
-#[derive(Debug)]
-pub enum Instruction {
-    BitTest7H,
-    Call,
-    CBPrefix,
-    JmpRelIfNonZero,
-    IncC,
-    IncDE,
-    LoadAPtrDE,
-    LoadAE,
-    LoadAImm8,
-    LoadBImm8,
-    LoadCImm8,
-    LoadDImm8,
-    LoadEImm8,
-    LoadHImm8,
-    LoadLImm8,
-    LoadBCImm16,
-    LoadDEImm16,
-    LoadHLImm16,
-    LoadSPImm16,
-    StoreAToAddrImm8High,
-    StoreAToAddrCHigh,
-    StoreAToAddrHL,
-    StoreAToAddrHLDec,
-    XorA8,
-}
-
-impl Instruction {
-    pub fn decode(opcode: u8) -> Self {
-        match opcode {
-            0x7C => Instruction::BitTest7H,
-            0xCD => Instruction::Call,
-            0xCB => Instruction::CBPrefix,
-            0x20 => Instruction::JmpRelIfNonZero,
-            0x0C => Instruction::IncC,
-            0x13 => Instruction::IncDE,
-            0x1A => Instruction::LoadAPtrDE,
-            0x7B => Instruction::LoadAE,
-            0x3E => Instruction::LoadAImm8,
-            0x06 => Instruction::LoadBImm8,
-            0x0E => Instruction::LoadCImm8,
-            0x16 => Instruction::LoadDImm8,
-            0x1E => Instruction::LoadEImm8,
-            0x26 => Instruction::LoadHImm8,
-            0x2E => Instruction::LoadLImm8,
-            0x01 => Instruction::LoadBCImm16,
-            0x11 => Instruction::LoadDEImm16,
-            0x21 => Instruction::LoadHLImm16,
-            0x31 => Instruction::LoadSPImm16,
-            0xE0 => Instruction::StoreAToAddrImm8High,
-            0xE2 => Instruction::StoreAToAddrCHigh,
-            0x77 => Instruction::StoreAToAddrHL,
-            0x32 => Instruction::StoreAToAddrHLDec,
-            0xAF => Instruction::XorA8,
-            _ => panic!("deocde: invalid opcode value {:#x}", opcode),
-        }
-    }
-}
+mod microcode;
+pub mod opcodes;
 
 const FLAG_ZERO: u8 = 1 << 7;
 const FLAG_SUB: u8 = 1 << 6;
 const FLAG_H_CARRY: u8 = 1 << 5;
 const FLAG_CARRY: u8 = 1 << 4;
 
-enum BusOp {
-    Read,
-    Write,
+const NOP: u8 = 0x00;
+
+// Interrupt handlers, in priority order.
+const INTERRUPT_VECTORS: [(u8, u16); 5] = [
+    (bus::INT_VBLANK, 0x40),
+    (bus::INT_STAT, 0x48),
+    (bus::INT_TIMER, 0x50),
+    (bus::INT_SERIAL, 0x58),
+    (bus::INT_JOYPAD, 0x60),
+];
+
+// A bus access that failed during an instruction. The access itself reads
+// 0xFF or is dropped and the M-cycle completes, so execution can go on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EmuError {
+    // Address and opcode of the instruction making the access.
+    pub pc: u16,
+    pub opcode: u8,
+    pub access: bus::BusError,
 }
 
-struct BusRequest {
-    address: u16,
-    op: BusOp,
-    read_dest: Reg8,
-    write_value: u8,
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} by opcode {:#04x} at {:#06x}",
+            self.access, self.opcode, self.pc
+        )
+    }
 }
 
-impl BusRequest {
-    fn read(address: u16, read_dest: Reg8) -> Self {
-        Self {
-            op: BusOp::Read,
-            address,
-            read_dest,
-            write_value: 0,
-        }
+// What the CPU sees of the rest of the system. Each M-cycle makes at most
+// one access and then calls `tick`.
+pub trait MemoryBus {
+    fn read(&mut self, address: u16) -> Result<u8, bus::BusError>;
+    fn write(&mut self, address: u16, value: u8) -> Result<(), bus::BusError>;
+    // Runs everything else on the bus for one M-cycle.
+    fn tick(&mut self);
+    // Runs M-cycles in which the CPU does nothing, up to the next one that
+    // might request an interrupt, and returns how many ran.
+    fn idle(&mut self) -> u64 {
+        self.tick();
+        1
     }
+    // Interrupts both requested and enabled.
+    fn pending_interrupts(&self) -> u8;
+    // Clears the request once the CPU has dispatched it.
+    fn acknowledge_interrupt(&mut self, mask: u8);
+}
 
-    fn write(address: u16, write_value: u8) -> Self {
-        Self {
-            op: BusOp::Write,
-            address,
-            write_value,
-            read_dest: Reg8::None,
-        }
+impl<T: MemoryBus + ?Sized> MemoryBus for &mut T {
+    fn read(&mut self, address: u16) -> Result<u8, bus::BusError> {
+        (**self).read(address)
     }
-}
 
-enum ExecOp {
-    Nop,
-    Dec16,
-    Inc8,
-    JmpRelative,
-}
+    fn write(&mut self, address: u16, value: u8) -> Result<(), bus::BusError> {
+        (**self).write(address, value)
+    }
 
-struct ExecRequest {
-    op: ExecOp,
-    op8_1: Reg8,
-    op8_2: Reg8,
-    op16_1: Reg16,
-    op16_2: Reg16,
-}
+    fn tick(&mut self) {
+        (**self).tick()
+    }
 
-impl ExecRequest {
-    fn nop() -> Self {
-        Self {
-            op: ExecOp::Nop,
-            op8_1: Reg8::None,
-            op8_2: Reg8::None,
-            op16_1: Reg16::None,
-            op16_2: Reg16::None,
-        }
+    fn idle(&mut self) -> u64 {
+        (**self).idle()
     }
 
-    fn op8(op: ExecOp, arg: Reg8) -> Self {
-        Self {
-            op,
-            op8_1: arg,
-            op8_2: Reg8::None,
-            op16_1: Reg16::None,
-            op16_2: Reg16::None,
-        }
+    fn pending_interrupts(&self) -> u8 {
+        (**self).pending_interrupts()
     }
 
-    fn op16(op: ExecOp, arg: Reg16) -> Self {
-        Self {
-            op,
-            op8_1: Reg8::None,
-            op8_2: Reg8::None,
-            op16_1: arg,
-            op16_2: Reg16::None,
-        }
+    fn acknowledge_interrupt(&mut self, mask: u8) {
+        (**self).acknowledge_interrupt(mask)
     }
 }
 
-pub struct Cpu<'a> {
-    a: i8,
-    b: i8,
-    c: i8,
-    d: i8,
-    e: i8,
-    h: i8,
-    l: i8,
-    imm8: i8,
-
-    flags: u8,
+pub struct Cpu<B> {
+    a: u8,
+    // Only the upper nibble of F exists; the low four bits always read 0.
+    f: u8,
+    b: u8,
+    c: u8,
+    d: u8,
+    e: u8,
+    h: u8,
+    l: u8,
+
     sp: u16,
     pc: u16,
-    ticks: u64,
 
-    bus_queue: std::collections::VecDeque<BusRequest>,
-    exec_queue: std::collections::VecDeque<ExecRequest>,
+    // Internal registers holding operands and addresses read from memory.
+    w: u8,
+    z: u8,
+
+    // Opcode of the next instruction. The SM83 fetches it during the last
+    // M-cycle of the current instruction, so PC already points past it.
+    ir: u8,
+
+    // The rest of the current instruction, one micro-op per M-cycle.
+    program: VecDeque<MicroOp>,
+
+    ime: bool,
+    // EI only enables interrupts after the instruction that follows it.
+    ime_scheduled: bool,
+    halted: bool,
+    // HALT with IME off and an interrupt already pending doesn't halt, but
+    // the next opcode fetch fails to increment PC.
+    halt_bug: bool,
+    // Set by the unused opcodes. Nothing but a reset gets the CPU going
+    // again, not even an interrupt.
+    locked: bool,
+
+    // Where the current instruction started, for error reports.
+    instruction_pc: u16,
+    opcode: u8,
 
-    bus: &'a mut bus::Bus<'a>,
+    ticks: u64,
+
+    bus: B,
 }
 
-impl<'a> Cpu<'a> {
-    pub fn new(bus: &'a mut bus::Bus<'a>) -> Self {
-        return Self {
+impl<B: MemoryBus> Cpu<B> {
+    // The CPU starts out as if it had just executed a NOP, so the first
+    // instruction fetches the opcode at PC.
+    pub fn new(bus: B) -> Self {
+        Self {
             a: 0,
+            f: 0,
             b: 0,
             c: 0,
             d: 0,
             e: 0,
-            flags: 0,
             h: 0,
             l: 0,
-            imm8: 0,
             sp: 0,
             pc: 0,
+            w: 0,
+            z: 0,
+            ir: NOP,
+            program: VecDeque::with_capacity(8),
+            ime: false,
+            ime_scheduled: false,
+            halted: false,
+            halt_bug: false,
+            locked: false,
+            instruction_pc: 0,
+            opcode: NOP,
             ticks: 0,
-            bus_queue: std::collections::VecDeque::new(),
-            exec_queue: std::collections::VecDeque::new(),
 
             bus,
-        };
+        }
     }
 
-    pub fn tick(&mut self) {
-        self.ticks += 1;
-        match self.bus_queue.pop_front() {
-            None => self.do_decode(),
-            Some(req) => self.do_bus_read_write(req),
-        }
-        match self.exec_queue.pop_front() {
-            None => (),
-            Some(req) => self.do_execute(req),
-        }
-        self.pc = self.pc + 1;
+    // Continues execution at `pc`, as a jump there would.
+    pub fn set_pc(&mut self, pc: u16) {
+        self.pc = pc;
+        self.ir = NOP;
+        self.program.clear();
     }
 
-    fn do_bus_read_write(&mut self, req: BusRequest) {
-        match req.op {
-            BusOp::Read => self.set_reg8(req.read_dest, self.bus.read(req.address) as i8),
-            BusOp::Write => self.bus.write(req.address, req.write_value),
-        }
+    pub fn bus(&self) -> &B {
+        &self.bus
     }
 
-    fn do_decode(&mut self) {
-        let opcode = Instruction::decode(self.bus.read(self.pc));
-
-        match opcode {
-            Instruction::BitTest7H => self.exec_bit_test_8(Reg8::H, 7),
-            Instruction::CBPrefix => self.cb(),
-            Instruction::JmpRelIfNonZero => self.queue_jmp_relative((!self.flags & FLAG_ZERO) > 0),
-            Instruction::IncC => self.queue_inc_8(Reg8::C),
-            Instruction::LoadAImm8 => self.queue_load_imm_8(Reg8::A),
-            Instruction::LoadCImm8 => self.queue_load_imm_8(Reg8::C),
-            Instruction::LoadHLImm16 => self.queue_load_imm_16(Reg8::L, Reg8::H),
-            Instruction::LoadSPImm16 => self.queue_load_imm_16(Reg8::SPLow, Reg8::SPHigh),
-            Instruction::StoreAToAddrHL => self.queue_store_to_addr_in_hl(self.a),
-            Instruction::StoreAToAddrHLDec => self.queue_store_a_to_addr_in_hl_dec(self.a),
-            Instruction::StoreAToAddrCHigh => self.queue_store_a_to_addr_in_c(),
-            Instruction::XorA8 => self.exec_xor_8(Reg8::A),
-            _ => panic!("cpu: instruction not implemented {:?}", opcode),
-        }
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn sp(&self) -> u16 {
+        self.sp
+    }
+
+    pub fn set_sp(&mut self, value: u16) {
+        self.sp = value;
+    }
+
+    pub fn ime(&self) -> bool {
+        self.ime
+    }
+
+    pub fn set_ime(&mut self, enabled: bool) {
+        self.ime = enabled;
+        self.ime_scheduled = false;
+    }
+
+    // M-cycles run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked
+    }
+
+    // Opcode of the next instruction, already fetched from PC - 1.
+    pub fn ir(&self) -> u8 {
+        self.ir
+    }
+
+    // Saves the registers only; the bus saves itself. States are taken
+    // between instructions, when the next opcode is in IR and nothing of
+    // the current program is left.
+    pub fn save_state(&self, state: &mut state::Writer) {
+        debug_assert!(self.program.is_empty());
+        state.bytes(&[
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ]);
+        state.u16(self.sp);
+        state.u16(self.pc);
+        state.bytes(&[self.w, self.z, self.ir]);
+        state.bool(self.ime);
+        state.bool(self.ime_scheduled);
+        state.bool(self.halted);
+        state.bool(self.halt_bug);
+        state.bool(self.locked);
+        state.u16(self.instruction_pc);
+        state.u8(self.opcode);
+        state.u64(self.ticks);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        let mut registers = [0; 8];
+        state.bytes(&mut registers)?;
+        [
+            self.a, self.f, self.b, self.c, self.d, self.e, self.h, self.l,
+        ] = registers;
+        self.f &= 0xF0;
+        self.sp = state.u16()?;
+        self.pc = state.u16()?;
+        let mut internal = [0; 3];
+        state.bytes(&mut internal)?;
+        [self.w, self.z, self.ir] = internal;
+        self.ime = state.bool()?;
+        self.ime_scheduled = state.bool()?;
+        self.halted = state.bool()?;
+        self.halt_bug = state.bool()?;
+        self.locked = state.bool()?;
+        self.instruction_pc = state.u16()?;
+        self.opcode = state.u8()?;
+        self.ticks = state.u64()?;
+        self.program.clear();
+        Ok(())
+    }
+
+    pub fn af(&self) -> u16 {
+        u16::from_be_bytes([self.a, self.f])
+    }
+
+    pub fn set_af(&mut self, value: u16) {
+        let [a, f] = value.to_be_bytes();
+        self.a = a;
+        self.set_f(f);
+    }
+
+    pub fn bc(&self) -> u16 {
+        u16::from_be_bytes([self.b, self.c])
+    }
+
+    pub fn set_bc(&mut self, value: u16) {
+        [self.b, self.c] = value.to_be_bytes();
+    }
+
+    pub fn de(&self) -> u16 {
+        u16::from_be_bytes([self.d, self.e])
     }
 
-    fn do_execute(&mut self, req: ExecRequest) {
-        match req.op {
-            ExecOp::Dec16 => self.exec_dec_16(req.op16_1),
-            ExecOp::Inc8 => self.exec_inc_8(req.op8_1),
-            ExecOp::JmpRelative => self.exec_jmp_rel(),
-            ExecOp::Nop => (),
-            // _ => panic!("do_execute: invalid operation"),
+    pub fn set_de(&mut self, value: u16) {
+        [self.d, self.e] = value.to_be_bytes();
+    }
+
+    pub fn hl(&self) -> u16 {
+        u16::from_be_bytes([self.h, self.l])
+    }
+
+    pub fn set_hl(&mut self, value: u16) {
+        [self.h, self.l] = value.to_be_bytes();
+    }
+
+    fn set_f(&mut self, value: u8) {
+        self.f = value & 0xF0;
+    }
+
+    // Runs the rest of the current instruction, or an interrupt dispatch,
+    // and returns the number of M-cycles it took.
+    pub fn step(&mut self) -> Result<u64, EmuError> {
+        let start = self.ticks;
+        self.tick()?;
+        while !self.program.is_empty() {
+            self.tick()?;
         }
+        Ok(self.ticks - start)
     }
 
-    fn exec_bit_test_8(&mut self, reg: Reg8, bit: i64) {
-        let res: i8 = match reg {
-            Reg8::A => self.a & (1 << bit),
-            Reg8::B => self.b & (1 << bit),
-            Reg8::C => self.c & (1 << bit),
-            Reg8::D => self.d & (1 << bit),
-            Reg8::E => self.e & (1 << bit),
-            Reg8::H => self.h & (1 << bit),
-            Reg8::L => self.l & (1 << bit),
-            _ => panic!("bit_test_8: invalid operand"),
-        };
-        self.flags = FLAG_H_CARRY | (self.flags & FLAG_CARRY);
-        if res == 0 {
-            self.flags |= FLAG_ZERO;
+    // Runs one M-cycle, or while halted possibly several.
+    pub fn tick(&mut self) -> Result<(), EmuError> {
+        if self.locked {
+            self.end_cycle();
+            return Ok(());
+        }
+        if self.halted {
+            // Only an interrupt wakes the CPU, so unless one is already
+            // pending the bus can run ahead to when the next might be.
+            if self.bus.pending_interrupts() == 0 {
+                self.ticks += self.bus.idle();
+            } else {
+                self.end_cycle();
+            }
+            if self.bus.pending_interrupts() != 0 {
+                self.halted = false;
+            }
+            return Ok(());
+        }
+
+        if self.program.is_empty() {
+            self.begin_instruction();
+        }
+        let mut op = self.program.pop_front().expect("cpu: empty program");
+
+        if let Some(condition) = op.condition {
+            if !self.condition(condition) {
+                self.program.clear();
+                op = microcode::fetch(Action::None);
+            }
+        }
+        if let Action::DecodeCb = op.action {
+            microcode::decode_cb(self.z, &mut self.program);
+            op = self.program.pop_front().expect("cpu: empty program");
         }
+
+        self.act(op.action);
+        let result = self.access(op.access);
+        self.end_cycle();
+        result.map_err(|access| EmuError {
+            pc: self.instruction_pc,
+            opcode: self.opcode,
+            access,
+        })
     }
 
-    fn exec_dec_16(&mut self, reg: Reg16) {
-        match reg {
-            Reg16::HL => {
-                let mut hl = ((self.h as i16) << 8) | (self.l as i16 & 0x00FF);
-                hl = hl.wrapping_sub(1);
+    fn end_cycle(&mut self) {
+        self.ticks += 1;
+        self.bus.tick();
+    }
 
-                self.flags = 0;
-                if hl == 0 {
-                    self.flags |= FLAG_ZERO;
+    // Interrupts are only taken between instructions.
+    fn begin_instruction(&mut self) {
+        self.instruction_pc = self.pc.wrapping_sub(1);
+        self.opcode = self.ir;
+        if self.ime && self.bus.pending_interrupts() != 0 {
+            microcode::interrupt(&mut self.program);
+            return;
+        }
+        if self.ime_scheduled {
+            self.ime_scheduled = false;
+            self.ime = true;
+        }
+        microcode::decode(self.ir, &mut self.program);
+    }
+
+    fn access(&mut self, access: Access) -> Result<(), bus::BusError> {
+        match access {
+            Access::None => Ok(()),
+            Access::Read(addr, dst) => {
+                let address = self.address(addr);
+                let (value, result) = match self.bus.read(address) {
+                    Ok(value) => (value, Ok(())),
+                    Err(err) => (0xFF, Err(err)),
+                };
+                match dst {
+                    Dst::Z => self.z = value,
+                    Dst::W => self.w = value,
+                    Dst::Ir => self.ir = value,
                 }
-                self.h = (hl >> 8) as i8;
-                self.l = hl as i8;
+                result
+            }
+            Access::Write(addr, src) => {
+                let value = match src {
+                    Src::Reg(r) => self.reg(r),
+                    Src::Hi(pair) => (self.pair(pair) >> 8) as u8,
+                    Src::Lo(pair) => self.pair(pair) as u8,
+                };
+                let address = self.address(addr);
+                self.bus.write(address, value)
             }
-            _ => panic!("exec_dec_16: invalid operand"),
         }
     }
 
-    fn exec_inc_8(&mut self, reg: Reg8) {
-        match reg {
-            Reg8::C => {
-                self.c += 1;
+    fn address(&mut self, addr: Addr) -> u16 {
+        match addr {
+            Addr::Pc => {
+                let pc = self.pc;
+                if self.halt_bug {
+                    self.halt_bug = false;
+                } else {
+                    self.pc = pc.wrapping_add(1);
+                }
+                pc
+            }
+            Addr::Bc => self.bc(),
+            Addr::De => self.de(),
+            Addr::Hl => self.hl(),
+            Addr::HlInc => {
+                let hl = self.hl();
+                self.set_hl(hl.wrapping_add(1));
+                hl
+            }
+            Addr::HlDec => {
+                let hl = self.hl();
+                self.set_hl(hl.wrapping_sub(1));
+                hl
+            }
+            Addr::Wz => self.pair(Pair::Wz),
+            Addr::WzInc => {
+                let wz = self.pair(Pair::Wz);
+                self.set_pair(Pair::Wz, wz.wrapping_add(1));
+                wz
+            }
+            Addr::HighZ => 0xFF00 | self.z as u16,
+            Addr::HighC => 0xFF00 | self.c as u16,
+            Addr::SpInc => {
+                let sp = self.sp;
+                self.sp = sp.wrapping_add(1);
+                sp
+            }
+            Addr::SpDec => {
+                self.sp = self.sp.wrapping_sub(1);
+                self.sp
             }
-            _ => panic!("exec_inc_u: invalid operand"),
         }
     }
 
-    fn exec_jmp_rel(&mut self) {
-        let signed_pc = self.pc as i16;
-        let signed_imm8 = self.imm8 as i16;
-        let signed_result = signed_pc + signed_imm8;
-        self.pc = signed_result as u16;
+    fn act(&mut self, action: Action) {
+        match action {
+            Action::None => (),
+            Action::Alu(op, r) => self.alu(op, self.reg(r)),
+            Action::Inc(r) => {
+                let value = self.reg(r);
+                let result = value.wrapping_add(1);
+                let carry = self.f & FLAG_CARRY != 0;
+                self.set_flags(result == 0, false, value & 0x0F == 0x0F, carry);
+                self.set_reg(r, result);
+            }
+            Action::Dec(r) => {
+                let value = self.reg(r);
+                let result = value.wrapping_sub(1);
+                let carry = self.f & FLAG_CARRY != 0;
+                self.set_flags(result == 0, true, value & 0x0F == 0, carry);
+                self.set_reg(r, result);
+            }
+            Action::Load(dst, src) => self.set_reg(dst, self.reg(src)),
+            Action::AccRotate(op) => {
+                // The accumulator rotates always clear Z.
+                self.a = self.rotate(op, self.a);
+                self.f &= !FLAG_ZERO;
+            }
+            Action::Daa => self.daa(),
+            Action::Cpl => {
+                self.a = !self.a;
+                self.f |= FLAG_SUB | FLAG_H_CARRY;
+            }
+            Action::Scf => {
+                let zero = self.f & FLAG_ZERO != 0;
+                self.set_flags(zero, false, false, true);
+            }
+            Action::Ccf => {
+                let zero = self.f & FLAG_ZERO != 0;
+                let carry = self.f & FLAG_CARRY != 0;
+                self.set_flags(zero, false, false, !carry);
+            }
+            Action::Cb(opcode) => self.cb(opcode),
+            Action::IncPair(pair) => self.set_pair(pair, self.pair(pair).wrapping_add(1)),
+            Action::DecPair(pair) => self.set_pair(pair, self.pair(pair).wrapping_sub(1)),
+            Action::AddHl(pair) => {
+                let hl = self.hl();
+                let value = self.pair(pair);
+                let (result, carry) = hl.overflowing_add(value);
+                let half_carry = (hl & 0x0FFF) + (value & 0x0FFF) > 0x0FFF;
+                let zero = self.f & FLAG_ZERO != 0;
+                self.set_flags(zero, false, half_carry, carry);
+                self.set_hl(result);
+            }
+            Action::SetPair(pair) => self.set_pair(pair, self.pair(Pair::Wz)),
+            Action::JumpWz => self.pc = self.pair(Pair::Wz),
+            Action::JumpHl => self.pc = self.hl(),
+            Action::JumpRelative => self.pc = self.pc.wrapping_add_signed(self.z as i8 as i16),
+            Action::Rst(vector) => self.pc = vector,
+            Action::SpFromHl => self.sp = self.hl(),
+            Action::AddSp => self.sp = self.add_sp_offset(),
+            Action::HlFromSpOffset => {
+                let value = self.add_sp_offset();
+                self.set_hl(value);
+            }
+            Action::Di => {
+                self.ime = false;
+                self.ime_scheduled = false;
+            }
+            Action::Ei => self.ime_scheduled = true,
+            Action::Reti => self.ime = true,
+            Action::Halt => {
+                if !self.ime && self.bus.pending_interrupts() != 0 {
+                    self.halt_bug = true;
+                } else {
+                    self.halted = true;
+                }
+            }
+            Action::DecodeCb => unreachable!(),
+            Action::Illegal => self.locked = true,
+            Action::BeginInterrupt => {
+                self.ime = false;
+                self.ime_scheduled = false;
+                self.pc = self.pc.wrapping_sub(1);
+            }
+            Action::PickVector => {
+                // With nothing left to service, execution continues at 0.
+                let pending = self.bus.pending_interrupts();
+                let vector = match INTERRUPT_VECTORS
+                    .iter()
+                    .find(|(mask, _)| pending & mask != 0)
+                {
+                    Some(&(mask, vector)) => {
+                        self.bus.acknowledge_interrupt(mask);
+                        vector
+                    }
+                    None => 0x0000,
+                };
+                self.set_pair(Pair::Wz, vector);
+            }
+        }
     }
 
-    fn exec_xor_8(&mut self, reg: Reg8) {
-        match reg {
-            Reg8::A => self.a ^= self.a,
-            Reg8::B => self.a ^= self.b,
-            Reg8::C => self.a ^= self.c,
-            Reg8::D => self.a ^= self.d,
-            Reg8::E => self.a ^= self.e,
-            Reg8::H => self.a ^= self.h,
-            Reg8::L => self.a ^= self.l,
-            _ => panic!("xor_8: invalid operand"),
-        }
-        self.flags = 0;
-        if self.a == 0 {
-            self.flags |= FLAG_ZERO;
+    fn reg(&self, r: u8) -> u8 {
+        match r {
+            0 => self.b,
+            1 => self.c,
+            2 => self.d,
+            3 => self.e,
+            4 => self.h,
+            5 => self.l,
+            microcode::Z => self.z,
+            _ => self.a,
         }
     }
 
-    fn queue_bus_read(&mut self, addr: u16, read_dest: Reg8) {
-        self.bus_queue.push_back(BusRequest::read(addr, read_dest));
+    fn set_reg(&mut self, r: u8, value: u8) {
+        match r {
+            0 => self.b = value,
+            1 => self.c = value,
+            2 => self.d = value,
+            3 => self.e = value,
+            4 => self.h = value,
+            5 => self.l = value,
+            microcode::Z => self.z = value,
+            _ => self.a = value,
+        }
     }
 
-    fn queue_bus_write(&mut self, addr: u16, write_value: i8) {
-        self.bus_queue
-            .push_back(BusRequest::write(addr, write_value as u8))
+    fn pair(&self, pair: Pair) -> u16 {
+        match pair {
+            Pair::Bc => self.bc(),
+            Pair::De => self.de(),
+            Pair::Hl => self.hl(),
+            Pair::Af => self.af(),
+            Pair::Sp => self.sp,
+            Pair::Pc => self.pc,
+            Pair::Wz => u16::from_be_bytes([self.w, self.z]),
+        }
     }
 
-    fn queue_inc_8(&mut self, reg: Reg8) {
-        self.exec_queue
-            .push_back(ExecRequest::op8(ExecOp::Inc8, reg))
+    fn set_pair(&mut self, pair: Pair, value: u16) {
+        match pair {
+            Pair::Bc => self.set_bc(value),
+            Pair::De => self.set_de(value),
+            Pair::Hl => self.set_hl(value),
+            Pair::Af => self.set_af(value),
+            Pair::Sp => self.sp = value,
+            Pair::Pc => self.pc = value,
+            Pair::Wz => [self.w, self.z] = value.to_be_bytes(),
+        }
     }
 
-    fn queue_jmp_relative(&mut self, condition: bool) {
-        self.queue_bus_read(self.pc + 1, Reg8::Imm8);
-        if condition {
-            self.exec_queue.push_back(ExecRequest::nop());
-            self.exec_queue
-                .push_back(ExecRequest::op8(ExecOp::JmpRelative, Reg8::Imm8));
+    // Conditions 0-3 in opcode order: NZ, Z, NC, C.
+    fn condition(&self, cc: u8) -> bool {
+        match cc {
+            0 => self.f & FLAG_ZERO == 0,
+            1 => self.f & FLAG_ZERO != 0,
+            2 => self.f & FLAG_CARRY == 0,
+            _ => self.f & FLAG_CARRY != 0,
         }
     }
 
-    fn queue_load_imm_8(&mut self, reg: Reg8) {
-        self.queue_bus_read(self.pc + 1, reg);
+    fn set_flags(&mut self, zero: bool, sub: bool, half_carry: bool, carry: bool) {
+        let mut f = 0;
+        if zero {
+            f |= FLAG_ZERO;
+        }
+        if sub {
+            f |= FLAG_SUB;
+        }
+        if half_carry {
+            f |= FLAG_H_CARRY;
+        }
+        if carry {
+            f |= FLAG_CARRY;
+        }
+        self.set_f(f);
     }
 
-    fn queue_load_imm_16(&mut self, reg_low: Reg8, reg_high: Reg8) {
-        self.queue_bus_read(self.pc + 1, reg_low);
-        self.queue_bus_read(self.pc + 2, reg_high);
+    fn cb(&mut self, opcode: u8) {
+        let y = (opcode >> 3) & 0x07;
+        let r = opcode & 0x07;
+        let value = self.reg(r);
+
+        match opcode >> 6 {
+            0 => {
+                let result = match y {
+                    0..=3 => {
+                        let result = self.rotate(y, value);
+                        if result == 0 {
+                            self.f |= FLAG_ZERO;
+                        }
+                        result
+                    }
+                    4 => self.shift(value << 1, value & 0x80 != 0),
+                    5 => self.shift((value >> 1) | (value & 0x80), value & 0x01 != 0),
+                    6 => {
+                        let result = value.rotate_left(4);
+                        self.set_flags(result == 0, false, false, false);
+                        result
+                    }
+                    _ => self.shift(value >> 1, value & 0x01 != 0),
+                };
+                self.set_reg(r, result);
+            }
+            1 => {
+                let carry = self.f & FLAG_CARRY != 0;
+                self.set_flags(value & (1 << y) == 0, false, true, carry);
+            }
+            2 => self.set_reg(r, value & !(1 << y)),
+            _ => self.set_reg(r, value | (1 << y)),
+        }
     }
 
-    fn queue_store_a_to_addr_in_c(&mut self) {
-        let addr = 0xFF00 | (self.c as u16 & 0x00FF);
-        self.queue_bus_write(addr, self.a);
+    // SP plus a signed immediate, as used by ADD SP,e and LD HL,SP+e. The
+    // carries come from the unsigned addition of the low bytes.
+    fn add_sp_offset(&mut self) -> u16 {
+        let offset = self.z;
+        let sp = self.sp;
+        let half_carry = (sp & 0x0F) + (offset as u16 & 0x0F) > 0x0F;
+        let carry = (sp & 0xFF) + offset as u16 > 0xFF;
+        self.set_flags(false, false, half_carry, carry);
+        sp.wrapping_add_signed(offset as i8 as i16)
     }
 
-    fn queue_store_to_addr_in_hl(&mut self, value: i8) {
-        let hl: u16 = (self.h as u16) << 8 | (self.l as u16 & 0x00FF);
-        self.queue_bus_write(hl, value)
+    // ADD, ADC, SUB, SBC, AND, XOR, OR and CP, in opcode order.
+    fn alu(&mut self, op: u8, value: u8) {
+        let a = self.a;
+        let carry_in = (op == 1 || op == 3) && self.f & FLAG_CARRY != 0;
+        let c = carry_in as u8;
+        match op {
+            0 | 1 => {
+                let result = a as u16 + value as u16 + c as u16;
+                let half_carry = (a & 0x0F) + (value & 0x0F) + c > 0x0F;
+                self.a = result as u8;
+                self.set_flags(self.a == 0, false, half_carry, result > 0xFF);
+            }
+            2 | 3 | 7 => {
+                let result = a as i16 - value as i16 - c as i16;
+                let half_carry = a & 0x0F < (value & 0x0F) + c;
+                self.set_flags(result as u8 == 0, true, half_carry, result < 0);
+                if op != 7 {
+                    self.a = result as u8;
+                }
+            }
+            4 => {
+                self.a &= value;
+                self.set_flags(self.a == 0, false, true, false);
+            }
+            5 => {
+                self.a ^= value;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+            _ => {
+                self.a |= value;
+                self.set_flags(self.a == 0, false, false, false);
+            }
+        }
     }
 
-    fn queue_store_a_to_addr_in_hl_dec(&mut self, value: i8) {
-        let hl: u16 = (self.h as u16) << 8 | (self.l as u16 & 0x00FF);
+    // RLC, RRC, RL and RR, in opcode order. Z is left for the caller.
+    fn rotate(&mut self, op: u8, value: u8) -> u8 {
+        let carry_in = self.f & FLAG_CARRY != 0;
+        let (result, carry) = match op {
+            0 => (value.rotate_left(1), value & 0x80 != 0),
+            1 => (value.rotate_right(1), value & 0x01 != 0),
+            2 => ((value << 1) | carry_in as u8, value & 0x80 != 0),
+            _ => ((value >> 1) | (carry_in as u8) << 7, value & 0x01 != 0),
+        };
+        self.set_flags(false, false, false, carry);
+        result
+    }
 
-        self.queue_bus_write(hl, value);
-        self.exec_queue.push_back(ExecRequest::nop());
-        self.exec_queue
-            .push_back(ExecRequest::op16(ExecOp::Dec16, Reg16::HL));
+    // SLA, SRA and SRL, given the shifted value and the bit shifted out.
+    fn shift(&mut self, result: u8, carry: bool) -> u8 {
+        self.set_flags(result == 0, false, false, carry);
+        result
     }
 
-    fn cb(&mut self) {}
+    fn daa(&mut self) {
+        let mut a = self.a;
+        let mut carry = self.f & FLAG_CARRY != 0;
+        let half_carry = self.f & FLAG_H_CARRY != 0;
+        let sub = self.f & FLAG_SUB != 0;
 
-    fn set_reg8(&mut self, reg: Reg8, val: i8) {
-        match reg {
-            Reg8::A => self.a = val,
-            Reg8::B => self.b = val,
-            Reg8::C => self.c = val,
-            Reg8::D => self.d = val,
-            Reg8::E => self.e = val,
-            Reg8::H => self.h = val,
-            Reg8::L => self.l = val,
-            Reg8::Imm8 => self.imm8 = val,
-            Reg8::SPLow => self.sp = (self.sp & 0xFF00) | (val as u16 & 0x00FF),
-            Reg8::SPHigh => self.sp = (self.sp & 0x00FF) | ((val as u16 & 0x00FF) << 8),
-            _ => panic!("invalid register"),
+        if sub {
+            if carry {
+                a = a.wrapping_sub(0x60);
+            }
+            if half_carry {
+                a = a.wrapping_sub(0x06);
+            }
+        } else {
+            if carry || a > 0x99 {
+                a = a.wrapping_add(0x60);
+                carry = true;
+            }
+            if half_carry || a & 0x0F > 0x09 {
+                a = a.wrapping_add(0x06);
+            }
         }
+
+        self.a = a;
+        self.set_flags(a == 0, sub, false, carry);
     }
 }
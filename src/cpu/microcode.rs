@@ -0,0 +1,416 @@
+use super::opcodes::{self, Mnemonic, Operand, R16, R8};
+use std::collections::VecDeque;
+use Mnemonic::*;
+use Operand::{
+    Cond, Direct, HighC, HighImm8, HlDec, HlInc, Imm16, Imm8, Ind, Reg16, Reg8, SpOffset,
+};
+
+// 8-bit operands are numbered as in the opcodes: B, C, D, E, H, L, (HL), A.
+// An operand in memory is always read into Z first, so in micro-ops 6 means
+// the Z register.
+pub const Z: u8 = 6;
+pub const A: u8 = 7;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Pair {
+    Bc,
+    De,
+    Hl,
+    Af,
+    Sp,
+    Pc,
+    Wz,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Addr {
+    // PC, incremented after the access.
+    Pc,
+    Bc,
+    De,
+    Hl,
+    HlInc,
+    HlDec,
+    Wz,
+    WzInc,
+    HighZ,
+    HighC,
+    // SP, incremented after the access.
+    SpInc,
+    // SP, decremented before the access.
+    SpDec,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Src {
+    Reg(u8),
+    Hi(Pair),
+    Lo(Pair),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Dst {
+    Z,
+    W,
+    Ir,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Access {
+    None,
+    Read(Addr, Dst),
+    Write(Addr, Src),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Action {
+    None,
+    // ADD, ADC, SUB, SBC, AND, XOR, OR or CP of A and an operand.
+    Alu(u8, u8),
+    Inc(u8),
+    Dec(u8),
+    // Destination and source operand.
+    Load(u8, u8),
+    // RLCA, RRCA, RLA or RRA.
+    AccRotate(u8),
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    // A CB-prefixed operation on the operand in its low 3 bits.
+    Cb(u8),
+    IncPair(Pair),
+    DecPair(Pair),
+    AddHl(Pair),
+    // Loads a pair from WZ.
+    SetPair(Pair),
+    JumpWz,
+    JumpHl,
+    JumpRelative,
+    Rst(u16),
+    SpFromHl,
+    AddSp,
+    HlFromSpOffset,
+    Di,
+    Ei,
+    Reti,
+    Halt,
+    // Replaces the rest of the program with the CB instruction in Z, which
+    // then runs from this cycle on.
+    DecodeCb,
+    Illegal,
+    BeginInterrupt,
+    PickVector,
+}
+
+// What the CPU does in one M-cycle. The action happens first, then the
+// access. With a condition that doesn't hold, the program ends early: this
+// cycle fetches the next opcode instead.
+#[derive(Clone, Copy, Debug)]
+pub struct MicroOp {
+    pub condition: Option<u8>,
+    pub action: Action,
+    pub access: Access,
+}
+
+const fn op(action: Action, access: Access) -> MicroOp {
+    MicroOp {
+        condition: None,
+        action,
+        access,
+    }
+}
+
+const fn when(condition: u8, action: Action, access: Access) -> MicroOp {
+    MicroOp {
+        condition: Some(condition),
+        action,
+        access,
+    }
+}
+
+const fn read(addr: Addr, dst: Dst) -> MicroOp {
+    op(Action::None, Access::Read(addr, dst))
+}
+
+const fn write(addr: Addr, src: Src) -> MicroOp {
+    op(Action::None, Access::Write(addr, src))
+}
+
+const fn internal(action: Action) -> MicroOp {
+    op(action, Access::None)
+}
+
+// Every program ends by fetching the next opcode, which overlaps with
+// whatever the instruction finishes in its last cycle.
+pub const fn fetch(action: Action) -> MicroOp {
+    op(action, Access::Read(Addr::Pc, Dst::Ir))
+}
+
+const IMM_Z: MicroOp = read(Addr::Pc, Dst::Z);
+const IMM_W: MicroOp = read(Addr::Pc, Dst::W);
+const FETCH: MicroOp = fetch(Action::None);
+
+// BC, DE, HL, SP and AF.
+fn pair(operand: Operand) -> Pair {
+    match operand {
+        Reg16(R16::Bc) => Pair::Bc,
+        Reg16(R16::De) => Pair::De,
+        Reg16(R16::Sp) => Pair::Sp,
+        Reg16(R16::Af) => Pair::Af,
+        _ => Pair::Hl,
+    }
+}
+
+// (BC), (DE), (HL+) and (HL-).
+fn indirect(operand: Operand) -> Addr {
+    match operand {
+        Ind(R16::Bc) => Addr::Bc,
+        Ind(R16::De) => Addr::De,
+        HlInc => Addr::HlInc,
+        HlDec => Addr::HlDec,
+        _ => Addr::Hl,
+    }
+}
+
+pub fn decode(opcode: u8, program: &mut VecDeque<MicroOp>) {
+    let info = opcodes::lookup(opcode);
+    let ops: &[MicroOp] = match (info.mnemonic, info.operands) {
+        (Nop, _) => &[FETCH],
+        // STOP is followed by a byte that is skipped. Low power mode isn't
+        // emulated yet.
+        (Stop, _) => &[IMM_Z, FETCH],
+        (Halt, _) => &[fetch(Action::Halt)],
+        (Di, _) => &[fetch(Action::Di)],
+        (Ei, _) => &[fetch(Action::Ei)],
+        (Daa, _) => &[fetch(Action::Daa)],
+        (Cpl, _) => &[fetch(Action::Cpl)],
+        (Scf, _) => &[fetch(Action::Scf)],
+        (Ccf, _) => &[fetch(Action::Ccf)],
+        (m @ (Rlca | Rrca | Rla | Rra), _) => &[fetch(Action::AccRotate(m as u8 - Rlca as u8))],
+
+        (Ld, [Direct, Reg16(R16::Sp)]) => &[
+            IMM_Z,
+            IMM_W,
+            write(Addr::WzInc, Src::Lo(Pair::Sp)),
+            write(Addr::Wz, Src::Hi(Pair::Sp)),
+            FETCH,
+        ],
+        (Ld, [Direct, Reg8(R8::A)]) => &[IMM_Z, IMM_W, write(Addr::Wz, Src::Reg(A)), FETCH],
+        (Ld, [Reg8(R8::A), Direct]) => &[
+            IMM_Z,
+            IMM_W,
+            read(Addr::Wz, Dst::Z),
+            fetch(Action::Load(A, Z)),
+        ],
+        (Ld, [Reg16(R16::Hl), SpOffset]) => &[IMM_Z, internal(Action::HlFromSpOffset), FETCH],
+        (Ld, [Reg16(R16::Sp), Reg16(R16::Hl)]) => &[internal(Action::SpFromHl), FETCH],
+        (Ld, [r @ Reg16(_), Imm16]) => &[IMM_Z, IMM_W, fetch(Action::SetPair(pair(r)))],
+        (Ld, [Reg8(R8::HlInd), Imm8]) => &[IMM_Z, write(Addr::Hl, Src::Reg(Z)), FETCH],
+        (Ld, [Reg8(r), Imm8]) => &[IMM_Z, fetch(Action::Load(r as u8, Z))],
+        (Ld, [Reg8(R8::HlInd), Reg8(r)]) => &[write(Addr::Hl, Src::Reg(r as u8)), FETCH],
+        (Ld, [Reg8(r), Reg8(R8::HlInd)]) => {
+            &[read(Addr::Hl, Dst::Z), fetch(Action::Load(r as u8, Z))]
+        }
+        (Ld, [Reg8(dst), Reg8(src)]) => &[fetch(Action::Load(dst as u8, src as u8))],
+        (Ld, [dst, Reg8(R8::A)]) => &[write(indirect(dst), Src::Reg(A)), FETCH],
+        (Ld, [_, src]) => &[read(indirect(src), Dst::Z), fetch(Action::Load(A, Z))],
+        (Ldh, [HighImm8, _]) => &[IMM_Z, write(Addr::HighZ, Src::Reg(A)), FETCH],
+        (Ldh, [_, HighImm8]) => &[IMM_Z, read(Addr::HighZ, Dst::Z), fetch(Action::Load(A, Z))],
+        (Ldh, [HighC, _]) => &[write(Addr::HighC, Src::Reg(A)), FETCH],
+        (Ldh, _) => &[read(Addr::HighC, Dst::Z), fetch(Action::Load(A, Z))],
+
+        (Inc, [Reg8(R8::HlInd), _]) => &[
+            read(Addr::Hl, Dst::Z),
+            op(Action::Inc(Z), Access::Write(Addr::Hl, Src::Reg(Z))),
+            FETCH,
+        ],
+        (Dec, [Reg8(R8::HlInd), _]) => &[
+            read(Addr::Hl, Dst::Z),
+            op(Action::Dec(Z), Access::Write(Addr::Hl, Src::Reg(Z))),
+            FETCH,
+        ],
+        (Inc, [Reg8(r), _]) => &[fetch(Action::Inc(r as u8))],
+        (Dec, [Reg8(r), _]) => &[fetch(Action::Dec(r as u8))],
+        (Inc, [r, _]) => &[internal(Action::IncPair(pair(r))), FETCH],
+        (Dec, [r, _]) => &[internal(Action::DecPair(pair(r))), FETCH],
+        (Add, [Reg16(R16::Hl), r]) => &[internal(Action::AddHl(pair(r))), FETCH],
+        (Add, [Reg16(R16::Sp), _]) => &[
+            IMM_Z,
+            internal(Action::AddSp),
+            internal(Action::None),
+            FETCH,
+        ],
+        (m @ (Add | Adc | Sub | Sbc | And | Xor | Or | Cp), [_, Reg8(R8::HlInd)]) => &[
+            read(Addr::Hl, Dst::Z),
+            fetch(Action::Alu(m as u8 - Add as u8, Z)),
+        ],
+        (m @ (Add | Adc | Sub | Sbc | And | Xor | Or | Cp), [_, Reg8(r)]) => {
+            &[fetch(Action::Alu(m as u8 - Add as u8, r as u8))]
+        }
+        (m @ (Add | Adc | Sub | Sbc | And | Xor | Or | Cp), _) => {
+            &[IMM_Z, fetch(Action::Alu(m as u8 - Add as u8, Z))]
+        }
+
+        (Jr, [Cond(c), _]) => &[
+            IMM_Z,
+            when(c as u8, Action::JumpRelative, Access::None),
+            FETCH,
+        ],
+        (Jr, _) => &[IMM_Z, internal(Action::JumpRelative), FETCH],
+        (Jp, [Cond(c), _]) => &[
+            IMM_Z,
+            IMM_W,
+            when(c as u8, Action::JumpWz, Access::None),
+            FETCH,
+        ],
+        (Jp, [Reg16(R16::Hl), _]) => &[fetch(Action::JumpHl)],
+        (Jp, _) => &[IMM_Z, IMM_W, internal(Action::JumpWz), FETCH],
+        (Call, [Cond(c), _]) => &[
+            IMM_Z,
+            IMM_W,
+            when(c as u8, Action::None, Access::None),
+            write(Addr::SpDec, Src::Hi(Pair::Pc)),
+            write(Addr::SpDec, Src::Lo(Pair::Pc)),
+            fetch(Action::JumpWz),
+        ],
+        (Call, _) => &[
+            IMM_Z,
+            IMM_W,
+            internal(Action::None),
+            write(Addr::SpDec, Src::Hi(Pair::Pc)),
+            write(Addr::SpDec, Src::Lo(Pair::Pc)),
+            fetch(Action::JumpWz),
+        ],
+        (Ret, [Cond(c), _]) => &[
+            internal(Action::None),
+            when(c as u8, Action::None, Access::Read(Addr::SpInc, Dst::Z)),
+            read(Addr::SpInc, Dst::W),
+            internal(Action::JumpWz),
+            FETCH,
+        ],
+        (Ret, _) => &[
+            read(Addr::SpInc, Dst::Z),
+            read(Addr::SpInc, Dst::W),
+            internal(Action::JumpWz),
+            FETCH,
+        ],
+        (Reti, _) => &[
+            read(Addr::SpInc, Dst::Z),
+            read(Addr::SpInc, Dst::W),
+            internal(Action::JumpWz),
+            fetch(Action::Reti),
+        ],
+        // The vector is also bits 3-5 of the opcode.
+        (Rst, _) => &[
+            internal(Action::None),
+            write(Addr::SpDec, Src::Hi(Pair::Pc)),
+            write(Addr::SpDec, Src::Lo(Pair::Pc)),
+            fetch(Action::Rst(opcode as u16 & 0x38)),
+        ],
+        (Push, [r, _]) => &[
+            internal(Action::None),
+            write(Addr::SpDec, Src::Hi(pair(r))),
+            write(Addr::SpDec, Src::Lo(pair(r))),
+            FETCH,
+        ],
+        (Pop, [r, _]) => &[
+            read(Addr::SpInc, Dst::Z),
+            read(Addr::SpInc, Dst::W),
+            fetch(Action::SetPair(pair(r))),
+        ],
+        (Prefix, _) => &[IMM_Z, internal(Action::DecodeCb)],
+        // The shifts and bit operations only come after a CB prefix.
+        (Illegal | Rlc | Rrc | Rl | Rr | Sla | Sra | Swap | Srl | Bit | Res | Set, _) => {
+            &[internal(Action::Illegal)]
+        }
+    };
+    program.extend(ops);
+}
+
+// The CB opcode itself has already been read into Z.
+pub fn decode_cb(opcode: u8, program: &mut VecDeque<MicroOp>) {
+    let info = opcodes::lookup_cb(opcode);
+    let ops: &[MicroOp] = match (info.mnemonic, info.operands) {
+        (Bit, [_, Reg8(R8::HlInd)]) => &[read(Addr::Hl, Dst::Z), fetch(Action::Cb(opcode))],
+        (_, [Reg8(R8::HlInd), _] | [_, Reg8(R8::HlInd)]) => &[
+            read(Addr::Hl, Dst::Z),
+            op(Action::Cb(opcode), Access::Write(Addr::Hl, Src::Reg(Z))),
+            FETCH,
+        ],
+        _ => &[fetch(Action::Cb(opcode))],
+    };
+    program.extend(ops);
+}
+
+// The opcode already fetched is dropped and PC pushed as it was before the
+// fetch. Pushing the high byte can overwrite IE, so the interrupt to
+// service is only picked after that.
+pub fn interrupt(program: &mut VecDeque<MicroOp>) {
+    program.extend([
+        internal(Action::BeginInterrupt),
+        internal(Action::None),
+        write(Addr::SpDec, Src::Hi(Pair::Pc)),
+        op(
+            Action::PickVector,
+            Access::Write(Addr::SpDec, Src::Lo(Pair::Pc)),
+        ),
+        fetch(Action::JumpWz),
+    ]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // M-cycles with the condition held, and with it failing.
+    fn lengths(program: &VecDeque<MicroOp>) -> (usize, usize) {
+        let not_taken = program
+            .iter()
+            .position(|op| op.condition.is_some())
+            .map_or(program.len(), |i| i + 1);
+        (program.len(), not_taken)
+    }
+
+    #[test]
+    fn programs_take_the_listed_cycles() {
+        for opcode in 0..=255 {
+            // The prefix's cycles are counted with the CB opcodes.
+            let info = opcodes::lookup(opcode);
+            if info.is_illegal() || opcode == 0xCB {
+                continue;
+            }
+            let mut program = VecDeque::new();
+            decode(opcode, &mut program);
+            let (taken, not_taken) = lengths(&program);
+            assert_eq!(
+                (taken, not_taken),
+                (info.cycles as usize, info.cycles_not_taken as usize),
+                "{:02X}",
+                opcode
+            );
+        }
+    }
+
+    // The prefix reads the CB opcode, then its program takes over in the
+    // cycle after.
+    #[test]
+    fn cb_programs_take_the_listed_cycles() {
+        for opcode in 0..=255 {
+            let info = opcodes::lookup_cb(opcode);
+            let mut program = VecDeque::new();
+            decode(0xCB, &mut program);
+            assert!(matches!(
+                program.pop_back().unwrap().action,
+                Action::DecodeCb
+            ));
+            decode_cb(opcode, &mut program);
+            assert_eq!(
+                lengths(&program),
+                (info.cycles as usize, info.cycles_not_taken as usize),
+                "CB {:02X}",
+                opcode
+            );
+        }
+    }
+}
@@ -0,0 +1,581 @@
+// Everything the emulator knows about each opcode, in one place. The CPU
+// builds its micro-op programs from the mnemonic and operands, and the
+// disassembler prints them.
+//
+// Mnemonics and operand order follow the usual SM83 syntax, with ALU
+// instructions always naming A (`SUB A,B`). Cycle counts are in M-cycles.
+
+use std::fmt;
+use Mnemonic::*;
+use Operand::{
+    Cond, Direct, HighC, HighImm8, HlDec, HlInc, Imm16, Imm8, Ind, Offset, Reg16, Reg8, SpOffset,
+    Vector,
+};
+
+// The eight ALU operations, the four accumulator rotates and the eight CB
+// shifts are each kept together and in opcode order, so the CPU can number
+// them by their distance from the first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mnemonic {
+    Nop,
+    Stop,
+    Halt,
+    Di,
+    Ei,
+    Daa,
+    Cpl,
+    Scf,
+    Ccf,
+    Rlca,
+    Rrca,
+    Rla,
+    Rra,
+    Ld,
+    Ldh,
+    Inc,
+    Dec,
+    Add,
+    Adc,
+    Sub,
+    Sbc,
+    And,
+    Xor,
+    Or,
+    Cp,
+    Jr,
+    Jp,
+    Call,
+    Ret,
+    Reti,
+    Rst,
+    Push,
+    Pop,
+    Prefix,
+    Rlc,
+    Rrc,
+    Rl,
+    Rr,
+    Sla,
+    Sra,
+    Swap,
+    Srl,
+    Bit,
+    Res,
+    Set,
+    Illegal,
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Nop => "NOP",
+            Stop => "STOP",
+            Halt => "HALT",
+            Di => "DI",
+            Ei => "EI",
+            Daa => "DAA",
+            Cpl => "CPL",
+            Scf => "SCF",
+            Ccf => "CCF",
+            Rlca => "RLCA",
+            Rrca => "RRCA",
+            Rla => "RLA",
+            Rra => "RRA",
+            Ld => "LD",
+            Ldh => "LDH",
+            Inc => "INC",
+            Dec => "DEC",
+            Add => "ADD",
+            Adc => "ADC",
+            Sub => "SUB",
+            Sbc => "SBC",
+            And => "AND",
+            Xor => "XOR",
+            Or => "OR",
+            Cp => "CP",
+            Jr => "JR",
+            Jp => "JP",
+            Call => "CALL",
+            Ret => "RET",
+            Reti => "RETI",
+            Rst => "RST",
+            Push => "PUSH",
+            Pop => "POP",
+            Prefix => "PREFIX",
+            Rlc => "RLC",
+            Rrc => "RRC",
+            Rl => "RL",
+            Rr => "RR",
+            Sla => "SLA",
+            Sra => "SRA",
+            Swap => "SWAP",
+            Srl => "SRL",
+            Bit => "BIT",
+            Res => "RES",
+            Set => "SET",
+            Illegal => "ILLEGAL",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum R8 {
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    // The byte at (HL), which opcodes encode as an 8-bit register.
+    HlInd,
+    A,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum R16 {
+    Bc,
+    De,
+    Hl,
+    Sp,
+    Af,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Condition {
+    Nz,
+    Z,
+    Nc,
+    C,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    None,
+    Reg8(R8),
+    Reg16(R16),
+    // (BC) or (DE).
+    Ind(R16),
+    // (HL+) and (HL-).
+    HlInc,
+    HlDec,
+    // ($FF00+C) and ($FF00+n).
+    HighC,
+    HighImm8,
+    Imm8,
+    Imm16,
+    // The byte at an immediate 16-bit address.
+    Direct,
+    // Signed 8-bit immediate.
+    Offset,
+    // SP plus a signed 8-bit immediate.
+    SpOffset,
+    Cond(Condition),
+    Bit(u8),
+    Vector(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlagEffect {
+    Unchanged,
+    Reset,
+    Set,
+    Affected,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: Mnemonic,
+    pub operands: [Operand; 2],
+    // Including the opcode itself and, for CB opcodes, the prefix.
+    pub length: u8,
+    pub cycles: u8,
+    // The same as `cycles` unless the instruction is conditional.
+    pub cycles_not_taken: u8,
+    // Z, N, H and C.
+    pub flags: [FlagEffect; 4],
+}
+
+impl Opcode {
+    // The 11 unused opcodes lock up the CPU.
+    pub fn is_illegal(&self) -> bool {
+        self.length == 0
+    }
+
+    pub fn is_conditional(&self) -> bool {
+        matches!(self.operands[0], Operand::Cond(_))
+    }
+}
+
+pub fn lookup(opcode: u8) -> &'static Opcode {
+    &UNPREFIXED[opcode as usize]
+}
+
+pub fn lookup_cb(opcode: u8) -> &'static Opcode {
+    &CB_PREFIXED[opcode as usize]
+}
+
+const NONE: Operand = Operand::None;
+
+const ILLEGAL: Opcode = Opcode {
+    mnemonic: Illegal,
+    operands: [NONE; 2],
+    length: 0,
+    cycles: 0,
+    cycles_not_taken: 0,
+    flags: [FlagEffect::Unchanged; 4],
+};
+
+// Flags are written as in the usual opcode tables: the flag's name if it's
+// affected, 0 or 1 if it's reset or set, and - if it's left alone.
+const fn op(
+    mnemonic: Mnemonic,
+    operands: [Operand; 2],
+    length: u8,
+    cycles: u8,
+    flags: &str,
+) -> Opcode {
+    let flags = flags.as_bytes();
+    let mut effects = [FlagEffect::Unchanged; 4];
+    let mut i = 0;
+    while i < 4 {
+        effects[i] = match flags[i] {
+            b'-' => FlagEffect::Unchanged,
+            b'0' => FlagEffect::Reset,
+            b'1' => FlagEffect::Set,
+            _ => FlagEffect::Affected,
+        };
+        i += 1;
+    }
+    Opcode {
+        mnemonic,
+        operands,
+        length,
+        cycles,
+        cycles_not_taken: cycles,
+        flags: effects,
+    }
+}
+
+const fn branch(
+    mnemonic: Mnemonic,
+    operands: [Operand; 2],
+    length: u8,
+    cycles: u8,
+    cycles_not_taken: u8,
+) -> Opcode {
+    Opcode {
+        cycles_not_taken,
+        ..op(mnemonic, operands, length, cycles, "----")
+    }
+}
+
+#[rustfmt::skip]
+pub const UNPREFIXED: [Opcode; 256] = [
+    op(Nop, [NONE, NONE], 1, 1, "----"), // 00
+    op(Ld, [Reg16(R16::Bc), Imm16], 3, 3, "----"), // 01
+    op(Ld, [Ind(R16::Bc), Reg8(R8::A)], 1, 2, "----"), // 02
+    op(Inc, [Reg16(R16::Bc), NONE], 1, 2, "----"), // 03
+    op(Inc, [Reg8(R8::B), NONE], 1, 1, "Z0H-"), // 04
+    op(Dec, [Reg8(R8::B), NONE], 1, 1, "Z1H-"), // 05
+    op(Ld, [Reg8(R8::B), Imm8], 2, 2, "----"), // 06
+    op(Rlca, [NONE, NONE], 1, 1, "000C"), // 07
+    op(Ld, [Direct, Reg16(R16::Sp)], 3, 5, "----"), // 08
+    op(Add, [Reg16(R16::Hl), Reg16(R16::Bc)], 1, 2, "-0HC"), // 09
+    op(Ld, [Reg8(R8::A), Ind(R16::Bc)], 1, 2, "----"), // 0A
+    op(Dec, [Reg16(R16::Bc), NONE], 1, 2, "----"), // 0B
+    op(Inc, [Reg8(R8::C), NONE], 1, 1, "Z0H-"), // 0C
+    op(Dec, [Reg8(R8::C), NONE], 1, 1, "Z1H-"), // 0D
+    op(Ld, [Reg8(R8::C), Imm8], 2, 2, "----"), // 0E
+    op(Rrca, [NONE, NONE], 1, 1, "000C"), // 0F
+    op(Stop, [NONE, NONE], 2, 2, "----"), // 10
+    op(Ld, [Reg16(R16::De), Imm16], 3, 3, "----"), // 11
+    op(Ld, [Ind(R16::De), Reg8(R8::A)], 1, 2, "----"), // 12
+    op(Inc, [Reg16(R16::De), NONE], 1, 2, "----"), // 13
+    op(Inc, [Reg8(R8::D), NONE], 1, 1, "Z0H-"), // 14
+    op(Dec, [Reg8(R8::D), NONE], 1, 1, "Z1H-"), // 15
+    op(Ld, [Reg8(R8::D), Imm8], 2, 2, "----"), // 16
+    op(Rla, [NONE, NONE], 1, 1, "000C"), // 17
+    op(Jr, [Offset, NONE], 2, 3, "----"), // 18
+    op(Add, [Reg16(R16::Hl), Reg16(R16::De)], 1, 2, "-0HC"), // 19
+    op(Ld, [Reg8(R8::A), Ind(R16::De)], 1, 2, "----"), // 1A
+    op(Dec, [Reg16(R16::De), NONE], 1, 2, "----"), // 1B
+    op(Inc, [Reg8(R8::E), NONE], 1, 1, "Z0H-"), // 1C
+    op(Dec, [Reg8(R8::E), NONE], 1, 1, "Z1H-"), // 1D
+    op(Ld, [Reg8(R8::E), Imm8], 2, 2, "----"), // 1E
+    op(Rra, [NONE, NONE], 1, 1, "000C"), // 1F
+    branch(Jr, [Cond(Condition::Nz), Offset], 2, 3, 2), // 20
+    op(Ld, [Reg16(R16::Hl), Imm16], 3, 3, "----"), // 21
+    op(Ld, [HlInc, Reg8(R8::A)], 1, 2, "----"), // 22
+    op(Inc, [Reg16(R16::Hl), NONE], 1, 2, "----"), // 23
+    op(Inc, [Reg8(R8::H), NONE], 1, 1, "Z0H-"), // 24
+    op(Dec, [Reg8(R8::H), NONE], 1, 1, "Z1H-"), // 25
+    op(Ld, [Reg8(R8::H), Imm8], 2, 2, "----"), // 26
+    op(Daa, [NONE, NONE], 1, 1, "Z-0C"), // 27
+    branch(Jr, [Cond(Condition::Z), Offset], 2, 3, 2), // 28
+    op(Add, [Reg16(R16::Hl), Reg16(R16::Hl)], 1, 2, "-0HC"), // 29
+    op(Ld, [Reg8(R8::A), HlInc], 1, 2, "----"), // 2A
+    op(Dec, [Reg16(R16::Hl), NONE], 1, 2, "----"), // 2B
+    op(Inc, [Reg8(R8::L), NONE], 1, 1, "Z0H-"), // 2C
+    op(Dec, [Reg8(R8::L), NONE], 1, 1, "Z1H-"), // 2D
+    op(Ld, [Reg8(R8::L), Imm8], 2, 2, "----"), // 2E
+    op(Cpl, [NONE, NONE], 1, 1, "-11-"), // 2F
+    branch(Jr, [Cond(Condition::Nc), Offset], 2, 3, 2), // 30
+    op(Ld, [Reg16(R16::Sp), Imm16], 3, 3, "----"), // 31
+    op(Ld, [HlDec, Reg8(R8::A)], 1, 2, "----"), // 32
+    op(Inc, [Reg16(R16::Sp), NONE], 1, 2, "----"), // 33
+    op(Inc, [Reg8(R8::HlInd), NONE], 1, 3, "Z0H-"), // 34
+    op(Dec, [Reg8(R8::HlInd), NONE], 1, 3, "Z1H-"), // 35
+    op(Ld, [Reg8(R8::HlInd), Imm8], 2, 3, "----"), // 36
+    op(Scf, [NONE, NONE], 1, 1, "-001"), // 37
+    branch(Jr, [Cond(Condition::C), Offset], 2, 3, 2), // 38
+    op(Add, [Reg16(R16::Hl), Reg16(R16::Sp)], 1, 2, "-0HC"), // 39
+    op(Ld, [Reg8(R8::A), HlDec], 1, 2, "----"), // 3A
+    op(Dec, [Reg16(R16::Sp), NONE], 1, 2, "----"), // 3B
+    op(Inc, [Reg8(R8::A), NONE], 1, 1, "Z0H-"), // 3C
+    op(Dec, [Reg8(R8::A), NONE], 1, 1, "Z1H-"), // 3D
+    op(Ld, [Reg8(R8::A), Imm8], 2, 2, "----"), // 3E
+    op(Ccf, [NONE, NONE], 1, 1, "-00C"), // 3F
+    op(Ld, [Reg8(R8::B), Reg8(R8::B)], 1, 1, "----"), // 40
+    op(Ld, [Reg8(R8::B), Reg8(R8::C)], 1, 1, "----"), // 41
+    op(Ld, [Reg8(R8::B), Reg8(R8::D)], 1, 1, "----"), // 42
+    op(Ld, [Reg8(R8::B), Reg8(R8::E)], 1, 1, "----"), // 43
+    op(Ld, [Reg8(R8::B), Reg8(R8::H)], 1, 1, "----"), // 44
+    op(Ld, [Reg8(R8::B), Reg8(R8::L)], 1, 1, "----"), // 45
+    op(Ld, [Reg8(R8::B), Reg8(R8::HlInd)], 1, 2, "----"), // 46
+    op(Ld, [Reg8(R8::B), Reg8(R8::A)], 1, 1, "----"), // 47
+    op(Ld, [Reg8(R8::C), Reg8(R8::B)], 1, 1, "----"), // 48
+    op(Ld, [Reg8(R8::C), Reg8(R8::C)], 1, 1, "----"), // 49
+    op(Ld, [Reg8(R8::C), Reg8(R8::D)], 1, 1, "----"), // 4A
+    op(Ld, [Reg8(R8::C), Reg8(R8::E)], 1, 1, "----"), // 4B
+    op(Ld, [Reg8(R8::C), Reg8(R8::H)], 1, 1, "----"), // 4C
+    op(Ld, [Reg8(R8::C), Reg8(R8::L)], 1, 1, "----"), // 4D
+    op(Ld, [Reg8(R8::C), Reg8(R8::HlInd)], 1, 2, "----"), // 4E
+    op(Ld, [Reg8(R8::C), Reg8(R8::A)], 1, 1, "----"), // 4F
+    op(Ld, [Reg8(R8::D), Reg8(R8::B)], 1, 1, "----"), // 50
+    op(Ld, [Reg8(R8::D), Reg8(R8::C)], 1, 1, "----"), // 51
+    op(Ld, [Reg8(R8::D), Reg8(R8::D)], 1, 1, "----"), // 52
+    op(Ld, [Reg8(R8::D), Reg8(R8::E)], 1, 1, "----"), // 53
+    op(Ld, [Reg8(R8::D), Reg8(R8::H)], 1, 1, "----"), // 54
+    op(Ld, [Reg8(R8::D), Reg8(R8::L)], 1, 1, "----"), // 55
+    op(Ld, [Reg8(R8::D), Reg8(R8::HlInd)], 1, 2, "----"), // 56
+    op(Ld, [Reg8(R8::D), Reg8(R8::A)], 1, 1, "----"), // 57
+    op(Ld, [Reg8(R8::E), Reg8(R8::B)], 1, 1, "----"), // 58
+    op(Ld, [Reg8(R8::E), Reg8(R8::C)], 1, 1, "----"), // 59
+    op(Ld, [Reg8(R8::E), Reg8(R8::D)], 1, 1, "----"), // 5A
+    op(Ld, [Reg8(R8::E), Reg8(R8::E)], 1, 1, "----"), // 5B
+    op(Ld, [Reg8(R8::E), Reg8(R8::H)], 1, 1, "----"), // 5C
+    op(Ld, [Reg8(R8::E), Reg8(R8::L)], 1, 1, "----"), // 5D
+    op(Ld, [Reg8(R8::E), Reg8(R8::HlInd)], 1, 2, "----"), // 5E
+    op(Ld, [Reg8(R8::E), Reg8(R8::A)], 1, 1, "----"), // 5F
+    op(Ld, [Reg8(R8::H), Reg8(R8::B)], 1, 1, "----"), // 60
+    op(Ld, [Reg8(R8::H), Reg8(R8::C)], 1, 1, "----"), // 61
+    op(Ld, [Reg8(R8::H), Reg8(R8::D)], 1, 1, "----"), // 62
+    op(Ld, [Reg8(R8::H), Reg8(R8::E)], 1, 1, "----"), // 63
+    op(Ld, [Reg8(R8::H), Reg8(R8::H)], 1, 1, "----"), // 64
+    op(Ld, [Reg8(R8::H), Reg8(R8::L)], 1, 1, "----"), // 65
+    op(Ld, [Reg8(R8::H), Reg8(R8::HlInd)], 1, 2, "----"), // 66
+    op(Ld, [Reg8(R8::H), Reg8(R8::A)], 1, 1, "----"), // 67
+    op(Ld, [Reg8(R8::L), Reg8(R8::B)], 1, 1, "----"), // 68
+    op(Ld, [Reg8(R8::L), Reg8(R8::C)], 1, 1, "----"), // 69
+    op(Ld, [Reg8(R8::L), Reg8(R8::D)], 1, 1, "----"), // 6A
+    op(Ld, [Reg8(R8::L), Reg8(R8::E)], 1, 1, "----"), // 6B
+    op(Ld, [Reg8(R8::L), Reg8(R8::H)], 1, 1, "----"), // 6C
+    op(Ld, [Reg8(R8::L), Reg8(R8::L)], 1, 1, "----"), // 6D
+    op(Ld, [Reg8(R8::L), Reg8(R8::HlInd)], 1, 2, "----"), // 6E
+    op(Ld, [Reg8(R8::L), Reg8(R8::A)], 1, 1, "----"), // 6F
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::B)], 1, 2, "----"), // 70
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::C)], 1, 2, "----"), // 71
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::D)], 1, 2, "----"), // 72
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::E)], 1, 2, "----"), // 73
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::H)], 1, 2, "----"), // 74
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::L)], 1, 2, "----"), // 75
+    op(Halt, [NONE, NONE], 1, 1, "----"), // 76
+    op(Ld, [Reg8(R8::HlInd), Reg8(R8::A)], 1, 2, "----"), // 77
+    op(Ld, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "----"), // 78
+    op(Ld, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "----"), // 79
+    op(Ld, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "----"), // 7A
+    op(Ld, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "----"), // 7B
+    op(Ld, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "----"), // 7C
+    op(Ld, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "----"), // 7D
+    op(Ld, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "----"), // 7E
+    op(Ld, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "----"), // 7F
+    op(Add, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z0HC"), // 80
+    op(Add, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z0HC"), // 81
+    op(Add, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z0HC"), // 82
+    op(Add, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z0HC"), // 83
+    op(Add, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z0HC"), // 84
+    op(Add, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z0HC"), // 85
+    op(Add, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z0HC"), // 86
+    op(Add, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z0HC"), // 87
+    op(Adc, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z0HC"), // 88
+    op(Adc, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z0HC"), // 89
+    op(Adc, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z0HC"), // 8A
+    op(Adc, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z0HC"), // 8B
+    op(Adc, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z0HC"), // 8C
+    op(Adc, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z0HC"), // 8D
+    op(Adc, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z0HC"), // 8E
+    op(Adc, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z0HC"), // 8F
+    op(Sub, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z1HC"), // 90
+    op(Sub, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z1HC"), // 91
+    op(Sub, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z1HC"), // 92
+    op(Sub, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z1HC"), // 93
+    op(Sub, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z1HC"), // 94
+    op(Sub, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z1HC"), // 95
+    op(Sub, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z1HC"), // 96
+    op(Sub, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z1HC"), // 97
+    op(Sbc, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z1HC"), // 98
+    op(Sbc, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z1HC"), // 99
+    op(Sbc, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z1HC"), // 9A
+    op(Sbc, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z1HC"), // 9B
+    op(Sbc, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z1HC"), // 9C
+    op(Sbc, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z1HC"), // 9D
+    op(Sbc, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z1HC"), // 9E
+    op(Sbc, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z1HC"), // 9F
+    op(And, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z010"), // A0
+    op(And, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z010"), // A1
+    op(And, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z010"), // A2
+    op(And, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z010"), // A3
+    op(And, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z010"), // A4
+    op(And, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z010"), // A5
+    op(And, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z010"), // A6
+    op(And, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z010"), // A7
+    op(Xor, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z000"), // A8
+    op(Xor, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z000"), // A9
+    op(Xor, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z000"), // AA
+    op(Xor, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z000"), // AB
+    op(Xor, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z000"), // AC
+    op(Xor, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z000"), // AD
+    op(Xor, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z000"), // AE
+    op(Xor, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z000"), // AF
+    op(Or, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z000"), // B0
+    op(Or, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z000"), // B1
+    op(Or, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z000"), // B2
+    op(Or, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z000"), // B3
+    op(Or, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z000"), // B4
+    op(Or, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z000"), // B5
+    op(Or, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z000"), // B6
+    op(Or, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z000"), // B7
+    op(Cp, [Reg8(R8::A), Reg8(R8::B)], 1, 1, "Z1HC"), // B8
+    op(Cp, [Reg8(R8::A), Reg8(R8::C)], 1, 1, "Z1HC"), // B9
+    op(Cp, [Reg8(R8::A), Reg8(R8::D)], 1, 1, "Z1HC"), // BA
+    op(Cp, [Reg8(R8::A), Reg8(R8::E)], 1, 1, "Z1HC"), // BB
+    op(Cp, [Reg8(R8::A), Reg8(R8::H)], 1, 1, "Z1HC"), // BC
+    op(Cp, [Reg8(R8::A), Reg8(R8::L)], 1, 1, "Z1HC"), // BD
+    op(Cp, [Reg8(R8::A), Reg8(R8::HlInd)], 1, 2, "Z1HC"), // BE
+    op(Cp, [Reg8(R8::A), Reg8(R8::A)], 1, 1, "Z1HC"), // BF
+    branch(Ret, [Cond(Condition::Nz), NONE], 1, 5, 2), // C0
+    op(Pop, [Reg16(R16::Bc), NONE], 1, 3, "----"), // C1
+    branch(Jp, [Cond(Condition::Nz), Imm16], 3, 4, 3), // C2
+    op(Jp, [Imm16, NONE], 3, 4, "----"), // C3
+    branch(Call, [Cond(Condition::Nz), Imm16], 3, 6, 3), // C4
+    op(Push, [Reg16(R16::Bc), NONE], 1, 4, "----"), // C5
+    op(Add, [Reg8(R8::A), Imm8], 2, 2, "Z0HC"), // C6
+    op(Rst, [Vector(0x00), NONE], 1, 4, "----"), // C7
+    branch(Ret, [Cond(Condition::Z), NONE], 1, 5, 2), // C8
+    op(Ret, [NONE, NONE], 1, 4, "----"), // C9
+    branch(Jp, [Cond(Condition::Z), Imm16], 3, 4, 3), // CA
+    op(Prefix, [NONE, NONE], 1, 1, "----"), // CB
+    branch(Call, [Cond(Condition::Z), Imm16], 3, 6, 3), // CC
+    op(Call, [Imm16, NONE], 3, 6, "----"), // CD
+    op(Adc, [Reg8(R8::A), Imm8], 2, 2, "Z0HC"), // CE
+    op(Rst, [Vector(0x08), NONE], 1, 4, "----"), // CF
+    branch(Ret, [Cond(Condition::Nc), NONE], 1, 5, 2), // D0
+    op(Pop, [Reg16(R16::De), NONE], 1, 3, "----"), // D1
+    branch(Jp, [Cond(Condition::Nc), Imm16], 3, 4, 3), // D2
+    ILLEGAL, // D3
+    branch(Call, [Cond(Condition::Nc), Imm16], 3, 6, 3), // D4
+    op(Push, [Reg16(R16::De), NONE], 1, 4, "----"), // D5
+    op(Sub, [Reg8(R8::A), Imm8], 2, 2, "Z1HC"), // D6
+    op(Rst, [Vector(0x10), NONE], 1, 4, "----"), // D7
+    branch(Ret, [Cond(Condition::C), NONE], 1, 5, 2), // D8
+    op(Reti, [NONE, NONE], 1, 4, "----"), // D9
+    branch(Jp, [Cond(Condition::C), Imm16], 3, 4, 3), // DA
+    ILLEGAL, // DB
+    branch(Call, [Cond(Condition::C), Imm16], 3, 6, 3), // DC
+    ILLEGAL, // DD
+    op(Sbc, [Reg8(R8::A), Imm8], 2, 2, "Z1HC"), // DE
+    op(Rst, [Vector(0x18), NONE], 1, 4, "----"), // DF
+    op(Ldh, [HighImm8, Reg8(R8::A)], 2, 3, "----"), // E0
+    op(Pop, [Reg16(R16::Hl), NONE], 1, 3, "----"), // E1
+    op(Ldh, [HighC, Reg8(R8::A)], 1, 2, "----"), // E2
+    ILLEGAL, // E3
+    ILLEGAL, // E4
+    op(Push, [Reg16(R16::Hl), NONE], 1, 4, "----"), // E5
+    op(And, [Reg8(R8::A), Imm8], 2, 2, "Z010"), // E6
+    op(Rst, [Vector(0x20), NONE], 1, 4, "----"), // E7
+    op(Add, [Reg16(R16::Sp), Offset], 2, 4, "00HC"), // E8
+    op(Jp, [Reg16(R16::Hl), NONE], 1, 1, "----"), // E9
+    op(Ld, [Direct, Reg8(R8::A)], 3, 4, "----"), // EA
+    ILLEGAL, // EB
+    ILLEGAL, // EC
+    ILLEGAL, // ED
+    op(Xor, [Reg8(R8::A), Imm8], 2, 2, "Z000"), // EE
+    op(Rst, [Vector(0x28), NONE], 1, 4, "----"), // EF
+    op(Ldh, [Reg8(R8::A), HighImm8], 2, 3, "----"), // F0
+    op(Pop, [Reg16(R16::Af), NONE], 1, 3, "ZNHC"), // F1
+    op(Ldh, [Reg8(R8::A), HighC], 1, 2, "----"), // F2
+    op(Di, [NONE, NONE], 1, 1, "----"), // F3
+    ILLEGAL, // F4
+    op(Push, [Reg16(R16::Af), NONE], 1, 4, "----"), // F5
+    op(Or, [Reg8(R8::A), Imm8], 2, 2, "Z000"), // F6
+    op(Rst, [Vector(0x30), NONE], 1, 4, "----"), // F7
+    op(Ld, [Reg16(R16::Hl), SpOffset], 2, 3, "00HC"), // F8
+    op(Ld, [Reg16(R16::Sp), Reg16(R16::Hl)], 1, 2, "----"), // F9
+    op(Ld, [Reg8(R8::A), Direct], 3, 4, "----"), // FA
+    op(Ei, [NONE, NONE], 1, 1, "----"), // FB
+    ILLEGAL, // FC
+    ILLEGAL, // FD
+    op(Cp, [Reg8(R8::A), Imm8], 2, 2, "Z1HC"), // FE
+    op(Rst, [Vector(0x38), NONE], 1, 4, "----"), // FF
+];
+
+const CB_MNEMONICS: [Mnemonic; 8] = [Rlc, Rrc, Rl, Rr, Sla, Sra, Swap, Srl];
+const CB_FLAGS: [&str; 8] = [
+    "Z00C", "Z00C", "Z00C", "Z00C", "Z00C", "Z00C", "Z000", "Z00C",
+];
+const R8_OPERANDS: [R8; 8] = [R8::B, R8::C, R8::D, R8::E, R8::H, R8::L, R8::HlInd, R8::A];
+
+// The CB opcodes are regular enough to generate: two bits of operation, three
+// of bit number or shift kind, three of operand.
+pub const CB_PREFIXED: [Opcode; 256] = {
+    let mut table = [ILLEGAL; 256];
+    let mut opcode = 0;
+    while opcode < 256 {
+        let y = (opcode >> 3) & 0x07;
+        let r = R8_OPERANDS[opcode & 0x07];
+        let memory = matches!(r, R8::HlInd);
+        table[opcode] = match opcode >> 6 {
+            0 => op(
+                CB_MNEMONICS[y],
+                [Reg8(r), NONE],
+                2,
+                if memory { 4 } else { 2 },
+                CB_FLAGS[y],
+            ),
+            1 => op(
+                Bit,
+                [Operand::Bit(y as u8), Reg8(r)],
+                2,
+                if memory { 3 } else { 2 },
+                "Z01-",
+            ),
+            2 => op(
+                Res,
+                [Operand::Bit(y as u8), Reg8(r)],
+                2,
+                if memory { 4 } else { 2 },
+                "----",
+            ),
+            _ => op(
+                Set,
+                [Operand::Bit(y as u8), Reg8(r)],
+                2,
+                if memory { 4 } else { 2 },
+                "----",
+            ),
+        };
+        opcode += 1;
+    }
+    table
+};
@@ -1,50 +1,47 @@
+use crate::bus;
 use crate::cpu;
-use core::panic;
+use crate::cpu::opcodes::{self, Condition, Operand, R16, R8};
 use std::fmt;
 
-enum JmpCondition {
-    NonZero,
-}
-
-impl fmt::Display for JmpCondition {
+impl fmt::Display for Condition {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let symbol = match self {
-            JmpCondition::NonZero => "NZ",
+            Condition::Nz => "NZ",
+            Condition::Z => "Z",
+            Condition::Nc => "NC",
+            Condition::C => "C",
         };
-        write!(f, "${}", symbol)
+        write!(f, "{}", symbol)
     }
 }
 
-enum Reg8 {
-    A,
-    B,
-    C,
-    D,
-    E,
-    H,
-    L,
-}
-
-impl fmt::Display for Reg8 {
+impl fmt::Display for R8 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let symbol = match self {
-            Reg8::A => "A",
-            Reg8::B => "B",
-            Reg8::C => "C",
-            Reg8::D => "D",
-            Reg8::E => "E",
-            Reg8::H => "H",
-            Reg8::L => "L",
+            R8::A => "A",
+            R8::B => "B",
+            R8::C => "C",
+            R8::D => "D",
+            R8::E => "E",
+            R8::H => "H",
+            R8::L => "L",
+            R8::HlInd => "(HL)",
         };
         write!(f, "{}", symbol)
     }
 }
 
-enum Reg16 {
-    BC,
-    DE,
-    HL,
-    SP,
+impl fmt::Display for R16 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            R16::Bc => "BC",
+            R16::De => "DE",
+            R16::Hl => "HL",
+            R16::Sp => "SP",
+            R16::Af => "AF",
+        };
+        write!(f, "{}", symbol)
+    }
 }
 
 struct Imm8(u8);
@@ -63,131 +60,85 @@ impl fmt::Display for Imm16 {
     }
 }
 
-impl fmt::Display for Reg16 {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let symbol = match self {
-            Reg16::BC => "BC",
-            Reg16::DE => "DE",
-            Reg16::HL => "HL",
-            Reg16::SP => "SP",
-        };
-        write!(f, "{}", symbol)
-    }
-}
-
-pub fn disassemble(stream: &mut [u8]) {
+// Prints instructions until the stream runs out.
+pub fn disassemble(stream: &[u8]) {
     let mut offset: usize = 0;
-    loop {
-        let soff = &stream[offset..];
-
-        let instruction = cpu::Instruction::decode(soff[0]);
-        let (s, n) = match instruction {
-            cpu::Instruction::Call => disasm_call(soff),
-            cpu::Instruction::CBPrefix => disasm_cb_prefixed(soff),
-            cpu::Instruction::JmpRelIfNonZero => disasm_jmpr(soff, JmpCondition::NonZero),
-            cpu::Instruction::IncC => disasm_inc8(soff, Reg8::C),
-            cpu::Instruction::IncDE => disasm_inc16(soff, Reg16::DE),
-            cpu::Instruction::LoadAPtrDE => disasm_load_reg8_reg16ptr(soff, Reg8::A, Reg16::DE),
-            cpu::Instruction::LoadAE => disasm_load_reg8_reg8(soff, Reg8::A, Reg8::E),
-            cpu::Instruction::LoadAImm8 => disasm_load_reg8_imm8(soff, Reg8::A),
-            cpu::Instruction::LoadBImm8 => disasm_load_reg8_imm8(soff, Reg8::B),
-            cpu::Instruction::LoadCImm8 => disasm_load_reg8_imm8(soff, Reg8::C),
-            cpu::Instruction::LoadDImm8 => disasm_load_reg8_imm8(soff, Reg8::D),
-            cpu::Instruction::LoadEImm8 => disasm_load_reg8_imm8(soff, Reg8::E),
-            cpu::Instruction::LoadHImm8 => disasm_load_reg8_imm8(soff, Reg8::H),
-            cpu::Instruction::LoadLImm8 => disasm_load_reg8_imm8(soff, Reg8::L),
-            cpu::Instruction::LoadBCImm16 => disasm_load_reg16_imm16(soff, Reg16::BC),
-            cpu::Instruction::LoadDEImm16 => disasm_load_reg16_imm16(soff, Reg16::DE),
-            cpu::Instruction::LoadHLImm16 => disasm_load_reg16_imm16(soff, Reg16::HL),
-            cpu::Instruction::LoadSPImm16 => disasm_load_reg16_imm16(soff, Reg16::SP),
-            cpu::Instruction::StoreAToAddrCHigh => {
-                disasm_store_reg8ptr_reg8(soff, Reg8::A, Reg8::C)
-            }
-            cpu::Instruction::StoreAToAddrImm8High => disasm_store_imm8ptr_reg8(soff, Reg8::A),
-            cpu::Instruction::StoreAToAddrHL => {
-                disasm_store_reg16ptr_reg8(soff, Reg8::A, Reg16::HL)
-            }
-            cpu::Instruction::StoreAToAddrHLDec => {
-                disasm_store_reg16ptr_reg8_dec(soff, Reg8::A, Reg16::HL)
-            }
-            cpu::Instruction::XorA8 => disassemble_xor8(soff, Reg8::A),
-            _ => panic!("disassemble: unexpected instruction {:?}", instruction),
-        };
+    while let Some((s, n)) = disassemble_one(&stream[offset..]) {
         offset += n;
         println!("{}", &s);
     }
 }
 
-fn disasm_call(stream: &[u8]) -> (String, usize) {
-    return (format!("CALL {}", Imm16(stream[1], stream[2])), 3);
-}
-
-fn disasm_cb_prefixed(stream: &[u8]) -> (String, usize) {
-    let stream_at_offset = &stream[1..];
-
-    let instruction = cpu::Instruction::decode(stream_at_offset[0]);
-    let (s, n) = match instruction {
-        cpu::Instruction::BitTest7H => disassemble_test_bit_8(stream_at_offset, Reg8::H, 7),
-        _ => panic!(":("),
+// Returns the instruction at the start of `stream` and its length in bytes,
+// or None if the stream ends first. Illegal opcodes come out as data.
+pub fn disassemble_one(stream: &[u8]) -> Option<(String, usize)> {
+    let opcode = *stream.first()?;
+    let info = match opcode {
+        0xCB => opcodes::lookup_cb(*stream.get(1)?),
+        _ => opcodes::lookup(opcode),
     };
-    (s, n + 1)
-}
-
-fn disassemble_test_bit_8(_stream: &[u8], reg: Reg8, bit: i64) -> (String, usize) {
-    return (format!("BIT {},{}", bit, reg), 1);
-}
-// fn disassemble_dec_8(stream: &[u8], reg: Reg8) -> (String, usize) {
-//     return (format!("INC {}", reg), 1);
-// }
-
-// fn disassemble_dec_16(stream: &[u8], reg: Reg16) -> (String, usize) {
-//     return (format!("DEC {}", reg), 1);
-// }
-
-fn disasm_inc8(_stream: &[u8], reg: Reg8) -> (String, usize) {
-    return (format!("INC {}", reg), 1);
-}
-
-fn disasm_inc16(_stream: &[u8], reg: Reg16) -> (String, usize) {
-    return (format!("INC {}", reg), 1);
-}
-
-fn disasm_jmpr(stream: &[u8], condition: JmpCondition) -> (String, usize) {
-    return (format!("JR {},{}", condition, Imm8(stream[1])), 2);
-}
-
-fn disasm_load_reg8_reg16ptr(_stream: &[u8], reg_dest: Reg8, reg_addr: Reg16) -> (String, usize) {
-    return (format!("LD {},({})", reg_dest, reg_addr), 1);
-}
-
-fn disasm_load_reg8_reg8(stream: &[u8], reg_dest: Reg8, reg_src: Reg8) -> (String, usize) {
-    return (format!("LD {},{}", reg_dest, reg_src), 1);
-}
-
-fn disasm_load_reg8_imm8(stream: &[u8], reg: Reg8) -> (String, usize) {
-    return (format!("LD {},{}", reg, Imm8(stream[1])), 2);
-}
-
-fn disasm_load_reg16_imm16(stream: &[u8], reg: Reg16) -> (String, usize) {
-    return (format!("LD {},{}", reg, Imm16(stream[1], stream[2])), 3);
-}
-
-fn disasm_store_imm8ptr_reg8(stream: &[u8], reg8_value: Reg8) -> (String, usize) {
-    return (format!("LD ($FF00+{}),{}", Imm8(stream[1]), reg8_value), 2);
-}
-
-fn disasm_store_reg8ptr_reg8(_stream: &[u8], reg8_value: Reg8, reg8_addr: Reg8) -> (String, usize) {
-    return (format!("LD ($FF00+{}),{}", reg8_addr, reg8_value), 1);
-}
-
-fn disasm_store_reg16ptr_reg8(_stream: &[u8], reg8: Reg8, reg16: Reg16) -> (String, usize) {
-    return (format!("LD ({}),{}", reg16, reg8), 1);
-}
-
-fn disasm_store_reg16ptr_reg8_dec(_stream: &[u8], reg8: Reg8, reg16: Reg16) -> (String, usize) {
-    return (format!("LD ({}-),{}", reg16, reg8), 1);
-}
+    if info.is_illegal() {
+        return Some((format!("DB {}", Imm8(opcode)), 1));
+    }
 
-fn disassemble_xor8(_stream: &[u8], reg: Reg8) -> (String, usize) {
-    return (format!("XOR {}", reg), 1);
+    let length = info.length as usize;
+    let bytes = stream.get(..length)?;
+    let operands: Vec<String> = info
+        .operands
+        .iter()
+        .filter(|operand| **operand != Operand::None)
+        .map(|operand| format_operand(*operand, bytes))
+        .collect();
+    let s = if operands.is_empty() {
+        info.mnemonic.to_string()
+    } else {
+        format!("{} {}", info.mnemonic, operands.join(","))
+    };
+    Some((s, length))
+}
+
+// One line describing the instruction the CPU is about to run and the
+// registers it starts with. Its opcode is already in IR, so only the operand
+// bytes come from memory. They're peeked without catching up the rest of the
+// console, so tracing doesn't change how a run goes.
+pub fn trace(cpu: &cpu::Cpu<bus::Bus>) -> String {
+    let address = cpu.pc().wrapping_sub(1);
+    let mut bytes = [cpu.ir(), 0, 0];
+    for (i, byte) in bytes.iter_mut().enumerate().skip(1) {
+        let operand = address.wrapping_add(i as u16);
+        *byte = cpu.bus().read(operand).unwrap_or(0xFF);
+    }
+    let (instruction, _) = disassemble_one(&bytes).expect("three bytes hold any instruction");
+    format!(
+        "{:04x}: {:<20} af={:04x} bc={:04x} de={:04x} hl={:04x} sp={:04x} cycle={}",
+        address,
+        instruction,
+        cpu.af(),
+        cpu.bc(),
+        cpu.de(),
+        cpu.hl(),
+        cpu.sp(),
+        cpu.ticks()
+    )
+}
+
+// `bytes` is the whole instruction, so immediates start at index 1.
+fn format_operand(operand: Operand, bytes: &[u8]) -> String {
+    match operand {
+        Operand::None => String::new(),
+        Operand::Reg8(reg) => reg.to_string(),
+        Operand::Reg16(reg) => reg.to_string(),
+        Operand::Ind(reg) => format!("({})", reg),
+        Operand::HlInc => "(HL+)".to_string(),
+        Operand::HlDec => "(HL-)".to_string(),
+        Operand::HighC => "($FF00+C)".to_string(),
+        Operand::HighImm8 => format!("($FF00+{})", Imm8(bytes[1])),
+        Operand::Imm8 | Operand::Offset => Imm8(bytes[1]).to_string(),
+        Operand::Imm16 => Imm16(bytes[1], bytes[2]).to_string(),
+        Operand::Direct => format!("({})", Imm16(bytes[1], bytes[2])),
+        Operand::SpOffset => format!("SP+{}", Imm8(bytes[1])),
+        Operand::Cond(condition) => condition.to_string(),
+        Operand::Bit(bit) => bit.to_string(),
+        Operand::Vector(vector) => Imm8(vector).to_string(),
+    }
 }
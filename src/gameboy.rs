@@ -0,0 +1,276 @@
+use crate::apu;
+use crate::bus;
+use crate::cart;
+use crate::compat;
+use crate::cpu;
+use crate::joypad;
+use crate::model;
+use crate::ppu;
+use crate::state;
+use crate::timer;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Upper bound on a frame: 154 lines of 114 M-cycles. It's also how long a
+// frame lasts while the LCD is off and no VBlank comes.
+const FRAME_CYCLES: u64 = 154 * 114;
+
+// I/O registers as the boot ROM leaves them, in the order they're written:
+// the APU has to be powered before its other registers take writes.
+const POST_BOOT_IO: [(u16, u8); 8] = [
+    (0xFF26, 0x80), // NR52
+    (0xFF24, 0x77), // NR50
+    (0xFF25, 0xF3), // NR51
+    (0xFF40, 0x91), // LCDC
+    (0xFF47, 0xFC), // BGP
+    (0xFF48, 0xFF), // OBP0
+    (0xFF49, 0xFF), // OBP1
+    (0xFF0F, 0x01), // IF
+];
+
+// The whole console. Everything hangs off the CPU's bus and is owned by it,
+// so a frontend or debugger can look at any component between steps.
+pub struct GameBoy {
+    cpu: cpu::Cpu<bus::Bus>,
+    model: model::Model,
+    palette: compat::Selection,
+    audio: Option<Rc<RefCell<Vec<i16>>>>,
+}
+
+// Collects the mixer's output until the frontend takes it.
+struct AudioBuffer(Rc<RefCell<Vec<i16>>>);
+
+impl apu::AudioSink for AudioBuffer {
+    fn push_samples(&mut self, samples: &[i16]) {
+        self.0.borrow_mut().extend_from_slice(samples);
+    }
+}
+
+// Finishes the recordings with everything up to the moment the console
+// goes away.
+impl Drop for GameBoy {
+    fn drop(&mut self) {
+        self.stop_recording();
+    }
+}
+
+impl Default for GameBoy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl GameBoy {
+    pub fn new() -> Self {
+        Self::with_model(model::Model::Dmg)
+    }
+
+    // CGB models only differ in how they colorize DMG games; CGB mode
+    // itself isn't emulated.
+    pub fn with_model(model: model::Model) -> Self {
+        let mut bus = bus::Bus::new();
+        bus.apu_mut().set_model(model);
+        if model == model::Model::Sgb {
+            bus.enable_sgb();
+        }
+        GameBoy {
+            cpu: cpu::Cpu::new(bus),
+            model,
+            palette: compat::Selection::Auto,
+            audio: None,
+        }
+    }
+
+    pub fn model(&self) -> model::Model {
+        self.model
+    }
+
+    // Plain ROMs and MBC1, MBC3 and MBC5 carts are mapped in full, though
+    // the MBC3's clock doesn't run. Carts with other controllers only see
+    // their first 32 KiB of ROM and have no RAM.
+    pub fn load_rom(&mut self, rom: Vec<u8>) {
+        self.load_cart(cart::Cart::new(rom));
+    }
+
+    pub fn load_cart(&mut self, cart: cart::Cart) {
+        let palette = compat::palette(self.model, &cart, self.palette);
+        self.bus_mut().ppu_mut().set_palette(palette);
+        self.bus_mut().load_cart(cart);
+    }
+
+    // How a CGB colorizes DMG carts, as picked by holding keys while its
+    // boot ROM runs. Takes effect on the cart already loaded, if any.
+    pub fn set_palette(&mut self, selection: compat::Selection) {
+        self.palette = selection;
+        let palette = match self.cart() {
+            Some(cart) => compat::palette(self.model, cart, selection),
+            None => return,
+        };
+        self.bus_mut().ppu_mut().set_palette(palette);
+    }
+
+    pub fn load_boot_rom(&mut self, rom: Vec<u8>) {
+        self.bus_mut().load_boot_rom(rom);
+    }
+
+    // Starts the cart at 0x0100 with the registers the model's boot ROM
+    // would have left behind, without running it.
+    pub fn skip_boot(&mut self) {
+        let (af, bc, de, hl) = match self.model {
+            model::Model::Dmg => (0x01B0, 0x0013, 0x00D8, 0x014D),
+            model::Model::Mgb => (0xFFB0, 0x0013, 0x00D8, 0x014D),
+            model::Model::Sgb => (0x0100, 0x0014, 0x0000, 0xC060),
+            model::Model::Cgb => (0x1180, 0x0000, 0x0008, 0x007C),
+            model::Model::Agb => (0x1100, 0x0100, 0x0008, 0x007C),
+        };
+        let bus = self.bus_mut();
+        bus.disable_boot_rom();
+        for (address, value) in POST_BOOT_IO {
+            bus.write(address, value)
+                .expect("post-boot register is mapped");
+        }
+        self.cpu.set_af(af);
+        self.cpu.set_bc(bc);
+        self.cpu.set_de(de);
+        self.cpu.set_hl(hl);
+        self.cpu.set_sp(0xFFFE);
+        self.cpu.set_pc(0x0100);
+    }
+
+    // Runs one instruction, or an interrupt dispatch, and returns the
+    // M-cycles it took.
+    pub fn step(&mut self) -> Result<u64, cpu::EmuError> {
+        self.cpu.step()
+    }
+
+    // Runs until the PPU finishes a frame, and returns the M-cycles it took.
+    pub fn run_frame(&mut self) -> Result<u64, cpu::EmuError> {
+        self.run_frame_with(|_| {})
+    }
+
+    // Like `run_frame`, but lets a tracer or debugger look at the console
+    // before every step. Everything is caught up once the frame is done.
+    pub fn run_frame_with(
+        &mut self,
+        mut before_step: impl FnMut(&mut GameBoy),
+    ) -> Result<u64, cpu::EmuError> {
+        let frame = self.bus().frames();
+        let mut cycles = 0;
+        while self.bus().frames() == frame && cycles < FRAME_CYCLES {
+            before_step(self);
+            cycles += self.step()?;
+        }
+        self.sync();
+        Ok(cycles)
+    }
+
+    // The last frame drawn, SCREEN_WIDTH by SCREEN_HEIGHT pixels of RGB555.
+    pub fn framebuffer(&mut self) -> &[u16] {
+        self.ppu().pixels()
+    }
+
+    pub fn set_button(&mut self, button: joypad::Button, pressed: bool) {
+        self.bus_mut().joypad_mut().set_button(0, button, pressed);
+    }
+
+    // Starts mixing audio into a buffer that `take_audio` empties. This
+    // replaces any sink set on the APU directly.
+    pub fn enable_audio(&mut self, sample_rate: u32) {
+        let buffer = Rc::new(RefCell::new(Vec::new()));
+        let sink = AudioBuffer(Rc::clone(&buffer));
+        self.apu_mut().set_sink(Box::new(sink), sample_rate);
+        self.audio = Some(buffer);
+    }
+
+    // Interleaved stereo samples mixed since the last call.
+    pub fn take_audio(&mut self) -> Vec<i16> {
+        self.flush_audio();
+        match &self.audio {
+            Some(buffer) => buffer.take(),
+            None => Vec::new(),
+        }
+    }
+
+    // The mixer hands samples over in batches. This pushes out the last,
+    // partial batch, which would otherwise be lost when the emulator exits.
+    pub fn flush_audio(&mut self) {
+        self.apu_mut().flush_audio();
+    }
+
+    // Lets go of the WAV, VGM or other recorders attached to the APU, which
+    // is what finishes their files.
+    pub fn stop_recording(&mut self) {
+        self.apu_mut().stop_recording();
+    }
+
+    pub fn save_state(&mut self) -> Vec<u8> {
+        let mut state = state::Writer::new();
+        self.cpu.save_state(&mut state);
+        self.bus_mut().save_state(&mut state);
+        state.finish()
+    }
+
+    // Restores a state saved with the same cart loaded. A state that fails
+    // to load leaves the console as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), state::StateError> {
+        let current = self.save_state();
+        self.restore(data).inspect_err(|_| {
+            self.restore(&current).expect("reloading the current state");
+        })
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), state::StateError> {
+        let mut state = state::Reader::new(data)?;
+        self.cpu.load_state(&mut state)?;
+        self.bus_mut().load_state(&mut state)?;
+        state.finish()
+    }
+
+    pub fn cpu(&self) -> &cpu::Cpu<bus::Bus> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut cpu::Cpu<bus::Bus> {
+        &mut self.cpu
+    }
+
+    // The timer, PPU and APU catch up lazily, when their registers are
+    // accessed or they have something to do. This brings them up to date
+    // for looking at between steps.
+    pub fn sync(&mut self) {
+        self.bus_mut().sync();
+    }
+
+    pub fn bus(&self) -> &bus::Bus {
+        self.cpu.bus()
+    }
+
+    pub fn bus_mut(&mut self) -> &mut bus::Bus {
+        self.cpu.bus_mut()
+    }
+
+    // These catch everything up first; the ones on the bus show the
+    // component as of the last sync.
+    pub fn ppu(&mut self) -> &ppu::Ppu {
+        self.sync();
+        self.bus().ppu()
+    }
+
+    pub fn apu(&mut self) -> &apu::Apu {
+        self.sync();
+        self.bus().apu()
+    }
+
+    pub fn apu_mut(&mut self) -> &mut apu::Apu {
+        self.bus_mut().apu_mut()
+    }
+
+    pub fn timer(&mut self) -> &timer::Timer {
+        self.sync();
+        self.bus().timer()
+    }
+
+    pub fn cart(&self) -> Option<&cart::Cart> {
+        self.bus().cart()
+    }
+}
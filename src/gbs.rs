@@ -0,0 +1,158 @@
+use crate::bus;
+use crate::cart;
+use std::fmt;
+
+const HEADER_LEN: usize = 0x70;
+
+// Where the player code lives in the synthetic cart. Rips load their data at
+// 0x0400 or above, so everything below that is free.
+const DRIVER: u16 = 0x0100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GbsError {
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadLoadAddress(u16),
+}
+
+impl fmt::Display for GbsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GbsError::TooShort => write!(f, "file too short for a GBS header"),
+            GbsError::BadMagic => write!(f, "not a GBS file"),
+            GbsError::UnsupportedVersion(version) => {
+                write!(f, "GBS version {} isn't supported", version)
+            }
+            GbsError::BadLoadAddress(address) => {
+                write!(f, "load address {:#06x} is outside 0x0400-0x7FFF", address)
+            }
+        }
+    }
+}
+
+pub struct Gbs {
+    pub song_count: u8,
+    pub first_song: u8,
+    pub load_address: u16,
+    pub init_address: u16,
+    pub play_address: u16,
+    pub stack_pointer: u16,
+    pub timer_modulo: u8,
+    pub timer_control: u8,
+    pub title: String,
+    pub author: String,
+    pub copyright: String,
+    data: Vec<u8>,
+}
+
+impl Gbs {
+    pub fn parse(bytes: &[u8]) -> Result<Self, GbsError> {
+        if bytes.len() < HEADER_LEN {
+            return Err(GbsError::TooShort);
+        }
+        if &bytes[0..3] != b"GBS" {
+            return Err(GbsError::BadMagic);
+        }
+        if bytes[0x03] != 1 {
+            return Err(GbsError::UnsupportedVersion(bytes[0x03]));
+        }
+
+        let word = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let text = |offset: usize| {
+            let field = &bytes[offset..offset + 32];
+            let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+            String::from_utf8_lossy(&field[..len]).into_owned()
+        };
+
+        let load_address = word(0x06);
+        if !(0x0400..0x8000).contains(&load_address) {
+            return Err(GbsError::BadLoadAddress(load_address));
+        }
+
+        Ok(Gbs {
+            song_count: bytes[0x04],
+            first_song: bytes[0x05],
+            load_address,
+            init_address: word(0x08),
+            play_address: word(0x0A),
+            stack_pointer: word(0x0C),
+            timer_modulo: bytes[0x0E],
+            timer_control: bytes[0x0F],
+            title: text(0x10),
+            author: text(0x30),
+            copyright: text(0x50),
+            data: bytes[HEADER_LEN..].to_vec(),
+        })
+    }
+
+    // Play is driven by the timer when TAC has the timer enabled, otherwise
+    // by VBlank.
+    pub fn uses_timer(&self) -> bool {
+        self.timer_control & 0x04 != 0
+    }
+
+    pub fn entry_point(&self) -> u16 {
+        DRIVER
+    }
+
+    // Builds a cart holding the rip at its load address, with a small
+    // driver that calls init for `song` (counted from 0) and then calls play
+    // from the interrupt handler. Rips expect the APU powered and the master
+    // volume and panning set the way the boot ROM leaves them before init
+    // runs, and RST vectors redirected to the load address.
+    pub fn cart(&self, song: u8) -> cart::Cart {
+        let load = self.load_address as usize;
+        let len = (load + self.data.len())
+            .next_multiple_of(0x4000)
+            .max(0x8000);
+        let mut rom = vec![0; len];
+        rom[load..load + self.data.len()].copy_from_slice(&self.data);
+
+        for vector in (0x00..0x40).step_by(8) {
+            let [lo, hi] = (self.load_address + vector as u16).to_le_bytes();
+            rom[vector..vector + 3].copy_from_slice(&[0xC3, lo, hi]);
+        }
+
+        let [play_lo, play_hi] = self.play_address.to_le_bytes();
+        let handler = [0xCD, play_lo, play_hi, 0xD9];
+        let (vector, interrupt) = if self.uses_timer() {
+            (0x50, bus::INT_TIMER)
+        } else {
+            (0x40, bus::INT_VBLANK)
+        };
+        rom[vector..vector + handler.len()].copy_from_slice(&handler);
+
+        let [sp_lo, sp_hi] = self.stack_pointer.to_le_bytes();
+        let [init_lo, init_hi] = self.init_address.to_le_bytes();
+        let driver: Vec<u8> = [
+            &[0xF3][..],                 // di
+            &[0x31, sp_lo, sp_hi],       // ld sp, stack_pointer
+            &[0x3E, self.timer_modulo],  // ld a, timer_modulo
+            &[0xE0, 0x06],               // ldh (TMA), a
+            &[0x3E, self.timer_control], // ld a, timer_control
+            &[0xE0, 0x07],               // ldh (TAC), a
+            &[0x3E, 0x80],               // ld a, 0x80
+            &[0xE0, 0x40],               // ldh (LCDC), a
+            &[0xE0, 0x26],               // ldh (NR52), a
+            &[0x3E, 0x77],               // ld a, 0x77
+            &[0xE0, 0x24],               // ldh (NR50), a
+            &[0x3E, 0xF3],               // ld a, 0xF3
+            &[0xE0, 0x25],               // ldh (NR51), a
+            &[0x3E, song],               // ld a, song
+            &[0xCD, init_lo, init_hi],   // call init
+            &[0x3E, interrupt],          // ld a, interrupt
+            &[0xE0, 0xFF],               // ldh (IE), a
+            &[0xAF],                     // xor a
+            &[0xE0, 0x0F],               // ldh (IF), a
+            &[0xFB],                     // ei
+            &[0x76],                     // halt
+            &[0x18, 0xFD],               // jr -3
+        ]
+        .concat();
+        let start = DRIVER as usize;
+        rom[start..start + driver.len()].copy_from_slice(&driver);
+
+        cart::Cart::gbs(rom)
+    }
+}
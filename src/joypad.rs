@@ -0,0 +1,141 @@
+use crate::bus;
+use crate::state;
+
+// Up to four controllers can be connected through the SGB multiplayer
+// adapter; everything else only ever uses the first.
+pub const PORTS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    // Bit in the combined state: directions in the low nibble and buttons in
+    // the high one, each in the order P10-P13 report them.
+    fn mask(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+pub struct Joypad {
+    // P14 (directions) and P15 (buttons) as last written; a line selects its
+    // group when it is low.
+    select: u8,
+    pressed: [u8; PORTS],
+    port: usize,
+    interrupt: bool,
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            select: 0x30,
+            pressed: [0; PORTS],
+            port: 0,
+            interrupt: false,
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    pub fn write(&mut self, value: u8) {
+        self.update(|joypad| joypad.select = value & 0x30);
+    }
+
+    pub fn select(&self) -> u8 {
+        self.select
+    }
+
+    // Changes which controller the register reports, for the SGB.
+    pub fn set_port(&mut self, port: usize) {
+        self.update(|joypad| joypad.port = port);
+    }
+
+    pub fn set_button(&mut self, port: usize, button: Button, pressed: bool) {
+        self.update(|joypad| {
+            if pressed {
+                joypad.pressed[port] |= button.mask();
+            } else {
+                joypad.pressed[port] &= !button.mask();
+            }
+        });
+    }
+
+    pub fn is_pressed(&self, port: usize, button: Button) -> bool {
+        self.pressed[port] & button.mask() != 0
+    }
+
+    pub fn release_all(&mut self) {
+        self.update(|joypad| joypad.pressed = [0; PORTS]);
+    }
+
+    pub fn interrupt_pending(&self) -> bool {
+        self.interrupt
+    }
+
+    pub fn tick(&mut self) -> u8 {
+        if std::mem::take(&mut self.interrupt) {
+            bus::INT_JOYPAD
+        } else {
+            0
+        }
+    }
+
+    // P10-P13, active low. With both groups selected a line is low if either
+    // button on it is pressed.
+    fn lines(&self) -> u8 {
+        let pressed = self.pressed[self.port];
+        let mut low = 0;
+        if self.select & 0x10 == 0 {
+            low |= pressed & 0x0F;
+        }
+        if self.select & 0x20 == 0 {
+            low |= pressed >> 4;
+        }
+        !low & 0x0F
+    }
+
+    // Any line going from high to low requests the joypad interrupt, whether
+    // it's caused by a press or by a change of selection.
+    fn update(&mut self, change: impl FnOnce(&mut Self)) {
+        let before = self.lines();
+        change(self);
+        if before & !self.lines() != 0 {
+            self.interrupt = true;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u8(self.select);
+        state.bytes(&self.pressed);
+        state.usize(self.port);
+        state.bool(self.interrupt);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.select = state.u8()?;
+        state.bytes(&mut self.pressed)?;
+        self.port = state.usize()?;
+        if self.port >= PORTS {
+            return Err(state::StateError::Invalid("joypad port"));
+        }
+        self.interrupt = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,31 @@
+// A Game Boy emulator core. `GameBoy` is the whole console; the modules
+// behind it are public for tools that need to reach further in, such as
+// debuggers, disassemblers and the recorders the CLI uses.
+
+pub mod apu;
+pub mod bus;
+pub mod cart;
+pub mod compat;
+pub mod cpu;
+pub mod debug;
+pub mod gameboy;
+pub mod gbs;
+pub mod joypad;
+pub mod link;
+pub mod model;
+pub mod png;
+pub mod ppu;
+pub mod printer;
+pub mod scheduler;
+pub mod serial;
+pub mod sgb;
+pub mod state;
+pub mod timer;
+pub mod vgm;
+pub mod wav;
+
+pub use cpu::EmuError;
+pub use gameboy::GameBoy;
+pub use joypad::Button;
+pub use ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+pub use state::StateError;
@@ -0,0 +1,106 @@
+use crate::cpu;
+use crate::gameboy::GameBoy;
+use crate::serial::SerialLink;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+mod tcp;
+
+pub use tcp::TcpLink;
+
+// State of the cable between two emulators in the same process, indexed by
+// side.
+struct Wire {
+    // The byte a side waiting on the external clock would send back.
+    waiting: [Option<u8>; 2],
+    // A byte clocked into a waiting side, not yet picked up by its poll.
+    received: [Option<u8>; 2],
+}
+
+pub struct CableEnd {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+// Makes a link cable; plug one end into each emulator's serial port.
+pub fn cable() -> (CableEnd, CableEnd) {
+    let wire = Rc::new(RefCell::new(Wire {
+        waiting: [None; 2],
+        received: [None; 2],
+    }));
+    let left = CableEnd {
+        wire: wire.clone(),
+        side: 0,
+    };
+    let right = CableEnd { wire, side: 1 };
+    (left, right)
+}
+
+impl SerialLink for CableEnd {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let other = 1 - self.side;
+        wire.waiting[self.side] = None;
+        match wire.waiting[other].take() {
+            Some(reply) => {
+                wire.received[other] = Some(data);
+                reply
+            }
+            // Nobody is listening, so the line stays high.
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let received = wire.received[self.side].take();
+        wire.waiting[self.side] = if received.is_some() { None } else { Some(data) };
+        received
+    }
+
+    fn cancel(&mut self) {
+        let mut wire = self.wire.borrow_mut();
+        wire.waiting[self.side] = None;
+        wire.received[self.side] = None;
+    }
+}
+
+// Runs two linked emulators for `m_cycles` more M-cycles, always stepping
+// whichever is behind so neither gets more than an instruction ahead of the
+// other on the cable. Stops at the first error from either side.
+pub fn run_lockstep<L: cpu::MemoryBus, R: cpu::MemoryBus>(
+    left: &mut cpu::Cpu<L>,
+    right: &mut cpu::Cpu<R>,
+    m_cycles: u64,
+) -> Result<(), cpu::EmuError> {
+    let (left_start, right_start) = (left.ticks(), right.ticks());
+    loop {
+        let left_elapsed = left.ticks() - left_start;
+        let right_elapsed = right.ticks() - right_start;
+        if left_elapsed >= m_cycles && right_elapsed >= m_cycles {
+            return Ok(());
+        }
+        if left_elapsed <= right_elapsed {
+            left.step()?;
+        } else {
+            right.step()?;
+        }
+    }
+}
+
+// Plugs a cable between two consoles, replacing whatever either had
+// connected.
+pub fn connect(left: &mut GameBoy, right: &mut GameBoy) {
+    let (left_end, right_end) = cable();
+    left.bus_mut().serial_mut().connect(Box::new(left_end));
+    right.bus_mut().serial_mut().connect(Box::new(right_end));
+}
+
+// run_lockstep for two consoles joined with `connect`.
+pub fn run_linked(
+    left: &mut GameBoy,
+    right: &mut GameBoy,
+    m_cycles: u64,
+) -> Result<(), cpu::EmuError> {
+    run_lockstep(left.cpu_mut(), right.cpu_mut(), m_cycles)
+}
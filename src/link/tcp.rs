@@ -0,0 +1,211 @@
+use crate::serial::SerialLink;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const PROTOCOL_VERSION: u8 = 1;
+
+// Every message is two bytes: a kind and a data byte.
+//
+// The side waiting on the external clock advertises the byte it would send
+// back with READY, again whenever it changes, and withdraws it with CANCEL
+// when it stops waiting. The side driving the clock answers a READY with
+// DATA, which completes the transfer on both ends without waiting for a
+// round trip. A side that clocks before the peer's READY has arrived reads
+// an idle line, as if the peer weren't listening.
+const MSG_READY: u8 = 0x01;
+const MSG_DATA: u8 = 0x02;
+const MSG_CANCEL: u8 = 0x03;
+
+// Link cable to another `gb` process.
+//
+// The two emulators run on their own clocks with nothing keeping them in
+// step, so a transfer only goes through if the waiting side's READY got
+// here before this side clocked it; otherwise this side reads 0xFF, as if
+// the peer weren't listening, and the peer never sees the byte. Games that
+// keep retrying until they get an answer link fine. Ones that expect a
+// single exchange to land, or both sides to be in step to the frame, may
+// not. Consoles in the same process can use `link::cable` instead, which
+// runs them in lockstep.
+pub struct TcpLink {
+    stream: Option<TcpStream>,
+    incoming: Vec<u8>,
+    // Messages the socket hasn't taken yet.
+    outgoing: Vec<u8>,
+    // The last byte the peer said it would reply with, if it's waiting.
+    peer_ready: Option<u8>,
+    // What we last advertised with READY.
+    advertised: Option<u8>,
+    // A byte the peer clocked into us.
+    received: Option<u8>,
+}
+
+impl TcpLink {
+    // Waits for one peer to connect on `address`.
+    pub fn listen(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::accept(&TcpListener::bind(address)?)
+    }
+
+    // Waits for one peer to connect to `listener`.
+    pub fn accept(listener: &TcpListener) -> io::Result<Self> {
+        let (stream, _) = listener.accept()?;
+        Self::handshake(stream)
+    }
+
+    pub fn connect(address: impl ToSocketAddrs) -> io::Result<Self> {
+        Self::handshake(TcpStream::connect(address)?)
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    fn handshake(mut stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        stream.write_all(MAGIC)?;
+        stream.write_all(&[PROTOCOL_VERSION])?;
+
+        let mut hello = [0; 5];
+        stream.read_exact(&mut hello)?;
+        if &hello[..4] != MAGIC || hello[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "peer isn't a compatible gb link",
+            ));
+        }
+        stream.set_nonblocking(true)?;
+
+        Ok(TcpLink {
+            stream: Some(stream),
+            incoming: Vec::new(),
+            outgoing: Vec::new(),
+            peer_ready: None,
+            advertised: None,
+            received: None,
+        })
+    }
+
+    fn send(&mut self, kind: u8, data: u8) {
+        if self.stream.is_some() {
+            self.outgoing.extend_from_slice(&[kind, data]);
+            self.flush();
+        }
+    }
+
+    // Writes as much of the queued messages as the socket takes without
+    // blocking; the rest goes out on a later call.
+    fn flush(&mut self) {
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+        while !self.outgoing.is_empty() {
+            match stream.write(&self.outgoing) {
+                Ok(0) => {
+                    self.disconnect(io::ErrorKind::WriteZero.into());
+                    return;
+                }
+                Ok(n) => {
+                    self.outgoing.drain(..n);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    self.disconnect(err);
+                    return;
+                }
+            }
+        }
+    }
+
+    // Tells the peer we're no longer waiting, if we said we were.
+    fn withdraw(&mut self) {
+        if self.advertised.take().is_some() {
+            self.send(MSG_CANCEL, 0);
+        }
+    }
+
+    // Sends anything still queued, then reads whatever has arrived without
+    // blocking and applies every complete message.
+    fn receive(&mut self) {
+        self.flush();
+        let Some(stream) = &mut self.stream else {
+            return;
+        };
+
+        let mut buffer = [0; 256];
+        loop {
+            match stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.disconnect(io::ErrorKind::UnexpectedEof.into());
+                    return;
+                }
+                Ok(n) => self.incoming.extend_from_slice(&buffer[..n]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => {
+                    self.disconnect(err);
+                    return;
+                }
+            }
+        }
+
+        let complete = self.incoming.len() & !1;
+        for message in self.incoming[..complete].chunks_exact(2) {
+            match message[0] {
+                MSG_READY => self.peer_ready = Some(message[1]),
+                MSG_CANCEL => self.peer_ready = None,
+                // Data that crosses our CANCEL is dropped: the game has
+                // stopped waiting for it.
+                MSG_DATA if self.advertised.is_some() => self.received = Some(message[1]),
+                MSG_DATA => (),
+                kind => eprintln!("link: ignoring unknown message {:#04x}", kind),
+            }
+        }
+        self.incoming.drain(..complete);
+    }
+
+    // Anything in flight is lost; the game sees an idle line from now on.
+    fn disconnect(&mut self, err: io::Error) {
+        eprintln!("link: peer disconnected: {}", err);
+        self.stream = None;
+        self.peer_ready = None;
+        self.advertised = None;
+        self.incoming.clear();
+        self.outgoing.clear();
+    }
+}
+
+impl SerialLink for TcpLink {
+    // Clocking a transfer ourselves means we're no longer waiting. The
+    // peer's READY has to be here already: waiting for it would stall the
+    // emulator.
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.withdraw();
+        self.receive();
+        match self.peer_ready.take() {
+            Some(reply) => {
+                self.send(MSG_DATA, data);
+                reply
+            }
+            None => 0xFF,
+        }
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        self.receive();
+        if let Some(received) = self.received.take() {
+            self.advertised = None;
+            return Some(received);
+        }
+        if self.stream.is_some() && self.advertised != Some(data) {
+            self.send(MSG_READY, data);
+            self.advertised = Some(data);
+        }
+        None
+    }
+
+    fn cancel(&mut self) {
+        self.withdraw();
+        self.received = None;
+    }
+}
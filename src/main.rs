@@ -1,19 +1,380 @@
-mod apu;
-mod bus;
-mod cart;
-mod cpu;
-mod debug;
-mod ppu;
+use gb::{compat, debug, gbs, link, model, png, printer, serial, sgb, vgm, wav, EmuError, GameBoy};
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const SAMPLE_RATE: u32 = 44_100;
+const CYCLES_PER_SECOND: u64 = 1 << 20;
+
+// 70224 T-cycles at 4.194304 MHz.
+const FRAME_DURATION: Duration = Duration::from_nanos(16_742_706);
+
+// How long a GBS track is played for when --seconds isn't given.
+const DEFAULT_GBS_SECONDS: u64 = 120;
+
+// Smallest file that still has a complete cart header.
+const MIN_ROM_LEN: usize = 0x150;
+
+const USAGE: &str = "\
+usage: gb [options] ROM
+       gb [options] --gbs FILE
+
+  --model MODEL        dmg, mgb, sgb, cgb or agb (default dmg)
+  --palette PALETTE    CGB colors for DMG games: auto, keys held at boot
+                       such as up or left+b, or a combination by number
+  --boot-rom FILE      run this boot ROM before the cart
+  --skip-boot          start the cart directly even with --boot-rom
+  --frames N           stop after N frames
+  --headless           run as fast as possible instead of in real time
+  --screenshot FILE    write the last frame as a PNG (needs --frames)
+  --trace              print every instruction before it runs
+  --strict             stop at the first memory access that fails
+  --wav FILE           record audio; --stems adds a file per channel
+  --vgm FILE           log APU register writes
+  --serial-stdout      print bytes sent over the serial port
+  --printer DIR        attach a Game Boy Printer saving pages to DIR
+  --link-listen ADDR   wait for a link cable connection on ADDR
+  --link-connect ADDR  connect a link cable to ADDR
+  --gbs FILE           play a GBS rip; --track N, --seconds N
+  --help               show this message
+";
+
+// Options that take a value; every other option is a flag.
+const VALUE_OPTIONS: [&str; 13] = [
+    "--model",
+    "--palette",
+    "--boot-rom",
+    "--frames",
+    "--screenshot",
+    "--wav",
+    "--vgm",
+    "--printer",
+    "--link-listen",
+    "--link-connect",
+    "--gbs",
+    "--track",
+    "--seconds",
+];
+
+// Options that each connect something to the serial port.
+const SERIAL_OPTIONS: [&str; 4] = [
+    "--serial-stdout",
+    "--printer",
+    "--link-listen",
+    "--link-connect",
+];
+
+const FLAGS: [&str; 7] = [
+    "--skip-boot",
+    "--headless",
+    "--trace",
+    "--strict",
+    "--stems",
+    "--serial-stdout",
+    "--help",
+];
 
 fn main() {
-    let mut apu = apu::Apu::new();
-    let mut ppu = ppu::Ppu::new();
-    let mut bus = bus::Bus::new(&mut apu, &mut ppu);
-    let mut cpu = cpu::Cpu::new(&mut bus);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let rom_path = check_args(&args);
+    if has_flag(&args, "--help") {
+        print!("{}", USAGE);
+        return;
+    }
+
+    let gbs = arg_value(&args, "--gbs").map(load_gbs);
+    match (&gbs, rom_path) {
+        (Some(_), Some(_)) => usage_error("give either a ROM or --gbs, not both"),
+        (None, None) => usage_error("no ROM given"),
+        _ => {}
+    }
+    let frames = arg_value(&args, "--frames").map(|n| parse_or_exit::<u64>("--frames", n));
+    let screenshot = arg_value(&args, "--screenshot");
+    if screenshot.is_some() && frames.is_none() {
+        usage_error("--screenshot needs --frames to know when to take it");
+    }
+    let model = match arg_value(&args, "--model") {
+        Some(name) => parse_or_exit::<model::Model>("--model", name),
+        None => model::Model::Dmg,
+    };
+    let trace = has_flag(&args, "--trace");
+    let strict = has_flag(&args, "--strict");
+
+    // Only one thing can be plugged into the link port.
+    let serial_options: Vec<&str> = SERIAL_OPTIONS
+        .into_iter()
+        .filter(|option| has_flag(&args, option))
+        .collect();
+    if let [first, second, ..] = serial_options[..] {
+        usage_error(&format!("{} can't be used with {}", second, first));
+    }
+
+    let mut gb = GameBoy::with_model(model);
+    if let Some(name) = arg_value(&args, "--palette") {
+        gb.set_palette(parse_or_exit::<compat::Selection>("--palette", name));
+    }
+    if let Some(path) = arg_value(&args, "--wav") {
+        let stems = has_flag(&args, "--stems");
+        match wav::WavRecorder::create(Path::new(path), SAMPLE_RATE, stems) {
+            Ok(recorder) => gb.apu_mut().set_sink(Box::new(recorder), SAMPLE_RATE),
+            Err(err) => {
+                eprintln!("gb: can't record to {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(path) = arg_value(&args, "--vgm") {
+        match vgm::VgmRecorder::create(Path::new(path)) {
+            Ok(recorder) => gb.apu_mut().set_register_log(Box::new(recorder)),
+            Err(err) => {
+                eprintln!("gb: can't log to {}: {}", path, err);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let bus = gb.bus_mut();
+    if has_flag(&args, "--serial-stdout") {
+        bus.serial_mut().connect(Box::new(serial::StdoutLink));
+    } else if let Some(dir) = arg_value(&args, "--printer") {
+        let printer = printer::Printer::new(dir.into());
+        bus.serial_mut().connect(Box::new(printer));
+    } else if let Some(address) = arg_value(&args, "--link-listen") {
+        eprintln!("gb: waiting for a link on {}", address);
+        let link = link::TcpLink::listen(address).unwrap_or_else(|err| {
+            eprintln!("gb: can't link on {}: {}", address, err);
+            std::process::exit(1);
+        });
+        bus.serial_mut().connect(Box::new(link));
+    } else if let Some(address) = arg_value(&args, "--link-connect") {
+        let link = link::TcpLink::connect(address).unwrap_or_else(|err| {
+            eprintln!("gb: can't link to {}: {}", address, err);
+            std::process::exit(1);
+        });
+        bus.serial_mut().connect(Box::new(link));
+    }
+
+    if let Some(gbs) = &gbs {
+        let song = match arg_value(&args, "--track") {
+            Some(track) => parse_or_exit::<u8>("--track", track),
+            None => gbs.first_song,
+        };
+        if song == 0 || song > gbs.song_count {
+            eprintln!("gb: track {} out of range 1-{}", song, gbs.song_count);
+            std::process::exit(1);
+        }
+        let seconds = match arg_value(&args, "--seconds") {
+            Some(seconds) => parse_or_exit::<u64>("--seconds", seconds),
+            None => DEFAULT_GBS_SECONDS,
+        };
+        eprintln!(
+            "gb: playing \"{}\" by {}, track {}/{}",
+            gbs.title, gbs.author, song, gbs.song_count
+        );
+
+        gb.load_cart(gbs.cart(song - 1));
+        gb.bus_mut().disable_boot_rom();
+        gb.cpu_mut().set_pc(gbs.entry_point());
+        while gb.cpu().ticks() < seconds * CYCLES_PER_SECOND {
+            if trace {
+                print_trace(&mut gb);
+            }
+            if let Err(err) = gb.step() {
+                report(&mut gb, err, strict);
+            }
+        }
+        gb.stop_recording();
+        return;
+    }
+
+    if let Some(path) = rom_path {
+        gb.load_rom(load_rom(path));
+    }
+
+    // The built-in boot ROM is only a fragment, so without a dump the cart
+    // starts straight away.
+    match arg_value(&args, "--boot-rom") {
+        Some(path) if !has_flag(&args, "--skip-boot") => {
+            gb.load_boot_rom(load_boot_rom(path, model))
+        }
+        _ => gb.skip_boot(),
+    }
+
+    let headless = has_flag(&args, "--headless");
+    let start = Instant::now();
+    let mut frame: u32 = 0;
+    while frames.is_none_or(|frames| u64::from(frame) < frames) {
+        let result = gb.run_frame_with(|gb| {
+            if trace {
+                print_trace(gb);
+            }
+        });
+        if let Err(err) = result {
+            report(&mut gb, err, strict);
+        }
+        frame += 1;
+
+        if !headless {
+            let due = start + FRAME_DURATION * frame;
+            if let Some(wait) = due.checked_duration_since(Instant::now()) {
+                std::thread::sleep(wait);
+            }
+        }
+    }
+    gb.stop_recording();
+
+    if let Some(path) = screenshot {
+        if let Err(err) = write_screenshot(&mut gb, Path::new(path)) {
+            eprintln!("gb: can't write {}: {}", path, err);
+            std::process::exit(1);
+        }
+    }
+}
+
+// The access that failed reads 0xFF or is dropped, so unless asked to stop
+// the emulator carries on like the hardware would.
+fn report(gb: &mut GameBoy, err: EmuError, strict: bool) {
+    eprintln!("gb: {}", err);
+    if strict {
+        exit(gb, 1);
+    }
+}
+
+// std::process::exit skips destructors, and the recorders only finish their
+// files once they're dropped.
+fn exit(gb: &mut GameBoy, code: i32) -> ! {
+    gb.stop_recording();
+    std::process::exit(code);
+}
+
+// Rejects unknown options and options missing their value, and returns the
+// ROM path if one was given.
+fn check_args(args: &[String]) -> Option<&str> {
+    let mut rom_path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if VALUE_OPTIONS.contains(&arg.as_str()) {
+            if args.next().is_none() {
+                usage_error(&format!("{} needs a value", arg));
+            }
+        } else if FLAGS.contains(&arg.as_str()) {
+            continue;
+        } else if arg.starts_with("--") {
+            usage_error(&format!("unknown option {}", arg));
+        } else if rom_path.is_some() {
+            usage_error(&format!("unexpected argument {}", arg));
+        } else {
+            rom_path = Some(arg.as_str());
+        }
+    }
+    rom_path
+}
 
-    debug::disassemble(&mut bus::BOOT_ROM[..]);
+fn usage_error(message: &str) -> ! {
+    eprintln!("gb: {}", message);
+    eprintln!("run gb --help for usage");
+    std::process::exit(2);
+}
 
-    loop {
-        cpu.tick()
+// Stops quietly once whatever reads the trace goes away, such as `head`.
+fn print_trace(gb: &mut GameBoy) {
+    let mut stdout = std::io::stdout().lock();
+    if writeln!(stdout, "{}", debug::trace(gb.cpu())).is_err() {
+        drop(stdout);
+        exit(gb, 0);
     }
 }
+
+fn has_flag(args: &[String], name: &str) -> bool {
+    args.iter().any(|arg| arg == name)
+}
+
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    let index = args.iter().position(|arg| arg == name)?;
+    args.get(index + 1).map(String::as_str)
+}
+
+fn parse_or_exit<T>(name: &str, value: &str) -> T
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    value.parse().unwrap_or_else(|err| {
+        eprintln!("gb: invalid value for {}: {} ({})", name, value, err);
+        std::process::exit(1);
+    })
+}
+
+fn read_or_exit(path: &str) -> Vec<u8> {
+    std::fs::read(path).unwrap_or_else(|err| {
+        eprintln!("gb: can't read {}: {}", path, err);
+        std::process::exit(1);
+    })
+}
+
+fn load_rom(path: &str) -> Vec<u8> {
+    let rom = read_or_exit(path);
+    if rom.len() < MIN_ROM_LEN {
+        eprintln!(
+            "gb: {} is too short to be a Game Boy ROM ({} bytes)",
+            path,
+            rom.len()
+        );
+        std::process::exit(1);
+    }
+    rom
+}
+
+fn load_boot_rom(path: &str, model: model::Model) -> Vec<u8> {
+    let rom = read_or_exit(path);
+    if rom.len() != model.boot_rom_len() {
+        eprintln!(
+            "gb: {} is {} bytes, but a {} boot ROM is {}",
+            path,
+            rom.len(),
+            model,
+            model.boot_rom_len()
+        );
+        std::process::exit(1);
+    }
+    rom
+}
+
+fn load_gbs(path: &str) -> gbs::Gbs {
+    let parsed = std::fs::read(path)
+        .map_err(|err| err.to_string())
+        .and_then(|bytes| gbs::Gbs::parse(&bytes).map_err(|err| err.to_string()));
+    parsed.unwrap_or_else(|err| {
+        eprintln!("gb: can't load {}: {}", path, err);
+        std::process::exit(1);
+    })
+}
+
+// Saves what's on screen: the SGB's picture, border included, or else the
+// LCD. Both are RGB555 and widen to 8 bits per channel.
+fn write_screenshot(gb: &mut GameBoy, path: &Path) -> std::io::Result<()> {
+    let (width, height, pixels) = match gb.bus().sgb() {
+        Some(sgb) => {
+            let mut out = vec![0; sgb::SCREEN_WIDTH * sgb::SCREEN_HEIGHT];
+            sgb.render(&mut out);
+            (sgb::SCREEN_WIDTH, sgb::SCREEN_HEIGHT, out)
+        }
+        None => (
+            gb::SCREEN_WIDTH,
+            gb::SCREEN_HEIGHT,
+            gb.framebuffer().to_vec(),
+        ),
+    };
+    let rgb: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&color| {
+            [0, 5, 10].map(|shift| {
+                let level = (color >> shift & 0x1F) as u8;
+                level << 3 | level >> 2
+            })
+        })
+        .collect();
+    png::write(path, width as u32, height as u32, png::ColorType::Rgb, &rgb)
+}
@@ -0,0 +1,64 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    // Size of the model's boot ROM dump. The CGB one has a second part
+    // after the cart header.
+    pub fn boot_rom_len(self) -> usize {
+        if self.is_cgb() {
+            0x900
+        } else {
+            0x100
+        }
+    }
+}
+
+impl fmt::Display for Model {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Model::Dmg => "dmg",
+            Model::Mgb => "mgb",
+            Model::Sgb => "sgb",
+            Model::Cgb => "cgb",
+            Model::Agb => "agb",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnknownModel;
+
+impl fmt::Display for UnknownModel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected dmg, mgb, sgb, cgb or agb")
+    }
+}
+
+impl FromStr for Model {
+    type Err = UnknownModel;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "dmg" => Ok(Model::Dmg),
+            "mgb" => Ok(Model::Mgb),
+            "sgb" => Ok(Model::Sgb),
+            "cgb" => Ok(Model::Cgb),
+            "agb" => Ok(Model::Agb),
+            _ => Err(UnknownModel),
+        }
+    }
+}
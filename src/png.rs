@@ -0,0 +1,119 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Largest stored (uncompressed) deflate block.
+const MAX_BLOCK: usize = 0xFFFF;
+
+#[derive(Clone, Copy)]
+pub enum ColorType {
+    Gray,
+    Rgb,
+}
+
+impl ColorType {
+    fn channels(self) -> usize {
+        match self {
+            ColorType::Gray => 1,
+            ColorType::Rgb => 3,
+        }
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            ColorType::Gray => 0,
+            ColorType::Rgb => 2,
+        }
+    }
+}
+
+// Writes 8-bit pixels, row by row, as a PNG. Images here are small, so the
+// data is stored without compression rather than pulling in a deflater.
+pub fn write(
+    path: &Path,
+    width: u32,
+    height: u32,
+    color: ColorType,
+    pixels: &[u8],
+) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(&encode(width, height, color, pixels))
+}
+
+pub fn encode(width: u32, height: u32, color: ColorType, pixels: &[u8]) -> Vec<u8> {
+    let stride = width as usize * color.channels();
+    assert_eq!(
+        pixels.len(),
+        stride * height as usize,
+        "png: wrong pixel count"
+    );
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&width.to_be_bytes());
+    ihdr.extend_from_slice(&height.to_be_bytes());
+    ihdr.extend_from_slice(&[8, color.code(), 0, 0, 0]);
+
+    // Each row starts with its filter type, always 0 (none).
+    let mut raw = Vec::with_capacity((stride + 1) * height as usize);
+    for row in pixels.chunks(stride.max(1)).take(height as usize) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    chunk(&mut png, b"IHDR", &ihdr);
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, no preset dictionary, fastest level.
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
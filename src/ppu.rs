@@ -1,25 +1,475 @@
+use crate::bus;
+use crate::compat;
+use crate::state;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+const LINES_PER_FRAME: u8 = 154;
+const OAM_SCAN_DOTS: u32 = 80;
+const DRAWING_DOTS: u32 = 172;
+
+const LCDC_ENABLE: u8 = 1 << 7;
+const LCDC_WINDOW_MAP: u8 = 1 << 6;
+const LCDC_WINDOW_ENABLE: u8 = 1 << 5;
+const LCDC_TILE_DATA: u8 = 1 << 4;
+const LCDC_BG_MAP: u8 = 1 << 3;
+const LCDC_OBJ_SIZE: u8 = 1 << 2;
+const LCDC_OBJ_ENABLE: u8 = 1 << 1;
+const LCDC_BG_ENABLE: u8 = 1 << 0;
+
+const STAT_LYC_INT: u8 = 1 << 6;
+const STAT_OAM_INT: u8 = 1 << 5;
+const STAT_VBLANK_INT: u8 = 1 << 4;
+const STAT_HBLANK_INT: u8 = 1 << 3;
+const STAT_LYC_EQUAL: u8 = 1 << 2;
+
+const OBJ_BEHIND_BG: u8 = 1 << 7;
+const OBJ_FLIP_Y: u8 = 1 << 6;
+const OBJ_FLIP_X: u8 = 1 << 5;
+const OBJ_PALETTE_1: u8 = 1 << 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
 pub struct Ppu {
     control: u8,
+    stat: u8,
+    scy: u8,
+    scx: u8,
+    ly: u8,
+    lyc: u8,
+    dma: u8,
+    bgp: u8,
+    obp0: u8,
+    obp1: u8,
+    wy: u8,
+    wx: u8,
+
     ram: [u8; 0x2000],
+    oam: [u8; 0xA0],
+
+    mode: Mode,
+    dot: u32,
+    window_line: u8,
+    stat_line: bool,
+
+    palette: compat::CompatPalette,
+    shades: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
+    pixels: [u16; SCREEN_WIDTH * SCREEN_HEIGHT],
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Ppu {
     pub fn new() -> Self {
         Ppu {
             control: 0,
+            stat: 0,
+            scy: 0,
+            scx: 0,
+            ly: 0,
+            lyc: 0,
+            dma: 0,
+            bgp: 0,
+            obp0: 0,
+            obp1: 0,
+            wy: 0,
+            wx: 0,
             ram: [0; 0x2000],
+            oam: [0; 0xA0],
+            mode: Mode::HBlank,
+            dot: 0,
+            window_line: 0,
+            stat_line: false,
+            palette: compat::GRAYSCALE,
+            shades: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            pixels: [0x7FFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: compat::CompatPalette) {
+        self.palette = palette;
+    }
+
+    // Final RGB555 colors of the last completed lines.
+    pub fn pixels(&self) -> &[u16] {
+        &self.pixels
+    }
+
+    // Shades 0-3 after BGP/OBP translation, before any colorization.
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    // The first 256 tiles shown on screen, in display order. This is how an
+    // SGB sees VRAM when a game sends bulk data with one of the *_TRN commands.
+    pub fn transfer_data(&self) -> Vec<u8> {
+        let map = if self.control & LCDC_BG_MAP != 0 {
+            0x1C00
+        } else {
+            0x1800
+        };
+        let mut data = Vec::with_capacity(256 * 16);
+        for i in 0..256 {
+            let tile = self.ram[map + (i / 20) * 32 + i % 20];
+            let address = self.tile_address(tile);
+            data.extend_from_slice(&self.ram[address..address + 16]);
         }
+        data
     }
 
-    pub fn read(&self, address: u16) -> u8 {
-        return self.ram[address as usize];
+    pub fn read(&self, address: u16) -> Result<u8, bus::BusError> {
+        let value = match address {
+            0x8000..=0x9FFF => self.ram[(address - 0x8000) as usize],
+            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize],
+            0xFF40 => self.control,
+            0xFF41 => 0x80 | self.stat | self.lyc_flag() | self.mode as u8,
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => self.ly,
+            0xFF45 => self.lyc,
+            0xFF46 => self.dma,
+            0xFF47 => self.bgp,
+            0xFF48 => self.obp0,
+            0xFF49 => self.obp1,
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+            _ => return Err(bus::BusError::Read(address)),
+        };
+        Ok(value)
     }
 
-    pub fn write(&mut self, address: u16, value: u8) {
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), bus::BusError> {
         match address {
-            0xFF40 => self.control = value,
             0x8000..=0x9FFF => self.ram[(address - 0x8000) as usize] = value,
-            _ => panic!("ppu: invalid write access {:#x}", address),
+            0xFE00..=0xFE9F => self.oam[(address - 0xFE00) as usize] = value,
+            0xFF40 => self.write_control(value),
+            0xFF41 => self.stat = value & 0x78,
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF44 => (),
+            0xFF45 => self.lyc = value,
+            0xFF46 => self.dma = value,
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obp0 = value,
+            0xFF49 => self.obp1 = value,
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            _ => return Err(bus::BusError::Write(address, value)),
+        }
+        Ok(())
+    }
+
+    // Where OAM DMA puts each byte it copies.
+    pub fn write_oam(&mut self, index: u8, value: u8) {
+        self.oam[index as usize] = value;
+    }
+
+    // Advances the LCD by `cycles` M-cycles and returns the interrupts it
+    // raised. Between mode and line changes only the dot counter moves, so
+    // those dots are skipped. The first dot always runs in full in case a
+    // register write since the last call has changed the STAT line.
+    pub fn advance(&mut self, cycles: u64) -> u8 {
+        if self.control & LCDC_ENABLE == 0 {
+            return 0;
+        }
+
+        let mut dots = cycles * 4;
+        let mut interrupts = 0;
+        while dots > 0 {
+            interrupts |= self.tick_dot();
+            dots -= 1;
+            let skipped = dots.min(self.dots_to_boundary() as u64 - 1);
+            self.dot += skipped as u32;
+            dots -= skipped;
+        }
+        interrupts
+    }
+
+    // M-cycles until the one in which the LCD next changes mode or line.
+    pub fn cycles_to_event(&self) -> Option<u64> {
+        if self.control & LCDC_ENABLE == 0 {
+            return None;
+        }
+        Some(self.dots_to_boundary().div_ceil(4) as u64)
+    }
+
+    fn dots_to_boundary(&self) -> u32 {
+        let boundary = if (self.ly as usize) < SCREEN_HEIGHT {
+            [OAM_SCAN_DOTS, OAM_SCAN_DOTS + DRAWING_DOTS, DOTS_PER_LINE]
+                .into_iter()
+                .find(|&boundary| boundary > self.dot)
+                .unwrap_or(DOTS_PER_LINE)
+        } else {
+            DOTS_PER_LINE
+        };
+        boundary - self.dot
+    }
+
+    fn tick_dot(&mut self) -> u8 {
+        let mut interrupts = 0;
+
+        self.dot += 1;
+        if self.dot == DOTS_PER_LINE {
+            self.dot = 0;
+            self.ly = (self.ly + 1) % LINES_PER_FRAME;
+            if self.ly == 0 {
+                self.window_line = 0;
+            }
+        }
+
+        let mode = if self.ly as usize >= SCREEN_HEIGHT {
+            Mode::VBlank
+        } else if self.dot < OAM_SCAN_DOTS {
+            Mode::OamScan
+        } else if self.dot < OAM_SCAN_DOTS + DRAWING_DOTS {
+            Mode::Drawing
+        } else {
+            Mode::HBlank
+        };
+
+        if mode != self.mode {
+            if mode == Mode::HBlank {
+                self.render_line();
+            }
+            if mode == Mode::VBlank {
+                interrupts |= bus::INT_VBLANK;
+            }
+            self.mode = mode;
+        }
+
+        if self.update_stat_line() {
+            interrupts |= bus::INT_STAT;
+        }
+        interrupts
+    }
+
+    fn write_control(&mut self, value: u8) {
+        if value & LCDC_ENABLE == 0 && self.control & LCDC_ENABLE != 0 {
+            self.ly = 0;
+            self.dot = 0;
+            self.window_line = 0;
+            self.mode = Mode::HBlank;
+            self.stat_line = false;
+        }
+        self.control = value;
+    }
+
+    fn lyc_flag(&self) -> u8 {
+        if self.ly == self.lyc {
+            STAT_LYC_EQUAL
+        } else {
+            0
+        }
+    }
+
+    // STAT interrupts fire on the rising edge of the OR of all enabled sources.
+    fn update_stat_line(&mut self) -> bool {
+        let line = (self.stat & STAT_LYC_INT != 0 && self.ly == self.lyc)
+            || (self.stat & STAT_HBLANK_INT != 0 && self.mode == Mode::HBlank)
+            || (self.stat & STAT_VBLANK_INT != 0 && self.mode == Mode::VBlank)
+            || (self.stat & STAT_OAM_INT != 0 && self.mode == Mode::OamScan);
+        let rising = line && !self.stat_line;
+        self.stat_line = line;
+        rising
+    }
+
+    fn render_line(&mut self) {
+        let y = self.ly as usize;
+        let mut bg_colors = [0u8; SCREEN_WIDTH];
+
+        if self.control & LCDC_BG_ENABLE != 0 {
+            let map = if self.control & LCDC_BG_MAP != 0 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            let bg_y = self.scy.wrapping_add(self.ly);
+            for (x, color) in bg_colors.iter_mut().enumerate() {
+                let bg_x = self.scx.wrapping_add(x as u8);
+                *color = self.tile_map_color(map, bg_x, bg_y);
+            }
+
+            let window_x = self.wx as i16 - 7;
+            if self.control & LCDC_WINDOW_ENABLE != 0 && self.ly >= self.wy && self.wx <= 166 {
+                let map = if self.control & LCDC_WINDOW_MAP != 0 {
+                    0x1C00
+                } else {
+                    0x1800
+                };
+                for (x, color) in bg_colors.iter_mut().enumerate() {
+                    let win_x = x as i16 - window_x;
+                    if win_x >= 0 {
+                        *color = self.tile_map_color(map, win_x as u8, self.window_line);
+                    }
+                }
+                self.window_line += 1;
+            }
+        }
+
+        let row = y * SCREEN_WIDTH;
+        for (x, &color) in bg_colors.iter().enumerate() {
+            let shade = (self.bgp >> (color * 2)) & 0x03;
+            self.shades[row + x] = shade;
+            self.pixels[row + x] = self.palette.bg[shade as usize];
+        }
+
+        if self.control & LCDC_OBJ_ENABLE != 0 {
+            self.render_objects(&bg_colors);
+        }
+    }
+
+    fn render_objects(&mut self, bg_colors: &[u8; SCREEN_WIDTH]) {
+        let height: i16 = if self.control & LCDC_OBJ_SIZE != 0 {
+            16
+        } else {
+            8
+        };
+        let ly = self.ly as i16;
+
+        let mut objects: Vec<usize> = (0..40)
+            .filter(|&i| {
+                let top = self.oam[i * 4] as i16 - 16;
+                ly >= top && ly < top + height
+            })
+            .take(10)
+            .collect();
+
+        // Lower x wins, then lower OAM index; draw the winners last.
+        objects.sort_by_key(|&i| (self.oam[i * 4 + 1], i));
+        objects.reverse();
+
+        let row = self.ly as usize * SCREEN_WIDTH;
+        for i in objects {
+            let top = self.oam[i * 4] as i16 - 16;
+            let left = self.oam[i * 4 + 1] as i16 - 8;
+            let mut tile = self.oam[i * 4 + 2];
+            let attributes = self.oam[i * 4 + 3];
+
+            let mut line = ly - top;
+            if attributes & OBJ_FLIP_Y != 0 {
+                line = height - 1 - line;
+            }
+            if height == 16 {
+                tile &= 0xFE;
+            }
+
+            let address = tile as usize * 16 + line as usize * 2;
+            let low = self.ram[address];
+            let high = self.ram[address + 1];
+
+            for col in 0..8 {
+                let x = left + col;
+                if !(0..SCREEN_WIDTH as i16).contains(&x) {
+                    continue;
+                }
+                let bit = if attributes & OBJ_FLIP_X != 0 {
+                    col
+                } else {
+                    7 - col
+                };
+                let color = ((high >> bit) & 1) << 1 | ((low >> bit) & 1);
+                if color == 0 {
+                    continue;
+                }
+                if attributes & OBJ_BEHIND_BG != 0 && bg_colors[x as usize] != 0 {
+                    continue;
+                }
+
+                let (register, colors) = if attributes & OBJ_PALETTE_1 != 0 {
+                    (self.obp1, self.palette.obj1)
+                } else {
+                    (self.obp0, self.palette.obj0)
+                };
+                let shade = (register >> (color * 2)) & 0x03;
+                self.shades[row + x as usize] = shade;
+                self.pixels[row + x as usize] = colors[shade as usize];
+            }
+        }
+    }
+
+    fn tile_map_color(&self, map: usize, x: u8, y: u8) -> u8 {
+        let tile = self.ram[map + (y as usize / 8) * 32 + x as usize / 8];
+        let address = self.tile_address(tile) + (y as usize % 8) * 2;
+        let bit = 7 - (x % 8);
+        ((self.ram[address + 1] >> bit) & 1) << 1 | ((self.ram[address] >> bit) & 1)
+    }
+
+    fn tile_address(&self, tile: u8) -> usize {
+        if self.control & LCDC_TILE_DATA != 0 {
+            tile as usize * 16
+        } else {
+            (0x1000 + (tile as i8 as isize) * 16) as usize
         }
     }
+
+    // The palette is a display setting rather than console state.
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.bytes(&[
+            self.control,
+            self.stat,
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.dma,
+            self.bgp,
+            self.obp0,
+            self.obp1,
+            self.wy,
+            self.wx,
+        ]);
+        state.bytes(&self.ram);
+        state.bytes(&self.oam);
+        state.u8(self.mode as u8);
+        state.u32(self.dot);
+        state.u8(self.window_line);
+        state.bool(self.stat_line);
+        state.bytes(&self.shades);
+        state.words(&self.pixels);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        let mut registers = [0; 12];
+        state.bytes(&mut registers)?;
+        [
+            self.control,
+            self.stat,
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.dma,
+            self.bgp,
+            self.obp0,
+            self.obp1,
+            self.wy,
+            self.wx,
+        ] = registers;
+        state.bytes(&mut self.ram)?;
+        state.bytes(&mut self.oam)?;
+        self.mode = match state.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            3 => Mode::Drawing,
+            _ => return Err(state::StateError::Invalid("PPU mode")),
+        };
+        self.dot = state.u32()?;
+        self.window_line = state.u8()?;
+        self.stat_line = state.bool()?;
+        state.bytes(&mut self.shades)?;
+        state.words(&mut self.pixels)
+    }
 }
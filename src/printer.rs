@@ -0,0 +1,327 @@
+use crate::png;
+use crate::serial::SerialLink;
+use std::path::PathBuf;
+
+const WIDTH: usize = 160;
+const TILES_PER_ROW: usize = WIDTH / 8;
+const TILE_BYTES: usize = 16;
+
+// A full DATA packet holds two rows of tiles, and the printer's buffer holds
+// nine packets: one screen's worth.
+const PACKET_BYTES: usize = TILES_PER_ROW * TILE_BYTES * 2;
+const BUFFER_BYTES: usize = PACKET_BYTES * 9;
+
+// Each unit of margin feeds this many blank lines of paper.
+const MARGIN_LINES: usize = 16;
+
+const CMD_INIT: u8 = 0x01;
+const CMD_PRINT: u8 = 0x02;
+const CMD_DATA: u8 = 0x04;
+const CMD_BREAK: u8 = 0x08;
+const CMD_STATUS: u8 = 0x0F;
+
+// Printer ID, sent in reply to the first byte after the checksum.
+const ALIVE: u8 = 0x81;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_IMAGE_FULL: u8 = 1 << 2;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+const STATUS_PACKET_ERROR: u8 = 1 << 4;
+
+// How many status requests report the printer busy after a PRINT.
+const PRINT_STATUS_POLLS: u8 = 4;
+
+enum State {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// Game Boy Printer, as the game sees it from the other end of the link
+// cable. Every packet is
+//
+//     0x88 0x33 command compression length(2) data... checksum(2) 0x00 0x00
+//
+// and the printer answers the last two bytes with its ID and its status.
+// Printed strips are stacked into pages, and a page is written out as a PNG
+// once a print asks for a margin after it.
+pub struct Printer {
+    state: State,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    packet: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+
+    status: u8,
+    busy_polls: u8,
+    buffer: Vec<u8>,
+
+    // Shades of the page printed so far, one byte per pixel.
+    page: Vec<u8>,
+    pages_written: usize,
+    output_dir: PathBuf,
+}
+
+impl Printer {
+    pub fn new(output_dir: PathBuf) -> Self {
+        Printer {
+            state: State::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            packet: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_polls: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            pages_written: 0,
+            output_dir,
+        }
+    }
+
+    // Takes one byte from the game and returns the byte shifted back.
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut reply = 0x00;
+        self.state = match self.state {
+            State::Magic1 if byte == 0x88 => State::Magic2,
+            State::Magic1 => State::Magic1,
+            State::Magic2 if byte == 0x33 => State::Command,
+            State::Magic2 => State::Magic1,
+            State::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                State::Compression
+            }
+            State::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthLow
+            }
+            State::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                State::LengthHigh
+            }
+            State::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.packet.clear();
+                if self.length == 0 {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::Data => {
+                self.packet.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.packet.len() == self.length as usize {
+                    State::ChecksumLow
+                } else {
+                    State::Data
+                }
+            }
+            State::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                State::ChecksumHigh
+            }
+            State::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                State::Alive
+            }
+            State::Alive => {
+                reply = ALIVE;
+                State::Status
+            }
+            State::Status => {
+                self.execute();
+                reply = self.status;
+                State::Magic1
+            }
+        };
+        reply
+    }
+
+    fn execute(&mut self) {
+        if self.checksum != self.received_checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            CMD_INIT => {
+                self.buffer.clear();
+                self.status = 0;
+                self.busy_polls = 0;
+            }
+            CMD_DATA => self.data(),
+            CMD_PRINT if self.packet.len() == 4 => self.print(),
+            // Stops a print in progress and throws away the image.
+            CMD_BREAK => {
+                self.buffer.clear();
+                self.busy_polls = 0;
+                self.status &= !(STATUS_PRINTING | STATUS_IMAGE_FULL | STATUS_UNPROCESSED);
+            }
+            CMD_STATUS => {
+                if self.busy_polls > 0 {
+                    self.busy_polls -= 1;
+                    if self.busy_polls == 0 {
+                        self.status &= !(STATUS_PRINTING | STATUS_IMAGE_FULL);
+                    }
+                }
+            }
+            _ => self.status |= STATUS_PACKET_ERROR,
+        }
+    }
+
+    // An empty DATA packet marks the end of the image.
+    fn data(&mut self) {
+        let packet = std::mem::take(&mut self.packet);
+        if self.compressed {
+            decompress(&packet, &mut self.buffer);
+        } else {
+            self.buffer.extend_from_slice(&packet);
+        }
+        self.packet = packet;
+
+        self.buffer.truncate(BUFFER_BYTES);
+        if !self.buffer.is_empty() {
+            self.status |= STATUS_UNPROCESSED;
+        }
+        if self.buffer.len() == BUFFER_BYTES {
+            self.status |= STATUS_IMAGE_FULL;
+        }
+    }
+
+    // The parameters are the number of copies, margins before (high
+    // nibble) and after (low nibble), the palette and the exposure, which
+    // has no bearing on the image. A palette of 0 prints like 0xE4, the
+    // usual shades in order.
+    fn print(&mut self) {
+        let copies = self.packet[0];
+        let margin_before = (self.packet[1] >> 4) as usize;
+        let margin_after = (self.packet[1] & 0x0F) as usize;
+        let palette = match self.packet[2] {
+            0x00 => 0xE4,
+            palette => palette,
+        };
+
+        if copies > 0 {
+            self.feed(margin_before);
+            let strip = self.render(palette);
+            for _ in 0..copies {
+                self.page.extend_from_slice(&strip);
+            }
+            self.feed(margin_after);
+            if margin_after > 0 {
+                self.finish_page();
+            }
+        }
+
+        self.buffer.clear();
+        self.status = STATUS_PRINTING | STATUS_IMAGE_FULL;
+        self.busy_polls = PRINT_STATUS_POLLS;
+    }
+
+    fn feed(&mut self, margin: usize) {
+        let len = self.page.len() + margin * MARGIN_LINES * WIDTH;
+        self.page.resize(len, 0xFF);
+    }
+
+    // Lays the buffered tiles out 20 to a row, mapping colors through the
+    // palette the same way BGP does.
+    fn render(&self, palette: u8) -> Vec<u8> {
+        let tile_rows = self.buffer.len() / (TILES_PER_ROW * TILE_BYTES);
+        let mut strip = vec![0xFF; tile_rows * 8 * WIDTH];
+        for (index, tile) in self.buffer.chunks_exact(TILE_BYTES).enumerate() {
+            let tile_x = (index % TILES_PER_ROW) * 8;
+            let tile_y = (index / TILES_PER_ROW) * 8;
+            for (row, bytes) in tile.chunks_exact(2).enumerate() {
+                for x in 0..8 {
+                    let bit = 7 - x;
+                    let color = ((bytes[0] >> bit) & 1) | (((bytes[1] >> bit) & 1) << 1);
+                    let shade = (palette >> (color * 2)) & 0x03;
+                    strip[(tile_y + row) * WIDTH + tile_x + x] = 0xFF - shade * 0x55;
+                }
+            }
+        }
+        strip
+    }
+
+    fn finish_page(&mut self) {
+        let page = std::mem::take(&mut self.page);
+        let height = page.len() / WIDTH;
+        if height == 0 {
+            return;
+        }
+
+        self.pages_written += 1;
+        let path = self
+            .output_dir
+            .join(format!("print-{:03}.png", self.pages_written));
+        match png::write(
+            &path,
+            WIDTH as u32,
+            height as u32,
+            png::ColorType::Gray,
+            &page,
+        ) {
+            Ok(()) => eprintln!("printer: wrote {}", path.display()),
+            Err(err) => eprintln!("printer: can't write {}: {}", path.display(), err),
+        }
+    }
+}
+
+impl SerialLink for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.receive(data)
+    }
+
+    // The printer never drives the clock.
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+impl Drop for Printer {
+    // Whatever was printed without a final margin is still on the paper.
+    fn drop(&mut self) {
+        self.finish_page();
+    }
+}
+
+// Runs with the high bit set repeat the next byte (length & 0x7F) + 2
+// times; otherwise the next (length + 1) bytes are copied as they are.
+fn decompress(data: &[u8], out: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            let Some(&byte) = data.get(i) else {
+                break;
+            };
+            i += 1;
+            let count = (control & 0x7F) as usize + 2;
+            out.extend(std::iter::repeat_n(byte, count));
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+}
@@ -0,0 +1,77 @@
+// Components that only change state at predictable times aren't ticked
+// every M-cycle. Each one schedules the next time it needs to do something
+// that the rest of the system can observe, such as raising an interrupt,
+// and is otherwise caught up lazily when the CPU touches one of its
+// registers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    // The PPU enters a new mode or line.
+    Ppu,
+    // TIMA reloads from TMA after overflowing.
+    Timer,
+    // DIV bit 4 falls and clocks the APU frame sequencer.
+    FrameSequencer,
+}
+
+pub struct Scheduler {
+    // M-cycles completed since power on.
+    now: u64,
+    // At most one entry per event, latest first so the next one due is at
+    // the end.
+    queue: Vec<(u64, Event)>,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Scheduler {
+            now: 0,
+            queue: Vec::with_capacity(4),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self) {
+        self.now += 1;
+    }
+
+    // M-cycles until the next event is due, counting the one it's due in.
+    pub fn cycles_to_next(&self) -> Option<u64> {
+        let &(time, _) = self.queue.last()?;
+        Some(time.saturating_sub(self.now).max(1))
+    }
+
+    // Moves time forward without running anything; nothing may be due in
+    // the cycles skipped.
+    pub fn skip(&mut self, cycles: u64) {
+        self.now += cycles;
+        debug_assert!(self.queue.last().is_none_or(|&(time, _)| time > self.now));
+    }
+
+    // Replaces any earlier schedule for `event`.
+    pub fn schedule(&mut self, event: Event, at: u64) {
+        self.cancel(event);
+        let index = self.queue.partition_point(|&(time, _)| time > at);
+        self.queue.insert(index, (at, event));
+    }
+
+    pub fn cancel(&mut self, event: Event) {
+        self.queue.retain(|&(_, queued)| queued != event);
+    }
+
+    // Takes the next event whose time has come, if any.
+    pub fn pop_due(&mut self) -> Option<(u64, Event)> {
+        match self.queue.last() {
+            Some(&(time, _)) if time <= self.now => self.queue.pop(),
+            _ => None,
+        }
+    }
+}
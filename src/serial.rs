@@ -0,0 +1,181 @@
+use crate::bus;
+use crate::state;
+use std::io::Write;
+
+// With the internal clock a bit is shifted every 512 T-cycles (8192 Hz).
+const BIT_CYCLES: u32 = 512;
+
+// Whatever is on the other end of the link cable. Transfers are exchanged a
+// byte at a time.
+pub trait SerialLink {
+    // This side drives the clock: sends `data` and returns the byte the
+    // other side shifted back.
+    fn exchange(&mut self, data: u8) -> u8;
+
+    // Called every M-cycle while this side waits on the external clock,
+    // with the byte it would send. Returns the byte received once the other
+    // side has clocked a transfer.
+    fn poll(&mut self, data: u8) -> Option<u8>;
+
+    // Called when this side stops waiting on the external clock without a
+    // transfer having happened.
+    fn cancel(&mut self) {}
+}
+
+// Prints every byte sent, which is how blargg's test ROMs report results.
+pub struct StdoutLink;
+
+impl SerialLink for StdoutLink {
+    fn exchange(&mut self, data: u8) -> u8 {
+        let mut stdout = std::io::stdout();
+        let _ = stdout.write_all(&[data]);
+        let _ = stdout.flush();
+        0xFF
+    }
+
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    // Bits left to shift in the current internal clock transfer.
+    bits: u8,
+    cycles: u32,
+    link: Option<Box<dyn SerialLink>>,
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Serial {
+    pub fn new() -> Self {
+        Serial {
+            sb: 0,
+            sc: 0,
+            bits: 0,
+            cycles: 0,
+            link: None,
+        }
+    }
+
+    pub fn connect(&mut self, link: Box<dyn SerialLink>) {
+        self.link = Some(link);
+    }
+
+    pub fn disconnect(&mut self) -> Option<Box<dyn SerialLink>> {
+        self.link.take()
+    }
+
+    pub fn read(&self, address: u16) -> Result<u8, bus::BusError> {
+        match address {
+            0xFF01 => Ok(self.sb),
+            0xFF02 => Ok(0x7E | self.sc),
+            _ => Err(bus::BusError::Read(address)),
+        }
+    }
+
+    pub fn write(&mut self, address: u16, value: u8) -> Result<(), bus::BusError> {
+        match address {
+            0xFF01 => self.sb = value,
+            0xFF02 => {
+                let was_waiting = self.waiting();
+                self.sc = value & 0x81;
+                if was_waiting && !self.waiting() {
+                    if let Some(link) = &mut self.link {
+                        link.cancel();
+                    }
+                }
+                if self.transferring() && self.internal_clock() {
+                    self.bits = 8;
+                    self.cycles = 0;
+                }
+            }
+            _ => return Err(bus::BusError::Write(address, value)),
+        }
+        Ok(())
+    }
+
+    // Advances by one M-cycle and returns the interrupts it raised.
+    pub fn tick(&mut self) -> u8 {
+        if !self.transferring() {
+            return 0;
+        }
+
+        if self.waiting() {
+            let received = match &mut self.link {
+                Some(link) => link.poll(self.sb),
+                None => None,
+            };
+            return match received {
+                Some(data) => self.complete(data),
+                None => 0,
+            };
+        }
+
+        self.cycles += 4;
+        if self.cycles < BIT_CYCLES {
+            return 0;
+        }
+        self.cycles -= BIT_CYCLES;
+        self.bits -= 1;
+        if self.bits > 0 {
+            return 0;
+        }
+
+        // With nothing connected the input line is pulled high.
+        let received = match &mut self.link {
+            Some(link) => link.exchange(self.sb),
+            None => 0xFF,
+        };
+        self.complete(received)
+    }
+
+    fn complete(&mut self, data: u8) -> u8 {
+        self.sb = data;
+        self.sc &= !0x80;
+        bus::INT_SERIAL
+    }
+
+    pub fn transferring(&self) -> bool {
+        self.sc & 0x80 != 0
+    }
+
+    fn internal_clock(&self) -> bool {
+        self.sc & 0x01 != 0
+    }
+
+    // A transfer is pending that the other side has to clock.
+    fn waiting(&self) -> bool {
+        self.transferring() && !self.internal_clock()
+    }
+
+    // Whatever is plugged in stays plugged in; only the port is saved.
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u8(self.sb);
+        state.u8(self.sc);
+        state.u8(self.bits);
+        state.u32(self.cycles);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        let sb = state.u8()?;
+        let sc = state.u8()?;
+        let bits = state.u8()?;
+        let cycles = state.u32()?;
+        // An internal clock transfer always has a bit left to shift.
+        if sc & 0x81 == 0x81 && (!(1..=8).contains(&bits) || cycles >= BIT_CYCLES) {
+            return Err(state::StateError::Invalid("serial"));
+        }
+        self.sb = sb;
+        self.sc = sc;
+        self.bits = bits;
+        self.cycles = cycles;
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+// Super Game Boy: commands arrive as 16 byte packets bit-banged through the
+// P14/P15 select lines of the joypad register. Bulk data (palettes, border
+// tiles, attribute files) is sent by putting it on screen and issuing one of
+// the *_TRN commands, which the SNES side captures at the next VBlank.
+use crate::ppu;
+use crate::state;
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 224;
+
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+const CELLS_X: usize = 20;
+const CELLS_Y: usize = 18;
+const PACKET_LEN: usize = 16;
+const ATTR_FILE_LEN: usize = 90;
+const ATTR_FILES: usize = 45;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const ATTR_TRN: u8 = 0x15;
+const ATTR_SET: u8 = 0x16;
+const MASK_EN: u8 = 0x17;
+
+// SGB palette 1-A, which is what the SNES side starts out with.
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Mask {
+    Cancel,
+    Freeze,
+    Black,
+    Color0,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Transfer {
+    Palettes,
+    Tiles(usize),
+    Border,
+    Attributes,
+}
+
+pub struct Sgb {
+    select: u8,
+    packet: [u8; PACKET_LEN],
+    packet_bit: usize,
+    receiving: bool,
+    command: Vec<u8>,
+
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    attributes: [u8; CELLS_X * CELLS_Y],
+    attribute_files: Vec<[u8; ATTR_FILE_LEN]>,
+
+    border_tiles: Vec<u8>,
+    border_map: [u16; 32 * 28],
+    border_palettes: [[u16; 16]; 4],
+
+    mask: Mask,
+    screen: Vec<u8>,
+    pending_transfer: Option<Transfer>,
+
+    players: usize,
+    player: usize,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Self {
+        Sgb {
+            select: 0x30,
+            packet: [0; PACKET_LEN],
+            packet_bit: 0,
+            receiving: false,
+            command: Vec::new(),
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; 512],
+            attributes: [0; CELLS_X * CELLS_Y],
+            attribute_files: vec![[0; ATTR_FILE_LEN]; ATTR_FILES],
+            border_tiles: vec![0; 256 * 32],
+            border_map: [0; 32 * 28],
+            border_palettes: [[0; 16]; 4],
+            mask: Mask::Cancel,
+            screen: vec![0; ppu::SCREEN_WIDTH * ppu::SCREEN_HEIGHT],
+            pending_transfer: None,
+            players: 1,
+            player: 0,
+        }
+    }
+
+    // Number of controller ports MLT_REQ has enabled: 1, 2 or 4.
+    pub fn players(&self) -> usize {
+        self.players
+    }
+
+    // Port whose buttons the joypad register currently reports.
+    pub fn player(&self) -> usize {
+        self.player
+    }
+
+    pub fn mask(&self) -> Mask {
+        self.mask
+    }
+
+    // Low nibble of P1 when neither button group is selected: the ID of the
+    // current controller, 0xF for player 1 down to 0xC for player 4.
+    pub fn joypad_id(&self) -> u8 {
+        0x0F - self.player as u8
+    }
+
+    pub fn write_joypad(&mut self, value: u8) {
+        let select = value & 0x30;
+        let previous = self.select;
+        self.select = select;
+
+        if previous & 0x20 == 0 && select & 0x20 != 0 && self.players > 1 {
+            self.player = (self.player + 1) % self.players;
+        }
+
+        match select {
+            0x00 => {
+                self.receiving = true;
+                self.packet = [0; PACKET_LEN];
+                self.packet_bit = 0;
+            }
+            0x10 | 0x20 if self.receiving && previous == 0x30 => {
+                self.receive_bit(select == 0x10);
+            }
+            _ => (),
+        }
+    }
+
+    fn receive_bit(&mut self, bit: bool) {
+        if self.packet_bit == PACKET_LEN * 8 {
+            // Stop bit; a packet that doesn't end in 0 is dropped.
+            self.receiving = false;
+            if !bit {
+                self.receive_packet();
+            }
+            return;
+        }
+
+        if bit {
+            self.packet[self.packet_bit / 8] |= 1 << (self.packet_bit % 8);
+        }
+        self.packet_bit += 1;
+    }
+
+    fn receive_packet(&mut self) {
+        if self.command.is_empty() && self.packet[0] & 0x07 == 0 {
+            return;
+        }
+
+        self.command.extend_from_slice(&self.packet);
+        let packets = (self.command[0] & 0x07) as usize;
+        if self.command.len() >= packets * PACKET_LEN {
+            let command = std::mem::take(&mut self.command);
+            self.execute(&command);
+        }
+    }
+
+    fn execute(&mut self, data: &[u8]) {
+        match data[0] >> 3 {
+            PAL01 => self.set_palette_pair(data, 0, 1),
+            PAL23 => self.set_palette_pair(data, 2, 3),
+            PAL03 => self.set_palette_pair(data, 0, 3),
+            PAL12 => self.set_palette_pair(data, 1, 2),
+            ATTR_BLK => self.attr_blk(data),
+            ATTR_LIN => self.attr_lin(data),
+            ATTR_DIV => self.attr_div(data),
+            ATTR_CHR => self.attr_chr(data),
+            PAL_SET => self.pal_set(data),
+            PAL_TRN => self.pending_transfer = Some(Transfer::Palettes),
+            MLT_REQ => self.mlt_req(data[1]),
+            CHR_TRN => self.pending_transfer = Some(Transfer::Tiles((data[1] & 1) as usize)),
+            PCT_TRN => self.pending_transfer = Some(Transfer::Border),
+            ATTR_TRN => self.pending_transfer = Some(Transfer::Attributes),
+            ATTR_SET => self.attr_set(data[1]),
+            MASK_EN => self.mask_en(data[1]),
+            // Sound, SNES program upload and the like have no effect here.
+            _ => (),
+        }
+    }
+
+    fn set_palette_pair(&mut self, data: &[u8], first: usize, second: usize) {
+        let color = |i: usize| u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]);
+
+        let color0 = color(0);
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+        for i in 0..3 {
+            self.palettes[first][i + 1] = color(i + 1);
+            self.palettes[second][i + 1] = color(i + 4);
+        }
+    }
+
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = (data[1] & 0x1F) as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let mut control = set[0] & 0x07;
+            let inside = set[1] & 0x03;
+            let mut border = (set[1] >> 2) & 0x03;
+            let outside = (set[1] >> 4) & 0x03;
+
+            // Setting only the inside or only the outside drags the border
+            // along with it.
+            if control == 0x01 {
+                control |= 0x02;
+                border = inside;
+            } else if control == 0x04 {
+                control |= 0x02;
+                border = outside;
+            }
+
+            let (x1, y1) = ((set[2] & 0x1F) as usize, (set[3] & 0x1F) as usize);
+            let (x2, y2) = ((set[4] & 0x1F) as usize, (set[5] & 0x1F) as usize);
+            for y in 0..CELLS_Y {
+                for x in 0..CELLS_X {
+                    let within_x = x >= x1 && x <= x2;
+                    let within_y = y >= y1 && y <= y2;
+                    let on_edge =
+                        (x == x1 || x == x2 || y == y1 || y == y2) && within_x && within_y;
+                    let cell = &mut self.attributes[y * CELLS_X + x];
+                    if on_edge {
+                        if control & 0x02 != 0 {
+                            *cell = border;
+                        }
+                    } else if within_x && within_y {
+                        if control & 0x01 != 0 {
+                            *cell = inside;
+                        }
+                    } else if control & 0x04 != 0 {
+                        *cell = outside;
+                    }
+                }
+            }
+        }
+    }
+
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for &line in data[2..].iter().take(count) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0x03;
+            if line & 0x80 != 0 {
+                if index < CELLS_Y {
+                    self.attributes[index * CELLS_X..(index + 1) * CELLS_X].fill(palette);
+                }
+            } else if index < CELLS_X {
+                for y in 0..CELLS_Y {
+                    self.attributes[y * CELLS_X + index] = palette;
+                }
+            }
+        }
+    }
+
+    fn attr_div(&mut self, data: &[u8]) {
+        let after = data[1] & 0x03;
+        let before = (data[1] >> 2) & 0x03;
+        let on_line = (data[1] >> 4) & 0x03;
+        let horizontal = data[1] & 0x40 != 0;
+        let split = (data[2] & 0x1F) as usize;
+
+        for y in 0..CELLS_Y {
+            for x in 0..CELLS_X {
+                let position = if horizontal { y } else { x };
+                self.attributes[y * CELLS_X + x] = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+
+    fn attr_chr(&mut self, data: &[u8]) {
+        let mut x = (data[1] as usize).min(CELLS_X - 1);
+        let mut y = (data[2] as usize).min(CELLS_Y - 1);
+        let count = (u16::from_le_bytes([data[3], data[4]]) as usize).min(CELLS_X * CELLS_Y);
+        let vertical = data[5] & 1 != 0;
+
+        for i in 0..count {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            self.attributes[y * CELLS_X + x] = (byte >> (6 - (i % 4) * 2)) & 0x03;
+
+            if vertical {
+                y += 1;
+                if y == CELLS_Y {
+                    y = 0;
+                    x = (x + 1) % CELLS_X;
+                }
+            } else {
+                x += 1;
+                if x == CELLS_X {
+                    x = 0;
+                    y = (y + 1) % CELLS_Y;
+                }
+            }
+        }
+    }
+
+    fn pal_set(&mut self, data: &[u8]) {
+        for i in 0..4 {
+            let index = u16::from_le_bytes([data[1 + i * 2], data[2 + i * 2]]) & 0x01FF;
+            self.palettes[i] = self.system_palettes[index as usize];
+        }
+        let color0 = self.palettes[0][0];
+        for palette in self.palettes.iter_mut() {
+            palette[0] = color0;
+        }
+
+        let flags = data[9];
+        if flags & 0x80 != 0 {
+            self.apply_attribute_file(flags & 0x3F);
+        }
+        if flags & 0x40 != 0 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    fn attr_set(&mut self, flags: u8) {
+        self.apply_attribute_file(flags & 0x3F);
+        if flags & 0x40 != 0 {
+            self.mask = Mask::Cancel;
+        }
+    }
+
+    fn apply_attribute_file(&mut self, file: u8) {
+        let Some(file) = self.attribute_files.get(file as usize) else {
+            return;
+        };
+        for (i, cell) in self.attributes.iter_mut().enumerate() {
+            *cell = (file[i / 4] >> (6 - (i % 4) * 2)) & 0x03;
+        }
+    }
+
+    fn mlt_req(&mut self, value: u8) {
+        self.players = match value & 0x03 {
+            0x01 => 2,
+            0x03 => 4,
+            _ => 1,
+        };
+        self.player = 0;
+    }
+
+    fn mask_en(&mut self, value: u8) {
+        self.mask = match value & 0x03 {
+            0x01 => Mask::Freeze,
+            0x02 => Mask::Black,
+            0x03 => Mask::Color0,
+            _ => Mask::Cancel,
+        };
+    }
+
+    // Called when the game's frame is complete: performs any pending VRAM
+    // transfer and latches the image unless the screen is frozen.
+    pub fn end_frame(&mut self, ppu: &ppu::Ppu) {
+        if let Some(transfer) = self.pending_transfer.take() {
+            self.transfer(transfer, &ppu.transfer_data());
+        }
+        if self.mask != Mask::Freeze {
+            self.screen.copy_from_slice(ppu.shades());
+        }
+    }
+
+    fn transfer(&mut self, transfer: Transfer, data: &[u8]) {
+        match transfer {
+            Transfer::Palettes => {
+                for (palette, bytes) in self.system_palettes.iter_mut().zip(data.chunks_exact(8)) {
+                    for (i, color) in palette.iter_mut().enumerate() {
+                        *color = u16::from_le_bytes([bytes[i * 2], bytes[i * 2 + 1]]);
+                    }
+                }
+            }
+            Transfer::Tiles(bank) => {
+                let start = bank * 128 * 32;
+                self.border_tiles[start..start + 128 * 32].copy_from_slice(&data[..128 * 32]);
+            }
+            Transfer::Border => {
+                for (entry, bytes) in self.border_map.iter_mut().zip(data.chunks_exact(2)) {
+                    *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+                }
+                let colors = &data[0x800..0x880];
+                for (i, palette) in self.border_palettes.iter_mut().enumerate() {
+                    for (j, color) in palette.iter_mut().enumerate() {
+                        let offset = (i * 16 + j) * 2;
+                        *color = u16::from_le_bytes([colors[offset], colors[offset + 1]]);
+                    }
+                }
+            }
+            Transfer::Attributes => {
+                for (file, bytes) in self.attribute_files.iter_mut().zip(data.chunks_exact(90)) {
+                    file.copy_from_slice(bytes);
+                }
+            }
+        }
+    }
+
+    // Composes the 256x224 RGB555 picture the SNES puts out: the colorized
+    // game screen in the middle and the border on top of it.
+    pub fn render(&self, out: &mut [u16]) {
+        let backdrop = self.palettes[0][0];
+        out[..SCREEN_WIDTH * SCREEN_HEIGHT].fill(backdrop);
+
+        for y in 0..ppu::SCREEN_HEIGHT {
+            for x in 0..ppu::SCREEN_WIDTH {
+                let color = match self.mask {
+                    Mask::Black => 0x0000,
+                    Mask::Color0 => backdrop,
+                    Mask::Cancel | Mask::Freeze => {
+                        let shade = self.screen[y * ppu::SCREEN_WIDTH + x] as usize;
+                        let palette = self.attributes[(y / 8) * CELLS_X + x / 8] as usize;
+                        self.palettes[palette][shade]
+                    }
+                };
+                out[(GAME_Y + y) * SCREEN_WIDTH + GAME_X + x] = color;
+            }
+        }
+
+        self.render_border(out);
+    }
+
+    fn render_border(&self, out: &mut [u16]) {
+        for (i, &entry) in self.border_map.iter().enumerate() {
+            let tile = (entry & 0xFF) as usize * 32;
+            let palette = &self.border_palettes[((entry >> 10) & 0x03) as usize];
+            let flip_x = entry & 0x4000 != 0;
+            let flip_y = entry & 0x8000 != 0;
+
+            for row in 0..8 {
+                let line = if flip_y { 7 - row } else { row };
+                let planes = [
+                    self.border_tiles[tile + line * 2],
+                    self.border_tiles[tile + line * 2 + 1],
+                    self.border_tiles[tile + 16 + line * 2],
+                    self.border_tiles[tile + 16 + line * 2 + 1],
+                ];
+
+                for col in 0..8 {
+                    let bit = if flip_x { col } else { 7 - col };
+                    let color = planes
+                        .iter()
+                        .enumerate()
+                        .fold(0, |c, (plane, byte)| c | ((byte >> bit) & 1) << plane);
+                    if color == 0 {
+                        continue;
+                    }
+                    let x = (i % 32) * 8 + col;
+                    let y = (i / 32) * 8 + row;
+                    out[y * SCREEN_WIDTH + x] = palette[color as usize];
+                }
+            }
+        }
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u8(self.select);
+        state.bytes(&self.packet);
+        state.usize(self.packet_bit);
+        state.bool(self.receiving);
+        state.usize(self.command.len());
+        state.bytes(&self.command);
+        for palette in self.palettes.iter().chain(&self.system_palettes) {
+            state.words(palette);
+        }
+        state.bytes(&self.attributes);
+        for file in &self.attribute_files {
+            state.bytes(file);
+        }
+        state.bytes(&self.border_tiles);
+        state.words(&self.border_map);
+        for palette in &self.border_palettes {
+            state.words(palette);
+        }
+        state.u8(self.mask as u8);
+        state.bytes(&self.screen);
+        state.u8(match self.pending_transfer {
+            None => 0,
+            Some(Transfer::Palettes) => 1,
+            Some(Transfer::Tiles(_)) => 2,
+            Some(Transfer::Border) => 3,
+            Some(Transfer::Attributes) => 4,
+        });
+        if let Some(Transfer::Tiles(offset)) = self.pending_transfer {
+            state.usize(offset);
+        }
+        state.usize(self.players);
+        state.usize(self.player);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.select = state.u8()?;
+        state.bytes(&mut self.packet)?;
+        self.packet_bit = state.usize()?;
+        self.receiving = state.bool()?;
+        self.command = vec![0; state.usize()?];
+        state.bytes(&mut self.command)?;
+        for palette in self.palettes.iter_mut().chain(&mut self.system_palettes) {
+            state.words(palette)?;
+        }
+        state.bytes(&mut self.attributes)?;
+        for file in &mut self.attribute_files {
+            state.bytes(file)?;
+        }
+        state.bytes(&mut self.border_tiles)?;
+        state.words(&mut self.border_map)?;
+        for palette in &mut self.border_palettes {
+            state.words(palette)?;
+        }
+        self.mask = match state.u8()? {
+            0 => Mask::Cancel,
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Color0,
+            _ => return Err(state::StateError::Invalid("SGB mask")),
+        };
+        state.bytes(&mut self.screen)?;
+        self.pending_transfer = match state.u8()? {
+            0 => None,
+            1 => Some(Transfer::Palettes),
+            2 => Some(Transfer::Tiles(state.usize()?)),
+            3 => Some(Transfer::Border),
+            4 => Some(Transfer::Attributes),
+            _ => return Err(state::StateError::Invalid("SGB transfer")),
+        };
+        self.players = state.usize()?;
+        self.player = state.usize()?;
+        if self.packet_bit > PACKET_LEN * 8 || self.player >= self.players {
+            return Err(state::StateError::Invalid("SGB controller"));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::fmt;
+
+// Save states are every component's fields, written one after another in a
+// fixed order behind a short header. Nothing is tagged, so any change to what
+// a component saves must bump VERSION.
+const MAGIC: &[u8; 4] = b"GBST";
+const VERSION: u8 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StateError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    Truncated,
+    // A value no component could have saved, or one that doesn't fit the
+    // console it's being loaded into.
+    Invalid(&'static str),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::BadMagic => write!(f, "not a save state"),
+            StateError::UnsupportedVersion(version) => {
+                write!(f, "unsupported save state version {}", version)
+            }
+            StateError::Truncated => write!(f, "save state is truncated"),
+            StateError::Invalid(what) => write!(f, "save state has an invalid {}", what),
+        }
+    }
+}
+
+pub struct Writer {
+    data: Vec<u8>,
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let mut data = MAGIC.to_vec();
+        data.push(VERSION);
+        Writer { data }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub fn u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn i32(&mut self, value: i32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u32(value as u32);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn words(&mut self, words: &[u16]) {
+        for &word in words {
+            self.u16(word);
+        }
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, StateError> {
+        let mut reader = Reader { data };
+        let mut magic = [0; 4];
+        reader.bytes(&mut magic).map_err(|_| StateError::BadMagic)?;
+        if &magic != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let version = reader.u8()?;
+        if version != VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+        Ok(reader)
+    }
+
+    // Everything should have been read once the last component is loaded.
+    pub fn finish(self) -> Result<(), StateError> {
+        if !self.data.is_empty() {
+            return Err(StateError::Invalid("length"));
+        }
+        Ok(())
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        let mut bytes = [0; N];
+        self.bytes(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn bool(&mut self) -> Result<bool, StateError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StateError::Invalid("flag")),
+        }
+    }
+
+    pub fn u16(&mut self) -> Result<u16, StateError> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub fn u32(&mut self) -> Result<u32, StateError> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub fn i32(&mut self) -> Result<i32, StateError> {
+        self.take().map(i32::from_le_bytes)
+    }
+
+    pub fn u64(&mut self) -> Result<u64, StateError> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, StateError> {
+        self.u32().map(|value| value as usize)
+    }
+
+    // Fills `bytes` completely.
+    pub fn bytes(&mut self, bytes: &mut [u8]) -> Result<(), StateError> {
+        if self.data.len() < bytes.len() {
+            return Err(StateError::Truncated);
+        }
+        let (head, rest) = self.data.split_at(bytes.len());
+        bytes.copy_from_slice(head);
+        self.data = rest;
+        Ok(())
+    }
+
+    pub fn words(&mut self, words: &mut [u16]) -> Result<(), StateError> {
+        for word in words {
+            *word = self.u16()?;
+        }
+        Ok(())
+    }
+}
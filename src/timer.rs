@@ -0,0 +1,199 @@
+use crate::bus;
+use crate::state;
+
+// Bit of the internal counter whose falling edge clocks the APU frame
+// sequencer (bit 4 of DIV).
+const APU_BIT: u16 = 1 << 12;
+
+pub struct Timer {
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
+    reload_pending: bool,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
+            reload_pending: false,
+        }
+    }
+
+    pub fn read(&self, address: u16) -> Result<u8, bus::BusError> {
+        let value = match address {
+            0xFF04 => (self.counter >> 8) as u8,
+            0xFF05 => self.tima,
+            0xFF06 => self.tma,
+            0xFF07 => 0xF8 | self.tac,
+            _ => return Err(bus::BusError::Read(address)),
+        };
+        Ok(value)
+    }
+
+    // Returns true if the write caused a falling edge on the frame sequencer
+    // bit, which happens when DIV is reset while it is set.
+    pub fn write(&mut self, address: u16, value: u8) -> Result<bool, bus::BusError> {
+        match address {
+            0xFF04 => {
+                let old = self.counter;
+                self.counter = 0;
+                self.check_tima_edge(old);
+                return Ok(old & APU_BIT != 0);
+            }
+            0xFF05 => {
+                // Writing TIMA in the cycle it overflowed cancels the reload.
+                self.tima = value;
+                self.reload_pending = false;
+            }
+            0xFF06 => self.tma = value,
+            0xFF07 => {
+                let old_bit = self.tima_bit_set(self.counter);
+                self.tac = value & 0x07;
+                if old_bit && !self.tima_bit_set(self.counter) {
+                    self.increment_tima();
+                }
+            }
+            _ => return Err(bus::BusError::Write(address, value)),
+        }
+        Ok(false)
+    }
+
+    // Advances the timer by `cycles` M-cycles and returns the interrupts
+    // raised. Only the cycles around an overflow are run one at a time.
+    pub fn advance(&mut self, mut cycles: u64) -> u8 {
+        let mut interrupts = 0;
+        while cycles > 0 {
+            let skipped = if self.reload_pending {
+                0
+            } else {
+                match self.cycles_to_overflow() {
+                    Some(overflow) => cycles.min(overflow) - 1,
+                    None => cycles,
+                }
+            };
+            self.skip(skipped);
+            cycles -= skipped;
+            if cycles > 0 {
+                interrupts |= self.tick();
+                cycles -= 1;
+            }
+        }
+        interrupts
+    }
+
+    // M-cycles until the timer raises its interrupt, if it's going to.
+    pub fn cycles_to_interrupt(&self) -> Option<u64> {
+        if self.reload_pending {
+            return Some(1);
+        }
+        self.cycles_to_overflow().map(|cycles| cycles + 1)
+    }
+
+    // M-cycles until the frame sequencer bit next falls.
+    pub fn cycles_to_apu_edge(&self) -> u64 {
+        cycles_to_edge(self.counter, APU_BIT, 1)
+    }
+
+    // M-cycles until the cycle in which TIMA overflows.
+    fn cycles_to_overflow(&self) -> Option<u64> {
+        let bit = self.tima_bit()?;
+        Some(cycles_to_edge(self.counter, bit, 0x100 - self.tima as u64))
+    }
+
+    // Runs M-cycles that are known not to overflow TIMA, all at once.
+    fn skip(&mut self, cycles: u64) {
+        let old = self.counter as u64;
+        let new = old + cycles * 4;
+        if let Some(bit) = self.tima_bit() {
+            let period = bit as u64 * 2;
+            let increments = new / period - old / period;
+            self.tima += increments as u8;
+        }
+        self.counter = new as u16;
+    }
+
+    // Advances the timer by one M-cycle and returns the interrupts raised.
+    fn tick(&mut self) -> u8 {
+        let mut interrupts = 0;
+        if self.reload_pending {
+            self.reload_pending = false;
+            self.tima = self.tma;
+            interrupts |= bus::INT_TIMER;
+        }
+
+        let old = self.counter;
+        self.counter = self.counter.wrapping_add(4);
+        self.check_tima_edge(old);
+        interrupts
+    }
+
+    fn check_tima_edge(&mut self, old: u16) {
+        if self.tima_bit_set(old) && !self.tima_bit_set(self.counter) {
+            self.increment_tima();
+        }
+    }
+
+    fn tima_bit_set(&self, counter: u16) -> bool {
+        self.tima_bit().is_some_and(|bit| counter & bit != 0)
+    }
+
+    // Counter bit whose falling edge increments TIMA, while it's enabled.
+    fn tima_bit(&self) -> Option<u16> {
+        if self.tac & 0x04 == 0 {
+            return None;
+        }
+        let bit = match self.tac & 0x03 {
+            0 => 9,
+            1 => 3,
+            2 => 5,
+            _ => 7,
+        };
+        Some(1 << bit)
+    }
+
+    fn increment_tima(&mut self) {
+        let (tima, overflow) = self.tima.overflowing_add(1);
+        self.tima = tima;
+        if overflow {
+            // TIMA reads 0 for one cycle before TMA is loaded.
+            self.reload_pending = true;
+        }
+    }
+
+    pub fn save_state(&self, state: &mut state::Writer) {
+        state.u16(self.counter);
+        state.u8(self.tima);
+        state.u8(self.tma);
+        state.u8(self.tac);
+        state.bool(self.reload_pending);
+    }
+
+    pub fn load_state(&mut self, state: &mut state::Reader) -> Result<(), state::StateError> {
+        self.counter = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.tac = state.u8()?;
+        self.reload_pending = state.bool()?;
+        Ok(())
+    }
+}
+
+// M-cycles until `bit` of a counter starting at `counter` has fallen `edges`
+// times. It falls whenever the counter reaches a multiple of twice the bit.
+fn cycles_to_edge(counter: u16, bit: u16, edges: u64) -> u64 {
+    let period = bit as u64 * 2;
+    let counter = counter as u64;
+    let target = (counter / period + edges) * period;
+    (target - counter).div_ceil(4)
+}
@@ -0,0 +1,145 @@
+use crate::apu;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const HEADER_LEN: u32 = 0x100;
+const VERSION: u32 = 0x171;
+const SAMPLE_RATE: u64 = 44_100;
+const CLOCK_RATE: u64 = 4_194_304;
+
+const CMD_DMG_WRITE: u8 = 0xB3;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC_FRAME: u8 = 0x62;
+const CMD_WAIT_PAL_FRAME: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+// Commands are buffered and written out in blocks of about this size.
+const FLUSH_LEN: usize = 4096;
+
+// Logs APU register writes as a VGM 1.71 file. Waits are counted in
+// 44100 Hz samples, so each write lands on the sample it happened in. The
+// end command and the header are rewritten after every flush so the file
+// stays playable even if the emulator is killed.
+pub struct VgmRecorder {
+    file: Option<File>,
+    buffer: Vec<u8>,
+    data_len: u32,
+    samples: u64,
+}
+
+impl VgmRecorder {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+
+        let mut header = vec![0; HEADER_LEN as usize];
+        header[0x00..0x04].copy_from_slice(b"Vgm ");
+        header[0x08..0x0C].copy_from_slice(&VERSION.to_le_bytes());
+        header[0x34..0x38].copy_from_slice(&(HEADER_LEN - 0x34).to_le_bytes());
+        header[0x80..0x84].copy_from_slice(&(CLOCK_RATE as u32).to_le_bytes());
+        file.write_all(&header)?;
+
+        let mut recorder = VgmRecorder {
+            file: Some(file),
+            buffer: Vec::new(),
+            data_len: 0,
+            samples: 0,
+        };
+        recorder.flush()?;
+        Ok(recorder)
+    }
+
+    // Waits until the sample `cycle` falls in.
+    fn wait_until(&mut self, cycle: u64) {
+        let sample = cycle * SAMPLE_RATE / CLOCK_RATE;
+        if sample > self.samples {
+            self.wait(sample - self.samples);
+        }
+    }
+
+    fn wait(&mut self, mut samples: u64) {
+        self.samples += samples;
+        while samples > 0 {
+            let step = match samples {
+                735 => {
+                    self.buffer.push(CMD_WAIT_NTSC_FRAME);
+                    735
+                }
+                882 => {
+                    self.buffer.push(CMD_WAIT_PAL_FRAME);
+                    882
+                }
+                1..=16 => {
+                    self.buffer.push(CMD_WAIT_SHORT | (samples - 1) as u8);
+                    samples
+                }
+                _ => {
+                    let step = samples.min(u16::MAX as u64);
+                    self.buffer.push(CMD_WAIT);
+                    self.buffer.extend_from_slice(&(step as u16).to_le_bytes());
+                    step
+                }
+            };
+            samples -= step;
+        }
+    }
+
+    // Appends the buffered commands over the previous end command, then
+    // brings the header up to date.
+    fn flush(&mut self) -> io::Result<()> {
+        let Some(file) = &mut self.file else {
+            return Ok(());
+        };
+
+        self.buffer.push(CMD_END);
+        file.seek(SeekFrom::Start((HEADER_LEN + self.data_len) as u64))?;
+        file.write_all(&self.buffer)?;
+        self.data_len += self.buffer.len() as u32 - 1;
+        self.buffer.clear();
+
+        let eof = HEADER_LEN + self.data_len + 1 - 4;
+        file.seek(SeekFrom::Start(0x04))?;
+        file.write_all(&eof.to_le_bytes())?;
+        file.seek(SeekFrom::Start(0x18))?;
+        file.write_all(&(self.samples as u32).to_le_bytes())?;
+        Ok(())
+    }
+
+    fn flush_or_close(&mut self) {
+        if let Err(err) = self.flush() {
+            eprintln!("vgm: logging stopped: {}", err);
+            self.file = None;
+        }
+    }
+}
+
+impl apu::RegisterLog for VgmRecorder {
+    fn log_write(&mut self, cycle: u64, address: u16, value: u8) {
+        if self.file.is_none() {
+            return;
+        }
+
+        self.wait_until(cycle);
+        self.buffer
+            .extend_from_slice(&[CMD_DMG_WRITE, (address - 0xFF10) as u8, value]);
+
+        if self.buffer.len() >= FLUSH_LEN {
+            self.flush_or_close();
+        }
+    }
+
+    // Without this the silence after the last write would be cut off.
+    fn finish(&mut self, cycle: u64) {
+        if self.file.is_some() {
+            self.wait_until(cycle);
+            self.flush_or_close();
+        }
+    }
+}
+
+impl Drop for VgmRecorder {
+    fn drop(&mut self) {
+        self.flush_or_close();
+    }
+}
@@ -0,0 +1,118 @@
+use crate::apu;
+use std::fs::File;
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const HEADER_LEN: u32 = 44;
+
+// 16-bit PCM WAV file. The header is kept up to date after every write so the
+// file stays playable even if the emulator is killed.
+pub struct WavWriter {
+    file: File,
+    data_len: u32,
+    bytes: Vec<u8>,
+}
+
+impl WavWriter {
+    pub fn create(path: &Path, sample_rate: u32, channels: u16) -> io::Result<Self> {
+        let mut file = File::create(path)?;
+        let block_align = channels * 2;
+
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&(HEADER_LEN - 8).to_le_bytes());
+        header.extend_from_slice(b"WAVE");
+        header.extend_from_slice(b"fmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&16u16.to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&0u32.to_le_bytes());
+        file.write_all(&header)?;
+
+        Ok(WavWriter {
+            file,
+            data_len: 0,
+            bytes: Vec::new(),
+        })
+    }
+
+    pub fn write_samples(&mut self, samples: &[i16]) -> io::Result<()> {
+        self.bytes.clear();
+        for sample in samples {
+            self.bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+        self.file.write_all(&self.bytes)?;
+        self.data_len += self.bytes.len() as u32;
+
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(HEADER_LEN - 8 + self.data_len).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(HEADER_LEN as u64 - 4))?;
+        self.file.write_all(&self.data_len.to_le_bytes())?;
+        self.file.seek(SeekFrom::End(0))?;
+        Ok(())
+    }
+}
+
+// Audio sink that records the mix, and optionally each channel as a stem
+// next to it: `out.wav` gets `out.ch1.wav` through `out.ch4.wav`.
+pub struct WavRecorder {
+    mix: Option<WavWriter>,
+    stems: Vec<Option<WavWriter>>,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path, sample_rate: u32, stems: bool) -> io::Result<Self> {
+        let mix = WavWriter::create(path, sample_rate, 2)?;
+        let stems = if stems {
+            (1..=4)
+                .map(|channel| {
+                    WavWriter::create(&stem_path(path, channel), sample_rate, 2).map(Some)
+                })
+                .collect::<io::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(WavRecorder {
+            mix: Some(mix),
+            stems,
+        })
+    }
+}
+
+impl apu::AudioSink for WavRecorder {
+    fn push_samples(&mut self, samples: &[i16]) {
+        write_or_close(&mut self.mix, samples);
+    }
+
+    fn wants_channels(&self) -> bool {
+        !self.stems.is_empty()
+    }
+
+    fn push_channel_samples(&mut self, channel: usize, samples: &[i16]) {
+        if let Some(stem) = self.stems.get_mut(channel) {
+            write_or_close(stem, samples);
+        }
+    }
+}
+
+// Recording is best effort: after a write fails, that file is left as it is.
+fn write_or_close(writer: &mut Option<WavWriter>, samples: &[i16]) {
+    if let Some(w) = writer {
+        if let Err(err) = w.write_samples(samples) {
+            eprintln!("wav: recording stopped: {}", err);
+            *writer = None;
+        }
+    }
+}
+
+fn stem_path(path: &Path, channel: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}.ch{}.wav", stem, channel))
+}
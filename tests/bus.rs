@@ -0,0 +1,73 @@
+// Addresses nothing answers on read high and ignore writes, the way games
+// that poke at CGB registers on a DMG expect. Also covers OAM DMA, which
+// the bus runs itself.
+
+use gb::bus::{Bus, BusError};
+
+const UNUSED: [u16; 9] = [
+    0xFEA0, 0xFEFF, 0xFF03, 0xFF08, 0xFF0E, 0xFF4C, 0xFF4F, 0xFF50, 0xFF7F,
+];
+
+#[test]
+fn unused_addresses_are_open_bus() {
+    let mut bus = Bus::new();
+    bus.disable_boot_rom();
+    for address in UNUSED {
+        assert_eq!(bus.read(address), Ok(0xFF), "{:#06x}", address);
+        assert_eq!(bus.write(address, 0x12), Ok(()), "{:#06x}", address);
+        assert_eq!(bus.read(address), Ok(0xFF), "{:#06x}", address);
+    }
+}
+
+#[test]
+fn cgb_registers_are_ignored() {
+    let mut bus = Bus::new();
+    bus.disable_boot_rom();
+    // KEY1, VBK, HDMA, the color palettes and SVBK.
+    for address in [0xFF4D, 0xFF4F, 0xFF51, 0xFF55, 0xFF68, 0xFF69, 0xFF70] {
+        assert_eq!(bus.write(address, 0x01), Ok(()));
+        assert_eq!(bus.read(address), Ok(0xFF));
+    }
+    // Nothing else moved.
+    assert_eq!(bus.read(0xFF0F), Ok(0xE0));
+}
+
+#[test]
+fn missing_boot_rom_is_still_an_error() {
+    let bus = Bus::new();
+    // The built-in boot ROM is only a fragment.
+    assert_eq!(bus.read(0x00F0), Err(BusError::Read(0x00F0)));
+}
+
+#[test]
+fn oam_dma() {
+    let mut bus = Bus::new();
+    bus.disable_boot_rom();
+    for i in 0..0xA0 {
+        bus.write(0xC100 + i, i as u8 ^ 0x5A).unwrap();
+    }
+    bus.write(0xFF46, 0xC1).unwrap();
+    assert_eq!(bus.read(0xFF46), Ok(0xC1));
+    // OAM is cut off from the CPU until the last byte is copied.
+    for _ in 0..0x9F {
+        bus.tick();
+    }
+    bus.write(0xFE00, 0x00).unwrap();
+    assert_eq!(bus.read(0xFE00), Ok(0xFF));
+    bus.tick();
+    for i in 0..0xA0 {
+        assert_eq!(bus.read(0xFE00 + i), Ok(i as u8 ^ 0x5A));
+    }
+}
+
+#[test]
+fn oam_dma_from_echo_ram() {
+    let mut bus = Bus::new();
+    bus.disable_boot_rom();
+    bus.write(0xDE10, 0x42).unwrap();
+    bus.write(0xFF46, 0xFE).unwrap();
+    for _ in 0..0xA0 {
+        bus.tick();
+    }
+    assert_eq!(bus.read(0xFE10), Ok(0x42));
+}
@@ -0,0 +1,125 @@
+// Checks the memory bank controllers map the ROM and RAM banks games
+// select, and fall back to the first 32 KiB for the ones that aren't
+// emulated.
+
+use gb::cart::Cart;
+
+// A ROM of `banks` 16 KiB banks, each filled with its own number, with
+// the given controller and RAM size in the header.
+fn rom(cart_type: u8, banks: usize, ram_size: u8) -> Vec<u8> {
+    let mut rom = Vec::new();
+    for bank in 0..banks {
+        rom.extend(std::iter::repeat_n(bank as u8, 0x4000));
+    }
+    rom[0x147] = cart_type;
+    rom[0x149] = ram_size;
+    rom
+}
+
+#[test]
+fn plain_rom() {
+    let mut cart = Cart::new(rom(0x00, 4, 0));
+    cart.write(0x2000, 3);
+    assert_eq!(cart.read(0x0000), 0);
+    assert_eq!(cart.read(0x4000), 1);
+    cart.write(0xA000, 0x12);
+    assert_eq!(cart.read(0xA000), 0xFF);
+}
+
+#[test]
+fn unsupported_controller_sees_32_kib() {
+    // MBC2.
+    let mut cart = Cart::new(rom(0x05, 8, 0));
+    cart.write(0x2100, 5);
+    assert_eq!(cart.read(0x4000), 1);
+}
+
+#[test]
+fn mbc1() {
+    let mut cart = Cart::new(rom(0x03, 128, 0x03));
+    assert_eq!(cart.read(0x4000), 1);
+    cart.write(0x2000, 0x05);
+    assert_eq!(cart.read(0x4000), 5);
+    // Bank 0 can't be selected at 0x4000, and only 5 bits are kept.
+    cart.write(0x2000, 0x00);
+    assert_eq!(cart.read(0x4000), 1);
+    cart.write(0x2000, 0x3F);
+    assert_eq!(cart.read(0x4000), 0x1F);
+    // The upper bits extend the bank, but only reach 0x0000 in mode 1.
+    cart.write(0x4000, 0x02);
+    assert_eq!(cart.read(0x4000), 0x5F);
+    assert_eq!(cart.read(0x0000), 0);
+    cart.write(0x6000, 0x01);
+    assert_eq!(cart.read(0x0000), 0x40);
+
+    // RAM reads high until enabled, and in mode 1 is banked too.
+    assert_eq!(cart.read(0xA000), 0xFF);
+    cart.write(0x0000, 0x0A);
+    cart.write(0xA000, 0x22);
+    cart.write(0x4000, 0x01);
+    cart.write(0xA000, 0x11);
+    assert_eq!(cart.read(0xA000), 0x11);
+    cart.write(0x6000, 0x00);
+    assert_eq!(cart.read(0xA000), 0x00);
+    cart.write(0x6000, 0x01);
+    cart.write(0x4000, 0x02);
+    assert_eq!(cart.read(0xA000), 0x22);
+    cart.write(0x0000, 0x00);
+    assert_eq!(cart.read(0xA000), 0xFF);
+}
+
+#[test]
+fn mbc1_bank_wraps_to_rom_size() {
+    let mut cart = Cart::new(rom(0x01, 4, 0));
+    cart.write(0x2000, 0x06);
+    assert_eq!(cart.read(0x4000), 2);
+}
+
+#[test]
+fn mbc3() {
+    let mut cart = Cart::new(rom(0x10, 128, 0x03));
+    cart.write(0x2000, 0x7F);
+    assert_eq!(cart.read(0x4000), 0x7F);
+    cart.write(0x2000, 0x00);
+    assert_eq!(cart.read(0x4000), 1);
+
+    cart.write(0x0000, 0x0A);
+    cart.write(0x4000, 0x03);
+    cart.write(0xA000, 0x33);
+    cart.write(0x4000, 0x00);
+    assert_eq!(cart.read(0xA000), 0x00);
+    cart.write(0x4000, 0x03);
+    assert_eq!(cart.read(0xA000), 0x33);
+
+    // The clock's seconds register only shows a write once latched.
+    cart.write(0x4000, 0x08);
+    cart.write(0xA000, 0x2A);
+    assert_eq!(cart.read(0xA000), 0x00);
+    cart.write(0x6000, 0x00);
+    cart.write(0x6000, 0x01);
+    assert_eq!(cart.read(0xA000), 0x2A);
+}
+
+#[test]
+fn mbc5() {
+    let mut rom = rom(0x1B, 512, 0x04);
+    rom[0x123 * 0x4000] = 0xAA;
+    let mut cart = Cart::new(rom);
+    // Bank 0 can be mapped at 0x4000, and bit 8 comes from 0x3000.
+    cart.write(0x2000, 0x00);
+    assert_eq!(cart.read(0x4000), 0);
+    cart.write(0x2000, 0x23);
+    cart.write(0x3000, 0x01);
+    assert_eq!(cart.read(0x4000), 0xAA);
+    assert_eq!(cart.read(0x7FFF), 0x23);
+    cart.write(0x3000, 0x00);
+    assert_eq!(cart.read(0x4000), 0x23);
+
+    cart.write(0x0000, 0x0A);
+    cart.write(0x4000, 0x0F);
+    cart.write(0xBFFF, 0x55);
+    cart.write(0x4000, 0x00);
+    assert_eq!(cart.read(0xBFFF), 0x00);
+    cart.write(0x4000, 0x0F);
+    assert_eq!(cart.read(0xBFFF), 0x55);
+}
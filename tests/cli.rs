@@ -0,0 +1,154 @@
+// Runs the gb binary the way a user would and checks how it treats its
+// arguments and the files it leaves behind.
+
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+fn gb(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_gb"))
+        .args(args)
+        .output()
+        .expect("gb runs")
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gb-cli-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Powers the APU, sets the master volume and spins.
+fn write_rom(dir: &std::path::Path) -> String {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x10A].copy_from_slice(&[
+        0x3E, 0x80, 0xE0, 0x26, // ld a,$80; ldh (NR52),a
+        0x3E, 0x77, 0xE0, 0x24, // ld a,$77; ldh (NR50),a
+        0x18, 0xFE, // jr @
+    ]);
+    let path = dir.join("spin.gb");
+    std::fs::write(&path, rom).unwrap();
+    path.to_str().unwrap().to_string()
+}
+
+fn assert_usage_error(args: &[&str], message: &str) {
+    let output = gb(args);
+    assert_eq!(output.status.code(), Some(2), "{:?}", args);
+    assert!(stderr(&output).contains(message), "{}", stderr(&output));
+}
+
+#[test]
+fn help() {
+    let output = gb(&["--help"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("usage: gb"));
+}
+
+#[test]
+fn bad_arguments() {
+    assert_usage_error(&["--bogus", "x.gb"], "unknown option --bogus");
+    assert_usage_error(&["x.gb", "--frames"], "--frames needs a value");
+    assert_usage_error(&["x.gb", "y.gb"], "unexpected argument y.gb");
+    assert_usage_error(&["--headless"], "no ROM given");
+    assert_usage_error(
+        &["--screenshot", "out.png", "x.gb"],
+        "--screenshot needs --frames",
+    );
+}
+
+#[test]
+fn only_one_serial_option() {
+    assert_usage_error(
+        &["--serial-stdout", "--printer", "prints", "x.gb"],
+        "--printer can't be used with --serial-stdout",
+    );
+    assert_usage_error(
+        &["--link-connect", "a:1", "--link-listen", "b:2", "x.gb"],
+        "--link-connect can't be used with --link-listen",
+    );
+}
+
+#[test]
+fn bad_values() {
+    let dir = temp_dir("values");
+    let rom = write_rom(&dir);
+    for (option, value) in [
+        ("--model", "gba"),
+        ("--palette", "sideways"),
+        ("--frames", "-1"),
+    ] {
+        let output = gb(&[option, value, &rom]);
+        assert_eq!(output.status.code(), Some(1));
+        let expected = format!("invalid value for {}: {}", option, value);
+        assert!(stderr(&output).contains(&expected), "{}", stderr(&output));
+    }
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn recordings_are_finished() {
+    let dir = temp_dir("recordings");
+    let rom = write_rom(&dir);
+    let wav = dir.join("out.wav");
+    let vgm = dir.join("out.vgm");
+    let output = gb(&[
+        "--headless",
+        "--frames",
+        "10",
+        "--wav",
+        wav.to_str().unwrap(),
+        "--vgm",
+        vgm.to_str().unwrap(),
+        &rom,
+    ]);
+    assert!(output.status.success(), "{}", stderr(&output));
+
+    let wav = std::fs::read(wav).unwrap();
+    assert_eq!(
+        u32::from_le_bytes(wav[4..8].try_into().unwrap()) as usize,
+        wav.len() - 8
+    );
+    assert!(wav.len() > 44);
+    let vgm = std::fs::read(vgm).unwrap();
+    assert_eq!(
+        u32::from_le_bytes(vgm[4..8].try_into().unwrap()) as usize,
+        vgm.len() - 4
+    );
+    assert_eq!(&vgm[0x100..0x106], &[0xB3, 0x16, 0x80, 0xB3, 0x14, 0x77]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+// Once whatever reads the trace goes away the emulator stops, and still
+// finishes its recordings.
+#[test]
+fn closed_trace_finishes_recordings() {
+    let dir = temp_dir("trace");
+    let rom = write_rom(&dir);
+    let vgm = dir.join("out.vgm");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gb"))
+        .args([
+            "--headless",
+            "--trace",
+            "--vgm",
+            vgm.to_str().unwrap(),
+            &rom,
+        ])
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    for _ in 0..10 {
+        lines.next().unwrap().unwrap();
+    }
+    drop(lines);
+    assert!(child.wait().unwrap().success());
+
+    let vgm = std::fs::read(vgm).unwrap();
+    assert_eq!(&vgm[0x100..0x106], &[0xB3, 0x16, 0x80, 0xB3, 0x14, 0x77]);
+    let _ = std::fs::remove_dir_all(&dir);
+}
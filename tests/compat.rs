@@ -0,0 +1,150 @@
+// Checks the palettes a CGB picks for DMG carts against what the boot ROM
+// is known to choose, by title and by keys held.
+
+use gb::cart::Cart;
+use gb::compat::{self, KeyCombo, Selection, GRAYSCALE};
+use gb::model::Model;
+
+fn cart(title: &[u8], old_licensee: u8, new_licensee: &[u8; 2]) -> Cart {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x134 + title.len()].copy_from_slice(title);
+    rom[0x144..0x146].copy_from_slice(new_licensee);
+    rom[0x14B] = old_licensee;
+    Cart::new(rom)
+}
+
+fn nintendo(title: &[u8]) -> Cart {
+    cart(title, 0x01, b"00")
+}
+
+// A title whose checksum is `checksum`, with `letter` fourth.
+fn title_with(checksum: u8, letter: u8) -> [u8; 16] {
+    let mut title = [0; 16];
+    title[3] = letter;
+    title[0] = checksum.wrapping_sub(letter);
+    title
+}
+
+#[test]
+fn known_titles() {
+    assert_eq!(
+        compat::for_cart(&nintendo(b"POKEMON RED")).bg,
+        [0x7FFF, 0x421F, 0x1CF2, 0x0000]
+    );
+    assert_eq!(
+        compat::for_cart(&nintendo(b"TETRIS")).bg,
+        [0x7FFF, 0x03FF, 0x001F, 0x0000]
+    );
+}
+
+#[test]
+fn checksum_lookup() {
+    // TETRIS sums to 0xDB, the sixth entry.
+    assert_eq!(nintendo(b"TETRIS").title_checksum(), 0xDB);
+    assert_eq!(
+        compat::for_cart(&nintendo(b"TETRIS")),
+        compat::combination(3)
+    );
+    // A checksum that isn't in the table gets the default.
+    assert_eq!(
+        compat::for_cart(&nintendo(&title_with(0x02, b'A'))),
+        compat::combination(0)
+    );
+}
+
+#[test]
+fn fourth_letter_picks_between_shared_checksums() {
+    for (letter, combination) in [(b'B', 36), (b'U', 17), (b'R', 29), (b'Z', 0)] {
+        let cart = nintendo(&title_with(0xB3, letter));
+        assert_eq!(
+            compat::for_cart(&cart),
+            compat::combination(combination),
+            "{}",
+            letter as char
+        );
+    }
+    // Checksums only used once match whatever the fourth letter is.
+    assert_eq!(
+        compat::for_cart(&nintendo(&title_with(0xDB, b'Z'))),
+        compat::combination(3)
+    );
+}
+
+#[test]
+fn other_licensees_get_the_default() {
+    let default = compat::combination(0);
+    assert_eq!(compat::for_cart(&cart(b"TETRIS", 0x08, b"00")), default);
+    assert_eq!(compat::for_cart(&cart(b"TETRIS", 0x33, b"08")), default);
+    // The new licensee code counts when the old one says to use it.
+    assert_eq!(
+        compat::for_cart(&cart(b"TETRIS", 0x33, b"01")),
+        compat::combination(3)
+    );
+}
+
+#[test]
+fn key_combinations() {
+    let all = |colors: [u16; 4]| compat::CompatPalette {
+        bg: colors,
+        obj0: colors,
+        obj1: colors,
+    };
+    // Green, grayscale, inverted and yellow, from the boot ROM's list.
+    assert_eq!(
+        compat::for_keys(KeyCombo::Right),
+        all([0x7FFF, 0x03EA, 0x011F, 0x0000])
+    );
+    assert_eq!(compat::for_keys(KeyCombo::LeftB), GRAYSCALE);
+    assert_eq!(
+        compat::for_keys(KeyCombo::RightB),
+        all([0x0000, 0x4200, 0x037F, 0x7FFF])
+    );
+    assert_eq!(
+        compat::for_keys(KeyCombo::DownB).bg,
+        [0x7FFF, 0x03FF, 0x012F, 0x0000]
+    );
+    // Every combination is one of the boot ROM's.
+    let combinations: Vec<_> = (0..compat::combination_count())
+        .map(compat::combination)
+        .collect();
+    for keys in [
+        KeyCombo::Right,
+        KeyCombo::Left,
+        KeyCombo::Up,
+        KeyCombo::Down,
+        KeyCombo::RightA,
+        KeyCombo::LeftA,
+        KeyCombo::UpA,
+        KeyCombo::DownA,
+        KeyCombo::RightB,
+        KeyCombo::LeftB,
+        KeyCombo::UpB,
+        KeyCombo::DownB,
+    ] {
+        assert!(combinations.contains(&compat::for_keys(keys)), "{:?}", keys);
+    }
+}
+
+#[test]
+fn selections() {
+    assert_eq!("auto".parse(), Ok(Selection::Auto));
+    assert_eq!("Left+B".parse(), Ok(Selection::Keys(KeyCombo::LeftB)));
+    assert_eq!("50".parse(), Ok(Selection::Combination(50)));
+    assert!("51".parse::<Selection>().is_err());
+    assert!("sideways".parse::<Selection>().is_err());
+
+    let tetris = nintendo(b"TETRIS");
+    assert_eq!(
+        compat::select(&tetris, Selection::Keys(KeyCombo::LeftB)),
+        GRAYSCALE
+    );
+    // Only a CGB colorizes, and only carts without CGB support.
+    assert_eq!(
+        compat::palette(Model::Dmg, &tetris, Selection::Auto),
+        GRAYSCALE
+    );
+    assert_eq!(
+        compat::palette(Model::Cgb, &tetris, Selection::Auto),
+        compat::combination(3)
+    );
+}
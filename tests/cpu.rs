@@ -0,0 +1,113 @@
+// Checks the register file: pairs read back what was written, F never holds
+// anything in its low nibble, and 8-bit arithmetic wraps.
+
+use gb::bus::BusError;
+use gb::cpu::{Cpu, MemoryBus};
+
+// 64 KB of RAM and nothing else.
+struct Ram(Vec<u8>);
+
+impl MemoryBus for Ram {
+    fn read(&mut self, address: u16) -> Result<u8, BusError> {
+        Ok(self.0[address as usize])
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        self.0[address as usize] = value;
+        Ok(())
+    }
+
+    fn tick(&mut self) {}
+
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    fn acknowledge_interrupt(&mut self, _mask: u8) {}
+}
+
+// A CPU about to run `program`, placed at 0, with the stack at the top.
+fn loaded(program: &[u8]) -> Cpu<Ram> {
+    let mut memory = vec![0; 0x10000];
+    memory[..program.len()].copy_from_slice(program);
+    let mut cpu = Cpu::new(Ram(memory));
+    cpu.set_sp(0xFFFE);
+    // Fetches the first opcode.
+    cpu.step().unwrap();
+    cpu
+}
+
+fn run(cpu: &mut Cpu<Ram>, instructions: usize) {
+    for _ in 0..instructions {
+        cpu.step().unwrap();
+    }
+}
+
+#[test]
+fn pairs_round_trip() {
+    let mut cpu = loaded(&[]);
+    for value in [0x0000, 0x1234, 0xA55A, 0xFFFF] {
+        cpu.set_bc(value);
+        cpu.set_de(value ^ 0xFFFF);
+        cpu.set_hl(value.rotate_left(4));
+        cpu.set_sp(value.rotate_right(4));
+        cpu.set_af(value & 0xFFF0);
+        assert_eq!(cpu.bc(), value);
+        assert_eq!(cpu.de(), value ^ 0xFFFF);
+        assert_eq!(cpu.hl(), value.rotate_left(4));
+        assert_eq!(cpu.sp(), value.rotate_right(4));
+        assert_eq!(cpu.af(), value & 0xFFF0);
+    }
+}
+
+#[test]
+fn f_low_nibble_reads_zero() {
+    let mut cpu = loaded(&[]);
+    cpu.set_af(0x12FF);
+    assert_eq!(cpu.af(), 0x12F0);
+    cpu.set_af(0x340F);
+    assert_eq!(cpu.af(), 0x3400);
+
+    // POP AF drops the bits too, and PUSH AF doesn't bring them back.
+    #[rustfmt::skip]
+    let mut cpu = loaded(&[
+        0x01, 0xFF, 0x56, // ld bc,$56ff
+        0xC5,             // push bc
+        0xF1,             // pop af
+        0xF5,             // push af
+        0xD1,             // pop de
+    ]);
+    run(&mut cpu, 3);
+    assert_eq!(cpu.af(), 0x56F0);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.de(), 0x56F0);
+}
+
+#[test]
+fn arithmetic_wraps() {
+    #[rustfmt::skip]
+    let mut cpu = loaded(&[
+        0x0E, 0xFF, // ld c,$ff
+        0x0C,       // inc c
+        0x05,       // dec b
+        0x3E, 0xFF, // ld a,$ff
+        0xC6, 0x01, // add a,1
+        0x1B,       // dec de
+        0x23,       // inc hl
+    ]);
+    cpu.set_bc(0x0000);
+    cpu.set_de(0x0000);
+    cpu.set_hl(0xFFFF);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.bc(), 0x0000);
+    // Z and H, with C untouched.
+    assert_eq!(cpu.af() & 0xF0, 0xA0);
+    run(&mut cpu, 1);
+    assert_eq!(cpu.bc(), 0xFF00);
+    assert_eq!(cpu.af() & 0xF0, 0x60);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.af(), 0x00B0);
+    run(&mut cpu, 2);
+    assert_eq!(cpu.de(), 0xFFFF);
+    assert_eq!(cpu.hl(), 0x0000);
+}
@@ -0,0 +1,53 @@
+// Checks the trace line and that printing it leaves the run alone.
+
+use gb::{debug, GameBoy};
+
+fn gameboy() -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    #[rustfmt::skip]
+    rom[0x100..0x10B].copy_from_slice(&[
+        0x3E, 0x91, 0xE0, 0x40, // ld a,$91; ldh (LCDC),a
+        0xF0, 0x44,             // loop: ldh a,(LY)
+        0xEA, 0x00, 0xC0,       // ld ($c000),a
+        0x18, 0xF9,             // jr loop
+    ]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(rom);
+    gb.skip_boot();
+    gb
+}
+
+#[test]
+fn trace_line() {
+    // The first step only fetches the opcode at 0x100.
+    let mut gb = gameboy();
+    gb.step().unwrap();
+    assert_eq!(
+        debug::trace(gb.cpu()),
+        "0100: LD A,$91             af=01b0 bc=0013 de=00d8 hl=014d sp=fffe cycle=1"
+    );
+    gb.step().unwrap();
+    let line = debug::trace(gb.cpu());
+    assert!(line.starts_with("0102: LDH ($FF00+$40),A "), "{}", line);
+    assert!(line.contains(" af=91b0 "), "{}", line);
+}
+
+#[test]
+fn tracing_changes_nothing() {
+    let mut traced = gameboy();
+    let mut lines = 0;
+    for _ in 0..3 {
+        traced
+            .run_frame_with(|gb| {
+                debug::trace(gb.cpu());
+                lines += 1;
+            })
+            .unwrap();
+    }
+    let mut plain = gameboy();
+    for _ in 0..3 {
+        plain.run_frame().unwrap();
+    }
+    assert!(lines > 1000);
+    assert_eq!(traced.save_state(), plain.save_state());
+}
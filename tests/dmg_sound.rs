@@ -0,0 +1,86 @@
+// Runs blargg's dmg_sound test ROMs, which check the APU's registers, length
+// counters and the wave RAM quirks, and report over the serial port.
+//
+// The ROMs aren't distributed with the emulator, so this is opt-in: point
+// DMG_SOUND at the rom_singles directory and run
+//
+//     cargo test --test dmg_sound -- --ignored
+
+use gb::serial::SerialLink;
+use gb::GameBoy;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// The slowest of the tests finishes in well under a minute.
+const MAX_FRAMES: u32 = 60 * 60;
+
+struct Capture(Rc<RefCell<Vec<u8>>>);
+
+impl SerialLink for Capture {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.0.borrow_mut().push(data);
+        0xFF
+    }
+
+    fn poll(&mut self, _data: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[test]
+#[ignore]
+fn dmg_sound() {
+    let dir = PathBuf::from(std::env::var_os("DMG_SOUND").expect("DMG_SOUND is not set"));
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("dmg_sound: can't read {}: {}", dir.display(), err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gb"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "dmg_sound: no ROMs in {}", dir.display());
+
+    let mut failed = Vec::new();
+    for path in &paths {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let output = run(std::fs::read(path).expect("dmg_sound: can't read ROM"));
+        if !output.contains("Passed") {
+            eprintln!("dmg_sound: {}:\n{}", name, output.trim_end());
+            failed.push(name);
+        }
+    }
+    assert!(
+        failed.is_empty(),
+        "dmg_sound: {} of {} failed: {}",
+        failed.len(),
+        paths.len(),
+        failed.join(", ")
+    );
+}
+
+// Returns what the ROM printed by the time it finished or gave up.
+fn run(rom: Vec<u8>) -> String {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let mut gb = GameBoy::new();
+    gb.load_rom(rom);
+    gb.skip_boot();
+    gb.bus_mut()
+        .serial_mut()
+        .connect(Box::new(Capture(Rc::clone(&output))));
+
+    for _ in 0..MAX_FRAMES {
+        if let Err(err) = gb.run_frame() {
+            return format!(
+                "{}\nstopped: {}",
+                String::from_utf8_lossy(&output.borrow()),
+                err
+            );
+        }
+        let text = String::from_utf8_lossy(&output.borrow()).into_owned();
+        if text.contains("Passed") || text.contains("Failed") {
+            return text;
+        }
+    }
+    let text = String::from_utf8_lossy(&output.borrow()).into_owned();
+    format!("{}\ntimed out", text)
+}
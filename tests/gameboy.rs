@@ -0,0 +1,56 @@
+// The console owns every component, and they can be looked at between
+// steps while a program runs.
+
+use gb::cpu::MemoryBus;
+use gb::GameBoy;
+
+// Turns on the LCD and a fast timer, then spins.
+fn gameboy() -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x134..0x139].copy_from_slice(b"OWNED");
+    #[rustfmt::skip]
+    rom[0x100..0x10A].copy_from_slice(&[
+        0x3E, 0x91, 0xE0, 0x40, // ld a,$91; ldh (LCDC),a
+        0x3E, 0x05, 0xE0, 0x07, // ld a,$05; ldh (TAC),a
+        0x18, 0xFE,             // jr -2
+    ]);
+    let mut gb = GameBoy::new();
+    gb.load_rom(rom);
+    gb.skip_boot();
+    gb
+}
+
+// Each accessor agrees with what the CPU would read at the same moment.
+#[test]
+fn components_between_steps() {
+    let mut gb = gameboy();
+    for steps in [1, 10, 1000, 5000] {
+        for _ in 0..steps {
+            gb.step().unwrap();
+        }
+        let ly = gb.ppu().read(0xFF44).unwrap();
+        let tima = gb.timer().read(0xFF05).unwrap();
+        let nr52 = gb.apu().read(0xFF26).unwrap();
+        let bus = gb.bus_mut();
+        assert_eq!(MemoryBus::read(bus, 0xFF44), Ok(ly), "after {}", steps);
+        assert_eq!(MemoryBus::read(bus, 0xFF05), Ok(tima), "after {}", steps);
+        assert_eq!(MemoryBus::read(bus, 0xFF26), Ok(nr52), "after {}", steps);
+    }
+    assert_eq!(&gb.cart().unwrap().title()[..5], b"OWNED");
+}
+
+#[test]
+fn cpu_can_be_steered() {
+    let mut gb = gameboy();
+    gb.run_frame().unwrap();
+    // PC is past the JR already fetched.
+    assert_eq!(gb.cpu().pc(), 0x0109);
+    // Back to the start, which turns the LCD on again and falls into the
+    // loop.
+    gb.cpu_mut().set_pc(0x0100);
+    gb.cpu_mut().set_bc(0xBEEF);
+    gb.run_frame().unwrap();
+    assert_eq!(gb.cpu().pc(), 0x0109);
+    assert_eq!(gb.cpu().bc(), 0xBEEF);
+    assert_eq!(gb.cpu().af() >> 8, 0x05);
+}
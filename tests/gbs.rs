@@ -0,0 +1,126 @@
+// Parses GBS headers and plays a tiny rip through the driver the synthetic
+// cart runs it with.
+
+use gb::gbs::{Gbs, GbsError};
+use gb::GameBoy;
+
+// A rip loaded at 0x0400. Init stores the song number and sets channel 1's
+// envelope, which only sticks if the APU is already powered. Play counts
+// its calls.
+fn rip(timer_control: u8) -> Vec<u8> {
+    let mut file = vec![0; 0x70];
+    file[0..4].copy_from_slice(b"GBS\x01");
+    file[0x04] = 3; // songs
+    file[0x05] = 2; // first song
+    file[0x06..0x08].copy_from_slice(&0x0400u16.to_le_bytes());
+    file[0x08..0x0A].copy_from_slice(&0x0400u16.to_le_bytes());
+    file[0x0A..0x0C].copy_from_slice(&0x0410u16.to_le_bytes());
+    file[0x0C..0x0E].copy_from_slice(&0xDFFEu16.to_le_bytes());
+    file[0x0E] = 0x00;
+    file[0x0F] = timer_control;
+    file[0x10..0x15].copy_from_slice(b"Title");
+    file[0x30..0x36].copy_from_slice(b"Author");
+    file[0x50..0x70].fill(b'c');
+    let mut code = vec![0; 0x20];
+    #[rustfmt::skip]
+    code[..8].copy_from_slice(&[
+        0xEA, 0x00, 0xC0,       // init: ld ($c000),a
+        0x3E, 0xF0, 0xE0, 0x12, // ld a,$f0; ldh (NR12),a
+        0xC9,                   // ret
+    ]);
+    #[rustfmt::skip]
+    code[0x10..0x15].copy_from_slice(&[
+        0x21, 0x01, 0xC0,       // play: ld hl,$c001
+        0x34,                   // inc (hl)
+        0xC9,                   // ret
+    ]);
+    file.extend(code);
+    file
+}
+
+fn play(gbs: &Gbs, song: u8, frames: usize) -> GameBoy {
+    let mut gb = GameBoy::new();
+    gb.load_cart(gbs.cart(song));
+    gb.bus_mut().disable_boot_rom();
+    gb.cpu_mut().set_pc(gbs.entry_point());
+    for _ in 0..frames {
+        gb.run_frame().unwrap();
+    }
+    gb.sync();
+    gb
+}
+
+#[test]
+fn header() {
+    let gbs = Gbs::parse(&rip(0x00)).unwrap();
+    assert_eq!(gbs.song_count, 3);
+    assert_eq!(gbs.first_song, 2);
+    assert_eq!(gbs.load_address, 0x0400);
+    assert_eq!(gbs.init_address, 0x0400);
+    assert_eq!(gbs.play_address, 0x0410);
+    assert_eq!(gbs.stack_pointer, 0xDFFE);
+    assert!(!gbs.uses_timer());
+    assert!(Gbs::parse(&rip(0x04)).unwrap().uses_timer());
+    // Text fields end at the first NUL, or fill all 32 bytes.
+    assert_eq!(gbs.title, "Title");
+    assert_eq!(gbs.author, "Author");
+    assert_eq!(gbs.copyright, "c".repeat(32));
+}
+
+#[test]
+fn bad_headers() {
+    let file = rip(0x00);
+    assert_eq!(Gbs::parse(&file[..0x6F]).err(), Some(GbsError::TooShort));
+    let mut bad = file.clone();
+    bad[0] = b'X';
+    assert_eq!(Gbs::parse(&bad).err(), Some(GbsError::BadMagic));
+    let mut bad = file.clone();
+    bad[3] = 2;
+    assert_eq!(
+        Gbs::parse(&bad).err(),
+        Some(GbsError::UnsupportedVersion(2))
+    );
+    let mut bad = file;
+    bad[0x06..0x08].copy_from_slice(&0x0100u16.to_le_bytes());
+    assert_eq!(
+        Gbs::parse(&bad).err(),
+        Some(GbsError::BadLoadAddress(0x0100))
+    );
+}
+
+#[test]
+fn rst_vectors_jump_to_the_rip() {
+    let cart = Gbs::parse(&rip(0x00)).unwrap().cart(0);
+    for vector in (0x00..0x40).step_by(8) {
+        let target = 0x0400 + vector;
+        assert_eq!(cart.read(vector), 0xC3);
+        assert_eq!(cart.read(vector + 1), target as u8);
+        assert_eq!(cart.read(vector + 2), (target >> 8) as u8);
+    }
+}
+
+#[test]
+fn driver_plays_on_vblank() {
+    let gbs = Gbs::parse(&rip(0x00)).unwrap();
+    let gb = play(&gbs, 1, 10);
+    let bus = gb.bus();
+    assert_eq!(bus.read(0xC000), Ok(1));
+    // The LCD is on, so play runs once a frame.
+    let calls = bus.read(0xC001).unwrap();
+    assert!((9..=10).contains(&calls), "{}", calls);
+    // The APU was set up as after the boot ROM before init ran.
+    assert_eq!(bus.read(0xFF12), Ok(0xF0));
+    assert_eq!(bus.read(0xFF24), Ok(0x77));
+    assert_eq!(bus.read(0xFF25), Ok(0xF3));
+    assert_eq!(bus.read(0xFF26).map(|nr52| nr52 & 0x80), Ok(0x80));
+}
+
+#[test]
+fn driver_plays_on_the_timer() {
+    // 16384 Hz with TMA 0: 64 calls a second, a bit over one a frame.
+    let gbs = Gbs::parse(&rip(0x07)).unwrap();
+    let gb = play(&gbs, 2, 10);
+    assert_eq!(gb.bus().read(0xC000), Ok(2));
+    let calls = gb.bus().read(0xC001).unwrap();
+    assert!((10..=11).contains(&calls), "{}", calls);
+}
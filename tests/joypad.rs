@@ -0,0 +1,135 @@
+// Reads P1 with every combination of select lines and buttons, and checks
+// which changes request the joypad interrupt.
+
+use gb::bus::INT_JOYPAD;
+use gb::joypad::{Joypad, PORTS};
+use gb::{Button, GameBoy};
+
+const BUTTONS: [Button; 8] = [
+    Button::Right,
+    Button::Left,
+    Button::Up,
+    Button::Down,
+    Button::A,
+    Button::B,
+    Button::Select,
+    Button::Start,
+];
+
+fn pressing(buttons: u8) -> Joypad {
+    let mut joypad = Joypad::new();
+    for (bit, &button) in BUTTONS.iter().enumerate() {
+        joypad.set_button(0, button, buttons & 1 << bit != 0);
+    }
+    joypad.tick();
+    joypad
+}
+
+#[test]
+fn select_lines() {
+    for buttons in 0..=255u8 {
+        let mut joypad = pressing(buttons);
+        let directions = !buttons & 0x0F;
+        let actions = !buttons >> 4;
+        for (select, lines) in [
+            (0x30, 0x0F),
+            (0x20, directions),
+            (0x10, actions),
+            (0x00, directions & actions),
+        ] {
+            joypad.write(select);
+            assert_eq!(
+                joypad.read(),
+                0xC0 | select | lines,
+                "buttons {:08b} select {:02X}",
+                buttons,
+                select
+            );
+        }
+    }
+}
+
+#[test]
+fn only_the_select_lines_are_written() {
+    let mut joypad = pressing(0xFF);
+    joypad.write(0xCF);
+    assert_eq!(joypad.read(), 0xC0);
+    joypad.write(0x3F);
+    assert_eq!(joypad.read(), 0xFF);
+}
+
+#[test]
+fn press_interrupts_only_on_a_selected_line() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x20);
+    // Buttons aren't selected, so pressing one leaves the lines high.
+    joypad.set_button(0, Button::A, true);
+    assert_eq!(joypad.tick(), 0);
+    joypad.set_button(0, Button::Right, true);
+    assert!(joypad.interrupt_pending());
+    assert_eq!(joypad.tick(), INT_JOYPAD);
+    assert_eq!(joypad.tick(), 0);
+    // Releasing takes a line from low to high, which doesn't count.
+    joypad.set_button(0, Button::Right, false);
+    assert_eq!(joypad.tick(), 0);
+}
+
+#[test]
+fn held_line_doesnt_interrupt_again() {
+    // A line already low stays low when its other button is pressed.
+    let mut joypad = Joypad::new();
+    joypad.write(0x00);
+    joypad.set_button(0, Button::Right, true);
+    assert_eq!(joypad.tick(), INT_JOYPAD);
+    joypad.set_button(0, Button::A, true);
+    assert_eq!(joypad.tick(), 0);
+    joypad.set_button(0, Button::Left, true);
+    assert_eq!(joypad.tick(), INT_JOYPAD);
+}
+
+#[test]
+fn selecting_a_held_group_interrupts() {
+    let mut joypad = Joypad::new();
+    joypad.set_button(0, Button::Start, true);
+    assert_eq!(joypad.tick(), 0);
+    joypad.write(0x20);
+    assert_eq!(joypad.tick(), 0);
+    joypad.write(0x10);
+    assert_eq!(joypad.tick(), INT_JOYPAD);
+    // Deselecting raises the line again.
+    joypad.write(0x30);
+    assert_eq!(joypad.tick(), 0);
+    joypad.release_all();
+    joypad.write(0x10);
+    assert_eq!(joypad.tick(), 0);
+}
+
+#[test]
+fn ports() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x20);
+    joypad.set_button(PORTS - 1, Button::Down, true);
+    assert!(joypad.is_pressed(PORTS - 1, Button::Down));
+    assert!(!joypad.is_pressed(0, Button::Down));
+    assert_eq!(joypad.read(), 0xEF);
+    assert_eq!(joypad.tick(), 0);
+    joypad.set_port(PORTS - 1);
+    assert_eq!(joypad.read(), 0xE7);
+    assert_eq!(joypad.tick(), INT_JOYPAD);
+}
+
+#[test]
+fn console_sees_presses() {
+    let mut gb = GameBoy::new();
+    gb.load_rom(vec![0; 0x8000]);
+    gb.skip_boot();
+    gb.bus_mut().write(0xFF00, 0x10).unwrap();
+    gb.bus_mut().write(0xFF0F, 0x00).unwrap();
+    gb.set_button(Button::B, true);
+    gb.run_frame().unwrap();
+    assert_eq!(gb.bus().read(0xFF00), Ok(0xDD));
+    assert_eq!(
+        gb.bus().read(0xFF0F).map(|i| i & INT_JOYPAD),
+        Ok(INT_JOYPAD)
+    );
+}
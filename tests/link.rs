@@ -0,0 +1,95 @@
+// Two consoles joined by a link cable swap a byte each way, then swap
+// which of them drives the clock and do it again.
+
+use gb::{link, GameBoy};
+
+// Each side stores what it received at 0xC000 and 0xC001.
+#[rustfmt::skip]
+const LEADER: [u8; 40] = [
+    0x3E, 0x42,       // ld a, 0x42
+    0xE0, 0x01,       // ldh (SB), a
+    0x3E, 0x81,       // ld a, 0x81
+    0xE0, 0x02,       // ldh (SC), a
+    0xF0, 0x02,       // ldh a, (SC)
+    0xCB, 0x7F,       // bit 7, a
+    0x20, 0xFA,       // jr nz, -6
+    0xF0, 0x01,       // ldh a, (SB)
+    0xEA, 0x00, 0xC0, // ld (0xC000), a
+    0x3E, 0x24,       // ld a, 0x24
+    0xE0, 0x01,       // ldh (SB), a
+    0x3E, 0x80,       // ld a, 0x80
+    0xE0, 0x02,       // ldh (SC), a
+    0xF0, 0x02,       // ldh a, (SC)
+    0xCB, 0x7F,       // bit 7, a
+    0x20, 0xFA,       // jr nz, -6
+    0xF0, 0x01,       // ldh a, (SB)
+    0xEA, 0x01, 0xC0, // ld (0xC001), a
+    0x18, 0xFE,       // jr -2
+];
+
+// Waits a little before clocking the second transfer, so the leader is
+// listening by then.
+#[rustfmt::skip]
+const FOLLOWER: [u8; 45] = [
+    0x3E, 0x99,       // ld a, 0x99
+    0xE0, 0x01,       // ldh (SB), a
+    0x3E, 0x80,       // ld a, 0x80
+    0xE0, 0x02,       // ldh (SC), a
+    0xF0, 0x02,       // ldh a, (SC)
+    0xCB, 0x7F,       // bit 7, a
+    0x20, 0xFA,       // jr nz, -6
+    0xF0, 0x01,       // ldh a, (SB)
+    0xEA, 0x00, 0xC0, // ld (0xC000), a
+    0x06, 0x00,       // ld b, 0
+    0x05,             // dec b
+    0x20, 0xFD,       // jr nz, -3
+    0x3E, 0x66,       // ld a, 0x66
+    0xE0, 0x01,       // ldh (SB), a
+    0x3E, 0x81,       // ld a, 0x81
+    0xE0, 0x02,       // ldh (SC), a
+    0xF0, 0x02,       // ldh a, (SC)
+    0xCB, 0x7F,       // bit 7, a
+    0x20, 0xFA,       // jr nz, -6
+    0xF0, 0x01,       // ldh a, (SB)
+    0xEA, 0x01, 0xC0, // ld (0xC001), a
+    0x18, 0xFE,       // jr -2
+];
+
+fn console(program: &[u8]) -> GameBoy {
+    let mut rom = vec![0; 0x8000];
+    rom[0x0100..0x0100 + program.len()].copy_from_slice(program);
+    let mut gb = GameBoy::new();
+    gb.load_rom(rom);
+    gb.skip_boot();
+    gb
+}
+
+fn received(gb: &GameBoy) -> [u8; 2] {
+    [0xC000, 0xC001].map(|address| gb.bus().read(address).unwrap())
+}
+
+#[test]
+fn bytes_cross_both_ways() {
+    let mut leader = console(&LEADER);
+    let mut follower = console(&FOLLOWER);
+    link::connect(&mut leader, &mut follower);
+
+    // A transfer takes 1024 M-cycles, and the follower waits another 1024
+    // between them.
+    link::run_linked(&mut leader, &mut follower, 8192).unwrap();
+
+    assert_eq!(received(&leader), [0x99, 0x66]);
+    assert_eq!(received(&follower), [0x42, 0x24]);
+}
+
+#[test]
+fn unplugged_line_reads_high() {
+    let mut leader = console(&LEADER);
+    let mut follower = console(&FOLLOWER);
+    link::run_linked(&mut leader, &mut follower, 8192).unwrap();
+
+    // Without a cable the leader clocks in 0xFF and the follower never
+    // completes a transfer.
+    assert_eq!(received(&leader)[0], 0xFF);
+    assert_eq!(received(&follower), [0x00, 0x00]);
+}
@@ -0,0 +1,116 @@
+// Talks to the printer the way a game does, a byte at a time over the link
+// cable, and checks the status it reports and the pages it writes.
+
+use gb::printer::Printer;
+use gb::serial::SerialLink;
+use std::path::PathBuf;
+
+const STATUS_CHECKSUM_ERROR: u8 = 1 << 0;
+const STATUS_PRINTING: u8 = 1 << 1;
+const STATUS_UNPROCESSED: u8 = 1 << 3;
+const STATUS_PACKET_ERROR: u8 = 1 << 4;
+
+const WIDTH: usize = 160;
+
+// Sends a packet with the given checksum and returns the printer's ID and
+// status, shifted back during the last two bytes.
+fn send_with_checksum(
+    printer: &mut Printer,
+    command: u8,
+    compression: u8,
+    data: &[u8],
+    checksum: u16,
+) -> (u8, u8) {
+    let len = data.len() as u16;
+    let mut packet = vec![0x88, 0x33, command, compression];
+    packet.extend_from_slice(&len.to_le_bytes());
+    packet.extend_from_slice(data);
+    packet.extend_from_slice(&checksum.to_le_bytes());
+    for byte in packet {
+        assert_eq!(printer.exchange(byte), 0x00);
+    }
+    (printer.exchange(0x00), printer.exchange(0x00))
+}
+
+fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> u8 {
+    let header = [
+        command,
+        compression,
+        data.len() as u8,
+        (data.len() >> 8) as u8,
+    ];
+    let checksum = header
+        .iter()
+        .chain(data)
+        .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+    let (alive, status) = send_with_checksum(printer, command, compression, data, checksum);
+    assert_eq!(alive, 0x81);
+    status
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gb-printer-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn compressed_data_with_default_palette() {
+    // Two rows of tiles: the first all color 3 as three repeated runs, the
+    // second all color 1 as three literal runs.
+    let mut data = vec![0xFF, 0xFF, 0xFF, 0xFF, 0x80 | 60, 0xFF];
+    for count in [128, 128, 64] {
+        data.push(count as u8 - 1);
+        for _ in 0..count / 2 {
+            data.extend_from_slice(&[0xFF, 0x00]);
+        }
+    }
+
+    let dir = output_dir("compressed");
+    let mut printer = Printer::new(dir.clone());
+    assert_eq!(send(&mut printer, 0x01, 0, &[]), 0);
+    assert_eq!(send(&mut printer, 0x04, 1, &data), STATUS_UNPROCESSED);
+    assert_eq!(send(&mut printer, 0x04, 0, &[]), STATUS_UNPROCESSED);
+    // One copy, no margin before and one after, palette 0.
+    let status = send(&mut printer, 0x02, 0, &[1, 0x01, 0x00, 0x40]);
+    assert_eq!(status & STATUS_PRINTING, STATUS_PRINTING);
+
+    let mut page = vec![0x00; 8 * WIDTH];
+    page.extend(vec![0xAA; 8 * WIDTH]);
+    page.extend(vec![0xFF; 16 * WIDTH]);
+    let expected = gb::png::encode(WIDTH as u32, 32, gb::png::ColorType::Gray, &page);
+    assert_eq!(std::fs::read(dir.join("print-001.png")).unwrap(), expected);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn bad_checksum_is_rejected() {
+    let dir = output_dir("checksum");
+    let mut printer = Printer::new(dir.clone());
+    let data = [0xFF; 640];
+    let (alive, status) = send_with_checksum(&mut printer, 0x04, 0, &data, 0x1234);
+    assert_eq!(alive, 0x81);
+    assert_eq!(status, STATUS_CHECKSUM_ERROR);
+
+    // Nothing was buffered, and the next good packet clears the error.
+    assert_eq!(send(&mut printer, 0x0F, 0, &[]), 0);
+    assert_eq!(send(&mut printer, 0x04, 0, &data), STATUS_UNPROCESSED);
+    drop(printer);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn break_stops_printing() {
+    let dir = output_dir("break");
+    let mut printer = Printer::new(dir.clone());
+    send(&mut printer, 0x04, 0, &[0x00; 640]);
+    let status = send(&mut printer, 0x02, 0, &[1, 0x00, 0xE4, 0x40]);
+    assert_eq!(status & STATUS_PRINTING, STATUS_PRINTING);
+
+    let status = send(&mut printer, 0x08, 0, &[]);
+    assert_eq!(status & (STATUS_PRINTING | STATUS_PACKET_ERROR), 0);
+    assert_eq!(send(&mut printer, 0x0F, 0, &[]), 0);
+    drop(printer);
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,231 @@
+// Runs the same program on the bus as the emulator drives it, where the
+// timer, PPU and APU catch up lazily and halts skip ahead to the next event,
+// and on one that runs every component every M-cycle, and checks the CPU
+// sees the same thing at the same time on both.
+
+use gb::bus::{Bus, BusError};
+use gb::cart::Cart;
+use gb::cpu::{Cpu, MemoryBus};
+use std::time::Instant;
+
+// A bit over 20 frames.
+const CYCLES: u64 = 360_000;
+
+// The bus without the scheduler's shortcuts: everything is caught up after
+// every M-cycle and a halted CPU idles one cycle at a time.
+struct PerCycle(Bus);
+
+impl MemoryBus for PerCycle {
+    fn read(&mut self, address: u16) -> Result<u8, BusError> {
+        MemoryBus::read(&mut self.0, address)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        self.0.write(address, value)
+    }
+
+    fn tick(&mut self) {
+        self.0.tick();
+        self.0.sync();
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.0.pending_interrupts()
+    }
+
+    fn acknowledge_interrupt(&mut self, mask: u8) {
+        self.0.acknowledge_interrupt(mask)
+    }
+}
+
+// Halts with the VBlank, STAT mode 2 and timer interrupts enabled. Their
+// handlers read LY, STAT, DIV and TIMA into registers, and between them
+// change the timer's frequency with every overflow, write DIV every fourth
+// line, and once a frame retrigger a square channel and wait for the frame
+// sequencer to run its length out. TIMA reloads with 0xFE, so it also
+// overflows while the CPU is halted.
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    let mut place = |address: usize, code: &[u8]| {
+        rom[address..address + code.len()].copy_from_slice(code);
+    };
+    place(0x40, &[0xC3, 0x00, 0x02]); // jp vblank
+    place(0x48, &[0xC3, 0x20, 0x02]); // jp stat
+    place(0x50, &[0xC3, 0x40, 0x02]); // jp timer
+    place(0x100, &[0xC3, 0x50, 0x01]); // jp main
+    #[rustfmt::skip]
+    place(0x150, &[
+        0x3E, 0x80, 0xE0, 0x26, // ld a,$80; ldh (NR52),a
+        0x3E, 0x3F, 0xE0, 0x11, // ld a,$3f; ldh (NR11),a
+        0x3E, 0xF0, 0xE0, 0x12, // ld a,$f0; ldh (NR12),a
+        0x3E, 0xC0, 0xE0, 0x14, // ld a,$c0; ldh (NR14),a
+        0x3E, 0x91, 0xE0, 0x40, // ld a,$91; ldh (LCDC),a
+        0x3E, 0x20, 0xE0, 0x41, // ld a,$20; ldh (STAT),a
+        0x3E, 0xFE, 0xE0, 0x06, // ld a,$fe; ldh (TMA),a
+        0x3E, 0x05, 0xE0, 0x07, // ld a,$05; ldh (TAC),a
+        0x3E, 0x07, 0xE0, 0xFF, // ld a,$07; ldh (IE),a
+        0xAF, 0xE0, 0x0F,       // xor a; ldh (IF),a
+        0xFB,                   // ei
+        0x76, 0x00, 0x18, 0xFC, // loop: halt; nop; jr loop
+    ]);
+    #[rustfmt::skip]
+    place(0x200, &[
+        0xF0, 0x44,             // ldh a,(LY)
+        0x0E, 0x40,             // ld c,$40
+        0x0D, 0x20, 0xFD,       // wait: dec c; jr nz,wait
+        0xF0, 0x41,             // ldh a,(STAT)
+        0x3E, 0x3F, 0xE0, 0x11, // ld a,$3f; ldh (NR11),a
+        0x3E, 0xC0, 0xE0, 0x14, // ld a,$c0; ldh (NR14),a
+        0xF0, 0x26, 0xE6, 0x01, // poll: ldh a,(NR52); and 1
+        0x20, 0xFA,             // jr nz,poll
+        0xD9,                   // reti
+    ]);
+    #[rustfmt::skip]
+    place(0x220, &[
+        0xF0, 0x44,             // ldh a,(LY)
+        0x47,                   // ld b,a
+        0xF0, 0x41,             // ldh a,(STAT)
+        0x78, 0xE6, 0x03,       // ld a,b; and 3
+        0x20, 0x02,             // jr nz,done
+        0xE0, 0x04,             // ldh (DIV),a
+        0xD9,                   // done: reti
+    ]);
+    #[rustfmt::skip]
+    place(0x240, &[
+        0xF0, 0x04, 0x57,       // ldh a,(DIV); ld d,a
+        0xF0, 0x05, 0x5F,       // ldh a,(TIMA); ld e,a
+        0xF0, 0x07, 0x3C,       // ldh a,(TAC); inc a
+        0xF6, 0x04, 0xE6, 0x07, // or 4; and 7
+        0xE0, 0x07,             // ldh (TAC),a
+        0xD9,                   // reti
+    ]);
+    rom
+}
+
+fn bus() -> Bus {
+    let mut bus = Bus::new();
+    bus.load_cart(Cart::new(rom()));
+    bus.disable_boot_rom();
+    bus
+}
+
+fn cpu<B: MemoryBus>(bus: B) -> Cpu<B> {
+    let mut cpu = Cpu::new(bus);
+    cpu.set_sp(0xFFFE);
+    cpu.set_pc(0x0100);
+    cpu
+}
+
+// Registers the CPU can see, in a form that's easy to compare.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct State {
+    pc: u16,
+    sp: u16,
+    af: u16,
+    bc: u16,
+    de: u16,
+    hl: u16,
+}
+
+fn state<B: MemoryBus>(cpu: &Cpu<B>) -> State {
+    State {
+        pc: cpu.pc(),
+        sp: cpu.sp(),
+        af: cpu.af(),
+        bc: cpu.bc(),
+        de: cpu.de(),
+        hl: cpu.hl(),
+    }
+}
+
+// Each time the registers change, when that step finished and what they
+// became. Steps spent halted change nothing, so however many M-cycles one
+// idles for, both buses give the same trace. Also returns the most M-cycles
+// a step took.
+fn run<B: MemoryBus>(cpu: &mut Cpu<B>) -> (Vec<(u64, State)>, u64) {
+    let mut trace = vec![(cpu.ticks(), state(cpu))];
+    let mut longest = 0;
+    while cpu.ticks() < CYCLES {
+        longest = longest.max(cpu.step().expect("test program only touches mapped memory"));
+        let state = state(cpu);
+        if trace.last().is_some_and(|&(_, last)| last != state) {
+            trace.push((cpu.ticks(), state));
+        }
+    }
+    (trace, longest)
+}
+
+// What the registers the program doesn't read show once caught up.
+fn registers<B: MemoryBus>(bus: &mut B) -> Vec<u8> {
+    (0xFF04..=0xFF07)
+        .chain([0xFF0F])
+        .chain(0xFF10..=0xFF26)
+        .chain(0xFF40..=0xFF4B)
+        .map(|address| bus.read(address).unwrap_or(0xFF))
+        .collect()
+}
+
+#[test]
+fn lazy_matches_per_cycle() {
+    let mut lazy = cpu(bus());
+    let mut reference = cpu(PerCycle(bus()));
+    let (lazy_trace, longest) = run(&mut lazy);
+    let (reference_trace, _) = run(&mut reference);
+
+    // The lazy side really skipped ahead while halted.
+    assert!(longest > 1);
+    // Every handler ran, the timer's with each of its frequencies.
+    for vector in [0x40, 0x48, 0x50] {
+        assert!(lazy_trace.iter().any(|&(_, state)| state.pc == vector + 1));
+    }
+    for tac in 4..8 {
+        assert!(lazy_trace
+            .iter()
+            .any(|&(_, state)| state.pc == 0x250 && state.af >> 8 == tac));
+    }
+
+    if let Some(index) =
+        (0..lazy_trace.len()).find(|&i| lazy_trace.get(i) != reference_trace.get(i))
+    {
+        panic!(
+            "diverged after {:?}: lazy {:?}, per-cycle {:?}",
+            index.checked_sub(1).map(|i| lazy_trace[i]),
+            lazy_trace.get(index),
+            reference_trace.get(index)
+        );
+    }
+    // The lazy side's last halt may have run past the end, while the other
+    // stopped as soon as it got there.
+    while reference.ticks() < lazy.ticks() {
+        reference.step().unwrap();
+    }
+    assert_eq!(lazy.ticks(), reference.ticks());
+    assert_eq!(state(&lazy), state(&reference));
+    assert_eq!(
+        registers(lazy.bus_mut()),
+        registers(&mut reference.bus_mut().0)
+    );
+    assert_eq!(lazy.bus().frames(), reference.bus().0.frames());
+}
+
+// Not a correctness check: compares how fast the two buses run the
+// program. Run it with
+//
+//     cargo test --release --test scheduler -- --ignored --nocapture
+#[test]
+#[ignore]
+fn benchmark() {
+    let start = Instant::now();
+    run(&mut cpu(PerCycle(bus())));
+    let per_cycle = start.elapsed();
+    let start = Instant::now();
+    run(&mut cpu(bus()));
+    let lazy = start.elapsed();
+    println!(
+        "{} M-cycles: per-cycle {:?}, lazy {:?} ({:.1}x)",
+        CYCLES,
+        per_cycle,
+        lazy,
+        per_cycle.as_secs_f64() / lazy.as_secs_f64()
+    );
+}
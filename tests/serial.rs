@@ -0,0 +1,180 @@
+// Drives the serial port directly: how long an internal clock transfer
+// takes, that an external clock one waits on the other side, and which
+// saved transfers can't be loaded.
+
+use gb::bus::INT_SERIAL;
+use gb::serial::{Serial, SerialLink};
+use gb::state::{Reader, Writer};
+use gb::StateError;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Replies with `reply` and remembers what it was sent. A transfer clocked
+// from this end only arrives after `delay` polls.
+struct Peer {
+    reply: u8,
+    delay: usize,
+    sent: Rc<RefCell<Vec<u8>>>,
+    cancels: Rc<RefCell<usize>>,
+}
+
+impl SerialLink for Peer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.sent.borrow_mut().push(data);
+        self.reply
+    }
+
+    fn poll(&mut self, data: u8) -> Option<u8> {
+        if self.delay > 0 {
+            self.delay -= 1;
+            return None;
+        }
+        self.sent.borrow_mut().push(data);
+        Some(self.reply)
+    }
+
+    fn cancel(&mut self) {
+        *self.cancels.borrow_mut() += 1;
+    }
+}
+
+fn peer(serial: &mut Serial, delay: usize) -> (Rc<RefCell<Vec<u8>>>, Rc<RefCell<usize>>) {
+    let sent = Rc::new(RefCell::new(Vec::new()));
+    let cancels = Rc::new(RefCell::new(0));
+    serial.connect(Box::new(Peer {
+        reply: 0x5A,
+        delay,
+        sent: sent.clone(),
+        cancels: cancels.clone(),
+    }));
+    (sent, cancels)
+}
+
+// Ticks until the serial interrupt, returning how many M-cycles it took.
+fn ticks_until_interrupt(serial: &mut Serial, limit: usize) -> Option<usize> {
+    for tick in 1..=limit {
+        let interrupts = serial.tick();
+        if interrupts != 0 {
+            assert_eq!(interrupts, INT_SERIAL);
+            return Some(tick);
+        }
+    }
+    None
+}
+
+#[test]
+fn internal_clock_takes_eight_bits() {
+    // Eight bits of 512 T-cycles each.
+    let mut serial = Serial::new();
+    serial.write(0xFF01, 0x42).unwrap();
+    serial.write(0xFF02, 0x81).unwrap();
+    assert_eq!(serial.read(0xFF02), Ok(0xFF));
+    assert_eq!(ticks_until_interrupt(&mut serial, 2000), Some(1024));
+    // With nothing connected the byte shifted in is all ones.
+    assert_eq!(serial.read(0xFF01), Ok(0xFF));
+    assert_eq!(serial.read(0xFF02), Ok(0x7F));
+    assert!(!serial.transferring());
+    // The interrupt is raised once.
+    assert_eq!(ticks_until_interrupt(&mut serial, 2000), None);
+
+    let (sent, _) = peer(&mut serial, 0);
+    serial.write(0xFF01, 0x24).unwrap();
+    serial.write(0xFF02, 0x81).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 2000), Some(1024));
+    assert_eq!(serial.read(0xFF01), Ok(0x5A));
+    assert_eq!(*sent.borrow(), [0x24]);
+}
+
+#[test]
+fn restarting_resets_the_count() {
+    let mut serial = Serial::new();
+    serial.write(0xFF02, 0x81).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 1000), None);
+    serial.write(0xFF02, 0x81).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 2000), Some(1024));
+}
+
+#[test]
+fn external_clock_waits_on_the_other_side() {
+    // Nothing connected never clocks the transfer.
+    let mut serial = Serial::new();
+    serial.write(0xFF01, 0x42).unwrap();
+    serial.write(0xFF02, 0x80).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 100_000), None);
+    assert!(serial.transferring());
+    assert_eq!(serial.read(0xFF01), Ok(0x42));
+
+    let mut serial = Serial::new();
+    let (sent, cancels) = peer(&mut serial, 300);
+    serial.write(0xFF01, 0x42).unwrap();
+    serial.write(0xFF02, 0x80).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 2000), Some(301));
+    assert_eq!(serial.read(0xFF01), Ok(0x5A));
+    assert_eq!(serial.read(0xFF02), Ok(0x7E));
+    assert_eq!(*sent.borrow(), [0x42]);
+    assert_eq!(*cancels.borrow(), 0);
+}
+
+#[test]
+fn giving_up_on_the_external_clock_cancels() {
+    let mut serial = Serial::new();
+    let (sent, cancels) = peer(&mut serial, usize::MAX);
+    serial.write(0xFF02, 0x80).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 100), None);
+    serial.write(0xFF02, 0x00).unwrap();
+    assert_eq!(*cancels.borrow(), 1);
+    assert_eq!(ticks_until_interrupt(&mut serial, 100), None);
+    assert!(sent.borrow().is_empty());
+}
+
+fn saved(sb: u8, sc: u8, bits: u8, cycles: u32) -> Vec<u8> {
+    let mut state = Writer::new();
+    state.u8(sb);
+    state.u8(sc);
+    state.u8(bits);
+    state.u32(cycles);
+    state.finish()
+}
+
+fn load(serial: &mut Serial, data: &[u8]) -> Result<(), StateError> {
+    let mut state = Reader::new(data)?;
+    serial.load_state(&mut state)?;
+    state.finish()
+}
+
+#[test]
+fn state_round_trip() {
+    let mut serial = Serial::new();
+    serial.write(0xFF01, 0x42).unwrap();
+    serial.write(0xFF02, 0x81).unwrap();
+    for _ in 0..500 {
+        serial.tick();
+    }
+    let mut state = Writer::new();
+    serial.save_state(&mut state);
+    let state = state.finish();
+
+    let mut loaded = Serial::new();
+    load(&mut loaded, &state).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut loaded, 2000), Some(524));
+}
+
+#[test]
+fn impossible_transfers_are_rejected() {
+    let mut serial = Serial::new();
+    serial.write(0xFF01, 0x42).unwrap();
+    for data in [
+        saved(0, 0x81, 0, 0),
+        saved(0, 0x81, 9, 0),
+        saved(0, 0x81, 8, 512),
+    ] {
+        assert_eq!(load(&mut serial, &data), Err(StateError::Invalid("serial")));
+        assert_eq!(serial.read(0xFF01), Ok(0x42));
+        assert!(!serial.transferring());
+    }
+    // Without an internal clock transfer running the count isn't used.
+    load(&mut serial, &saved(0, 0x80, 0, 0)).unwrap();
+    load(&mut serial, &saved(0, 0x01, 0, 0)).unwrap();
+    load(&mut serial, &saved(0, 0x81, 1, 508)).unwrap();
+    assert_eq!(ticks_until_interrupt(&mut serial, 10), Some(1));
+}
@@ -0,0 +1,453 @@
+// Runs SM83 vectors in the SingleStepTests format
+// (github.com/SingleStepTests/sm83) against the CPU. Each file holds tests
+// for one opcode: the registers and memory before and after, and what the
+// bus saw on every M-cycle.
+//
+// The vectors in tests/sm83/v1 are not from that suite. They're generated
+// by tests/sm83/gen.py, a separate model of the CPU written from the opcode
+// tables, with ten tests for every opcode and CB opcode except the illegal
+// ones, HALT and STOP. To run the real suite, point SM83_TESTS at the v1
+// directory of a checkout of it.
+
+use gb::bus::BusError;
+use gb::cpu::{Cpu, MemoryBus};
+use std::path::PathBuf;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Cycle {
+    Read(u16, u8),
+    Write(u16, u8),
+    Internal,
+}
+
+// 64 KB of RAM and nothing else: no I/O registers and no interrupts.
+struct TestBus {
+    memory: Vec<u8>,
+    cycles: Vec<Cycle>,
+    access: Option<Cycle>,
+}
+
+impl MemoryBus for TestBus {
+    fn read(&mut self, address: u16) -> Result<u8, BusError> {
+        let value = self.memory[address as usize];
+        self.access = Some(Cycle::Read(address, value));
+        Ok(value)
+    }
+
+    fn write(&mut self, address: u16, value: u8) -> Result<(), BusError> {
+        self.memory[address as usize] = value;
+        self.access = Some(Cycle::Write(address, value));
+        Ok(())
+    }
+
+    fn tick(&mut self) {
+        let cycle = self.access.take().unwrap_or(Cycle::Internal);
+        self.cycles.push(cycle);
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
+
+    fn acknowledge_interrupt(&mut self, _mask: u8) {}
+}
+
+#[test]
+fn sm83_single_step() {
+    let dir = match std::env::var_os("SM83_TESTS") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/sm83/v1"),
+    };
+    let entries = std::fs::read_dir(&dir)
+        .unwrap_or_else(|err| panic!("sm83: can't read {}: {}", dir.display(), err));
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    assert!(
+        !paths.is_empty(),
+        "sm83: no test vectors in {}",
+        dir.display()
+    );
+
+    let mut bus = TestBus {
+        memory: vec![0; 0x10000],
+        cycles: Vec::new(),
+        access: None,
+    };
+    let mut run = 0;
+    let mut failed_files = Vec::new();
+    for path in &paths {
+        let text = std::fs::read_to_string(path).expect("sm83: can't read test file");
+        let tests = Json::parse(&text).unwrap_or_else(|err| {
+            panic!("sm83: {}: {}", path.display(), err);
+        });
+
+        let mut failures = 0;
+        for test in tests.items() {
+            run += 1;
+            if let Err(err) = run_test(&mut bus, test) {
+                if failures == 0 {
+                    eprintln!("sm83: {}: {}", test.get("name").as_str(), err);
+                }
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            failed_files.push(format!("{} ({} failed)", name, failures));
+        }
+    }
+
+    eprintln!("sm83: ran {} tests from {} files", run, paths.len());
+    assert!(
+        failed_files.is_empty(),
+        "sm83: failures in {}",
+        failed_files.join(", ")
+    );
+}
+
+fn run_test(bus: &mut TestBus, test: &Json) -> Result<(), String> {
+    let initial = test.get("initial");
+    let expected = test.get("final");
+
+    for cell in initial.get("ram").items() {
+        bus.memory[cell.at(0).as_u64() as usize] = cell.at(1).as_u64() as u8;
+    }
+
+    let result = {
+        let mut cpu = Cpu::new(&mut *bus);
+        cpu.set_pc(initial.get("pc").as_u64() as u16);
+        cpu.set_sp(initial.get("sp").as_u64() as u16);
+        cpu.set_af(pair(initial, "a", "f"));
+        cpu.set_bc(pair(initial, "b", "c"));
+        cpu.set_de(pair(initial, "d", "e"));
+        cpu.set_hl(pair(initial, "h", "l"));
+        cpu.set_ime(initial.get("ime").as_u64() != 0);
+
+        // The vectors start with the opcode already fetched, which takes an
+        // untimed cycle here.
+        let prefetch = cpu.step();
+        cpu.bus_mut().cycles.clear();
+        prefetch
+            .and_then(|_| cpu.step())
+            .map_err(|err| err.to_string())
+            .and_then(|_| check_registers(&cpu, expected))
+    };
+
+    let result = result
+        .and_then(|()| check_memory(bus, expected))
+        .and_then(|()| check_cycles(bus, test.get("cycles")));
+
+    // Leave the bus zeroed for the next test.
+    for cell in initial
+        .get("ram")
+        .items()
+        .iter()
+        .chain(expected.get("ram").items())
+    {
+        bus.memory[cell.at(0).as_u64() as usize] = 0;
+    }
+    for cycle in bus.cycles.drain(..) {
+        if let Cycle::Write(address, _) = cycle {
+            bus.memory[address as usize] = 0;
+        }
+    }
+    result
+}
+
+fn pair(state: &Json, high: &str, low: &str) -> u16 {
+    (state.get(high).as_u64() as u16) << 8 | state.get(low).as_u64() as u16
+}
+
+fn check_registers(cpu: &Cpu<&mut TestBus>, expected: &Json) -> Result<(), String> {
+    let registers = [
+        ("pc", cpu.pc()),
+        ("sp", cpu.sp()),
+        ("af", cpu.af()),
+        ("bc", cpu.bc()),
+        ("de", cpu.de()),
+        ("hl", cpu.hl()),
+    ];
+    for (name, actual) in registers {
+        let wanted = match name {
+            "pc" | "sp" => expected.get(name).as_u64() as u16,
+            _ => {
+                let (high, low) = name.split_at(1);
+                pair(expected, high, low)
+            }
+        };
+        if actual != wanted {
+            return Err(format!(
+                "{} is {:#06x}, expected {:#06x}",
+                name, actual, wanted
+            ));
+        }
+    }
+    if let Json::Number(ime) = expected.get("ime") {
+        if cpu.ime() != (*ime != 0.0) {
+            return Err(format!("ime is {}, expected {}", cpu.ime(), ime));
+        }
+    }
+    Ok(())
+}
+
+fn check_memory(bus: &TestBus, expected: &Json) -> Result<(), String> {
+    for cell in expected.get("ram").items() {
+        let address = cell.at(0).as_u64() as usize;
+        let wanted = cell.at(1).as_u64() as u8;
+        if bus.memory[address] != wanted {
+            return Err(format!(
+                "memory at {:#06x} is {:#04x}, expected {:#04x}",
+                address, bus.memory[address], wanted
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Each cycle is [address, data, "rwm"]: whether the read, write and memory
+// request lines were active, with - for inactive.
+fn check_cycles(bus: &TestBus, expected: &Json) -> Result<(), String> {
+    let wanted: Vec<Cycle> = expected
+        .items()
+        .iter()
+        .map(|cycle| {
+            let address = cycle.at(0).as_u64() as u16;
+            let data = cycle.at(1).as_u64() as u8;
+            match cycle.at(2).as_str().as_bytes() {
+                [b'r', ..] => Cycle::Read(address, data),
+                [_, b'w', ..] => Cycle::Write(address, data),
+                _ => Cycle::Internal,
+            }
+        })
+        .collect();
+    if bus.cycles != wanted {
+        return Err(format!("bus saw {:x?}, expected {:x?}", bus.cycles, wanted));
+    }
+    Ok(())
+}
+
+// Just enough JSON for the test vectors.
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+const NULL: Json = Json::Null;
+
+impl Json {
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut parser = Parser {
+            bytes: text.as_bytes(),
+            pos: 0,
+        };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.pos != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    // Missing keys and indices come back as null.
+    fn get(&self, key: &str) -> &Json {
+        match self {
+            Json::Object(members) => members
+                .iter()
+                .find(|(name, _)| name == key)
+                .map_or(&NULL, |(_, value)| value),
+            _ => &NULL,
+        }
+    }
+
+    fn at(&self, index: usize) -> &Json {
+        match self {
+            Json::Array(items) => items.get(index).unwrap_or(&NULL),
+            _ => &NULL,
+        }
+    }
+
+    fn items(&self) -> &[Json] {
+        match self {
+            Json::Array(items) => items,
+            _ => &[],
+        }
+    }
+
+    // Null, as in the address of an idle cycle, reads as 0.
+    fn as_u64(&self) -> u64 {
+        match self {
+            Json::Number(n) => *n as u64,
+            Json::Bool(b) => *b as u64,
+            _ => 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        match self {
+            Json::String(s) => s,
+            _ => "",
+        }
+    }
+}
+
+struct Parser<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, message: &str) -> String {
+        format!("{} at byte {}", message, self.pos)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.bytes.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), String> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected '{}'", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if !self.bytes[self.pos..].starts_with(word.as_bytes()) {
+            return Err(self.error("unknown literal"));
+        }
+        self.pos += word.len();
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            _ => Err(self.error("expected a value")),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return Err(self.error("expected ',' or '}'")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(self.error("expected ',' or ']'")),
+            }
+        }
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.expect(b'"')?;
+        let mut s = Vec::new();
+        loop {
+            let Some(&byte) = self.bytes.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match byte {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&escape) = self.bytes.get(self.pos) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    match escape {
+                        b'n' => s.push(b'\n'),
+                        b't' => s.push(b'\t'),
+                        b'r' => s.push(b'\r'),
+                        b'b' => s.push(0x08),
+                        b'f' => s.push(0x0C),
+                        b'u' => {
+                            let hex = self
+                                .bytes
+                                .get(self.pos..self.pos + 4)
+                                .and_then(|hex| std::str::from_utf8(hex).ok())
+                                .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                                .ok_or_else(|| self.error("bad \\u escape"))?;
+                            self.pos += 4;
+                            let c = char::from_u32(hex).unwrap_or('\u{FFFD}');
+                            s.extend_from_slice(c.to_string().as_bytes());
+                        }
+                        other => s.push(other),
+                    }
+                }
+                _ => s.push(byte),
+            }
+        }
+        String::from_utf8(s).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.pos;
+        while self
+            .bytes
+            .get(self.pos)
+            .is_some_and(|b| matches!(b, b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9'))
+        {
+            self.pos += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.pos])
+            .ok()
+            .and_then(|text| text.parse().ok())
+            .map(Json::Number)
+            .ok_or_else(|| self.error("bad number"))
+    }
+}
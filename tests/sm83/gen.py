@@ -0,0 +1,389 @@
+# Generates the vectors in v1/: an SM83 model written from the published
+# opcode tables and bus timings, run on random registers and memory, and
+# written out in the SingleStepTests format tests/sm83.rs reads.
+#
+#     python3 tests/sm83/gen.py tests/sm83/v1
+#
+# It covers every opcode except the illegal ones, HALT and STOP, whose
+# behaviour depends on interrupts the test bus doesn't have. The random
+# seed is fixed, so rerunning it reproduces the same files.
+import json, os, random, sys
+
+TESTS_PER_OPCODE = 10
+
+rng = random.Random(0x5183)
+
+def r8(): return rng.randrange(256)
+def r16(): return rng.randrange(65536)
+
+def initial_state():
+    s = {k: r8() for k in "abcdehl"}
+    s["f"] = r8() & 0xF0
+    s["sp"] = r16()
+    s["pc"] = r16()
+    s["ime"] = 0
+    s["ie"] = 0
+    return s
+
+class Collision(Exception):
+    pass
+
+# Random memory that is only made up where the instruction touches it. An
+# instruction that overlaps itself or writes over its own bytes is retried
+# with other random values.
+class Memory:
+    def __init__(self):
+        self.initial = {}
+        self.written = {}
+        self.code = set()
+        self.cycles = []
+
+    def place(self, address, value):
+        address &= 0xFFFF
+        if address in self.initial:
+            raise Collision
+        self.initial[address] = value
+        self.code.add(address)
+
+    def read(self, address):
+        address &= 0xFFFF
+        if address in self.written:
+            value = self.written[address]
+        else:
+            value = self.initial.setdefault(address, r8())
+        self.cycles.append([address, value, "r-m"])
+        return value
+
+    def write(self, address, value):
+        address &= 0xFFFF
+        if address in self.code:
+            raise Collision
+        self.initial.setdefault(address, r8())
+        self.written[address] = value & 0xFF
+        self.cycles.append([address, value & 0xFF, "-wm"])
+
+    def internal(self):
+        self.cycles.append(None)
+
+# Instruction context: the registers and the bus.
+class Cpu:
+    def __init__(self, s, mem):
+        self.s = s
+        self.mem = mem
+
+    def fetch(self):
+        v = self.mem.read(self.s["pc"])
+        self.s["pc"] = (self.s["pc"] + 1) & 0xFFFF
+        return v
+
+    def fetch16(self):
+        lo = self.fetch()
+        return self.fetch() << 8 | lo
+
+    def pair(self, name):
+        if name == "sp":
+            return self.s["sp"]
+        return self.s[name[0]] << 8 | self.s[name[1]]
+
+    def set_pair(self, name, v):
+        v &= 0xFFFF
+        if name == "sp":
+            self.s["sp"] = v
+        elif name == "af":
+            self.s["a"], self.s["f"] = v >> 8, v & 0xF0
+        else:
+            self.s[name[0]], self.s[name[1]] = v >> 8, v & 0xFF
+
+    def flag(self, bit):
+        return self.s["f"] >> bit & 1
+
+    def set_flags(self, z, n, h, c):
+        self.s["f"] = int(bool(z)) << 7 | int(bool(n)) << 6 | int(bool(h)) << 5 | int(bool(c)) << 4
+
+    def condition(self, cc):
+        return [not self.flag(7), self.flag(7), not self.flag(4), self.flag(4)][cc]
+
+    def push(self, v):
+        self.s["sp"] = (self.s["sp"] - 1) & 0xFFFF
+        self.mem.write(self.s["sp"], v >> 8)
+        self.s["sp"] = (self.s["sp"] - 1) & 0xFFFF
+        self.mem.write(self.s["sp"], v & 0xFF)
+
+    def pop(self):
+        lo = self.mem.read(self.s["sp"])
+        self.s["sp"] = (self.s["sp"] + 1) & 0xFFFF
+        hi = self.mem.read(self.s["sp"])
+        self.s["sp"] = (self.s["sp"] + 1) & 0xFFFF
+        return hi << 8 | lo
+
+    # Operand 0-7 as encoded in the opcode: B C D E H L (HL) A.
+    def get_r(self, r):
+        if r == 6:
+            return self.mem.read(self.pair("hl"))
+        return self.s["bcdehl?a"[r]]
+
+    def set_r(self, r, v):
+        if r == 6:
+            self.mem.write(self.pair("hl"), v)
+        else:
+            self.s["bcdehl?a"[r]] = v & 0xFF
+
+def signed(e):
+    return e - 256 if e > 127 else e
+
+def alu(c, op, v):
+    a = c.s["a"]
+    carry = c.flag(4)
+    if op == 0:  # add
+        r = a + v
+        c.set_flags(r & 0xFF == 0, 0, (a & 0xF) + (v & 0xF) > 0xF, r > 0xFF)
+    elif op == 1:  # adc
+        r = a + v + carry
+        c.set_flags(r & 0xFF == 0, 0, (a & 0xF) + (v & 0xF) + carry > 0xF, r > 0xFF)
+    elif op == 2:  # sub
+        r = a - v
+        c.set_flags(r & 0xFF == 0, 1, (a & 0xF) < (v & 0xF), r < 0)
+    elif op == 3:  # sbc
+        r = a - v - carry
+        c.set_flags(r & 0xFF == 0, 1, (a & 0xF) < (v & 0xF) + carry, r < 0)
+    elif op == 4:  # and
+        r = a & v
+        c.set_flags(r == 0, 0, 1, 0)
+    elif op == 5:  # xor
+        r = a ^ v
+        c.set_flags(r == 0, 0, 0, 0)
+    elif op == 6:  # or
+        r = a | v
+        c.set_flags(r == 0, 0, 0, 0)
+    else:  # cp
+        r = a - v
+        c.set_flags(r & 0xFF == 0, 1, (a & 0xF) < (v & 0xF), r < 0)
+        return
+    c.s["a"] = r & 0xFF
+
+# The CB-prefixed rotates and shifts, returning the result and carry.
+def shift(c, op, v):
+    carry = c.flag(4)
+    if op == 0: return (v << 1 | v >> 7) & 0xFF, v >> 7        # rlc
+    if op == 1: return (v >> 1 | v << 7) & 0xFF, v & 1         # rrc
+    if op == 2: return (v << 1 | carry) & 0xFF, v >> 7         # rl
+    if op == 3: return (v >> 1 | carry << 7) & 0xFF, v & 1     # rr
+    if op == 4: return (v << 1) & 0xFF, v >> 7                 # sla
+    if op == 5: return (v >> 1 | v & 0x80), v & 1              # sra
+    if op == 6: return (v << 4 | v >> 4) & 0xFF, 0             # swap
+    return v >> 1, v & 1                                       # srl
+
+def cb(c, op):
+    r = op & 7
+    if op < 0x40:
+        v, carry = shift(c, op >> 3, c.get_r(r))
+        c.set_flags(v == 0, 0, 0, carry)
+        c.set_r(r, v)
+    elif op < 0x80:
+        v = c.get_r(r)
+        c.set_flags(not v >> (op >> 3 & 7) & 1, 0, 1, c.flag(4))
+    elif op < 0xC0:
+        c.set_r(r, c.get_r(r) & ~(1 << (op >> 3 & 7)))
+    else:
+        c.set_r(r, c.get_r(r) | 1 << (op >> 3 & 7))
+
+PAIRS = ["bc", "de", "hl", "sp"]
+STACK_PAIRS = ["bc", "de", "hl", "af"]
+
+def execute(c, op):
+    s, mem = c.s, c.mem
+    if op == 0x00:
+        pass
+    elif op & 0xCF == 0x01:  # ld rr,nn
+        c.set_pair(PAIRS[op >> 4], c.fetch16())
+    elif op & 0xCF == 0x02:  # ld (bc),a / (de),a / (hl+),a / (hl-),a
+        address = c.pair(["bc", "de", "hl", "hl"][op >> 4])
+        mem.write(address, s["a"])
+        if op >= 0x20:
+            c.set_pair("hl", address + (1 if op == 0x22 else -1))
+    elif op & 0xCF == 0x0A:  # ld a,(bc) / (de) / (hl+) / (hl-)
+        address = c.pair(["bc", "de", "hl", "hl"][op >> 4])
+        s["a"] = mem.read(address)
+        if op >= 0x20:
+            c.set_pair("hl", address + (1 if op == 0x2A else -1))
+    elif op & 0xCF == 0x03:  # inc rr
+        c.set_pair(PAIRS[op >> 4], c.pair(PAIRS[op >> 4]) + 1)
+        mem.internal()
+    elif op & 0xCF == 0x0B:  # dec rr
+        c.set_pair(PAIRS[op >> 4], c.pair(PAIRS[op >> 4]) - 1)
+        mem.internal()
+    elif op & 0xC7 == 0x04:  # inc r
+        r = op >> 3
+        v = c.get_r(r)
+        c.set_flags((v + 1) & 0xFF == 0, 0, v & 0xF == 0xF, c.flag(4))
+        c.set_r(r, v + 1)
+    elif op & 0xC7 == 0x05:  # dec r
+        r = op >> 3
+        v = c.get_r(r)
+        c.set_flags((v - 1) & 0xFF == 0, 1, v & 0xF == 0, c.flag(4))
+        c.set_r(r, v - 1)
+    elif op & 0xC7 == 0x06:  # ld r,n
+        c.set_r(op >> 3, c.fetch())
+    elif op in (0x07, 0x0F, 0x17, 0x1F):  # rlca, rrca, rla, rra
+        v, carry = shift(c, op >> 3, s["a"])
+        s["a"] = v
+        c.set_flags(0, 0, 0, carry)
+    elif op == 0x08:  # ld (nn),sp
+        address = c.fetch16()
+        mem.write(address, s["sp"] & 0xFF)
+        mem.write(address + 1, s["sp"] >> 8)
+    elif op & 0xCF == 0x09:  # add hl,rr
+        hl, v = c.pair("hl"), c.pair(PAIRS[op >> 4])
+        c.set_flags(c.flag(7), 0, (hl & 0xFFF) + (v & 0xFFF) > 0xFFF, hl + v > 0xFFFF)
+        c.set_pair("hl", hl + v)
+        mem.internal()
+    elif op == 0x18:  # jr e
+        e = c.fetch()
+        s["pc"] = (s["pc"] + signed(e)) & 0xFFFF
+        mem.internal()
+    elif op in (0x20, 0x28, 0x30, 0x38):  # jr cc,e
+        e = c.fetch()
+        if c.condition(op >> 3 & 3):
+            s["pc"] = (s["pc"] + signed(e)) & 0xFFFF
+            mem.internal()
+    elif op == 0x27:  # daa
+        a = s["a"]
+        n, h, carry = c.flag(6), c.flag(5), c.flag(4)
+        adjust = 0
+        if h or (not n and a & 0xF > 9):
+            adjust |= 0x06
+        if carry or (not n and a > 0x99):
+            adjust |= 0x60
+            carry = 1
+        a = (a - adjust if n else a + adjust) & 0xFF
+        s["a"] = a
+        c.set_flags(a == 0, n, 0, carry)
+    elif op == 0x2F:  # cpl
+        s["a"] ^= 0xFF
+        c.set_flags(c.flag(7), 1, 1, c.flag(4))
+    elif op == 0x37:  # scf
+        c.set_flags(c.flag(7), 0, 0, 1)
+    elif op == 0x3F:  # ccf
+        c.set_flags(c.flag(7), 0, 0, not c.flag(4))
+    elif 0x40 <= op < 0x80:  # ld r,r'
+        c.set_r(op >> 3 & 7, c.get_r(op & 7))
+    elif 0x80 <= op < 0xC0:  # alu a,r
+        alu(c, op >> 3 & 7, c.get_r(op & 7))
+    elif op & 0xC7 == 0xC6:  # alu a,n
+        alu(c, op >> 3 & 7, c.fetch())
+    elif op in (0xC0, 0xC8, 0xD0, 0xD8):  # ret cc
+        mem.internal()
+        if c.condition(op >> 3 & 3):
+            s["pc"] = c.pop()
+            mem.internal()
+    elif op in (0xC9, 0xD9):  # ret, reti
+        s["pc"] = c.pop()
+        mem.internal()
+        if op == 0xD9:
+            s["ime"] = 1
+    elif op & 0xCF == 0xC1:  # pop rr
+        c.set_pair(STACK_PAIRS[op >> 4 & 3], c.pop())
+    elif op & 0xCF == 0xC5:  # push rr
+        mem.internal()
+        c.push(c.pair(STACK_PAIRS[op >> 4 & 3]))
+    elif op in (0xC2, 0xCA, 0xD2, 0xDA):  # jp cc,nn
+        address = c.fetch16()
+        if c.condition(op >> 3 & 3):
+            s["pc"] = address
+            mem.internal()
+    elif op == 0xC3:  # jp nn
+        s["pc"] = c.fetch16()
+        mem.internal()
+    elif op in (0xC4, 0xCC, 0xD4, 0xDC):  # call cc,nn
+        address = c.fetch16()
+        if c.condition(op >> 3 & 3):
+            mem.internal()
+            c.push(s["pc"])
+            s["pc"] = address
+    elif op == 0xCD:  # call nn
+        address = c.fetch16()
+        mem.internal()
+        c.push(s["pc"])
+        s["pc"] = address
+    elif op & 0xC7 == 0xC7:  # rst
+        mem.internal()
+        c.push(s["pc"])
+        s["pc"] = op & 0x38
+    elif op == 0xCB:
+        cb(c, c.fetch())
+    elif op == 0xE0:  # ldh (n),a
+        mem.write(0xFF00 | c.fetch(), s["a"])
+    elif op == 0xF0:  # ldh a,(n)
+        s["a"] = mem.read(0xFF00 | c.fetch())
+    elif op == 0xE2:  # ld (c),a
+        mem.write(0xFF00 | s["c"], s["a"])
+    elif op == 0xF2:  # ld a,(c)
+        s["a"] = mem.read(0xFF00 | s["c"])
+    elif op == 0xEA:  # ld (nn),a
+        mem.write(c.fetch16(), s["a"])
+    elif op == 0xFA:  # ld a,(nn)
+        s["a"] = mem.read(c.fetch16())
+    elif op in (0xE8, 0xF8):  # add sp,e / ld hl,sp+e
+        e = c.fetch()
+        sp = s["sp"]
+        c.set_flags(0, 0, (sp & 0xF) + (e & 0xF) > 0xF, (sp & 0xFF) + e > 0xFF)
+        result = (sp + signed(e)) & 0xFFFF
+        mem.internal()
+        if op == 0xE8:
+            mem.internal()
+            s["sp"] = result
+        else:
+            c.set_pair("hl", result)
+    elif op == 0xE9:  # jp hl
+        s["pc"] = c.pair("hl")
+    elif op == 0xF9:  # ld sp,hl
+        s["sp"] = c.pair("hl")
+        mem.internal()
+    elif op == 0xF3:  # di
+        s["ime"] = 0
+    elif op == 0xFB:  # ei, which takes effect after the next instruction
+        pass
+    else:
+        raise ValueError("opcode %02x" % op)
+
+def build(opcodes):
+    while True:
+        mem = Memory()
+        init = initial_state()
+        try:
+            for i, b in enumerate(opcodes):
+                mem.place(init["pc"] + i, b)
+            s = dict(init)
+            s["pc"] = (s["pc"] + 1) & 0xFFFF
+            c = Cpu(s, mem)
+            execute(c, opcodes[0])
+            # The next opcode is fetched during the last M-cycle.
+            c.fetch()
+        except Collision:
+            continue
+        init["ram"] = sorted([a, v] for a, v in mem.initial.items())
+        s["ram"] = sorted([a, mem.written.get(a, v)] for a, v in mem.initial.items())
+        return {"initial": init, "final": s, "cycles": mem.cycles}
+
+ILLEGAL = {0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD}
+UNTESTED = {0x10, 0x76, 0xCB}
+
+def write(out, name, opcodes):
+    tests = []
+    for i in range(TESTS_PER_OPCODE):
+        tests.append({"name": "%s %04x" % (name, i), **build(opcodes)})
+    with open(os.path.join(out, name + ".json"), "w") as f:
+        f.write("[\n")
+        f.write(",\n".join(json.dumps(t, separators=(", ", ": ")) for t in tests))
+        f.write("\n]\n")
+
+def main():
+    out = sys.argv[1]
+    os.makedirs(out, exist_ok=True)
+    for op in range(256):
+        if op not in ILLEGAL | UNTESTED:
+            write(out, "%02x" % op, [op])
+    for op in range(256):
+        write(out, "cb %02x" % op, [0xCB, op])
+
+main()
@@ -0,0 +1,107 @@
+// Saves the console mid-run and checks that loading the state brings back
+// exactly what was saved, and that a state that can't be loaded changes
+// nothing.
+
+use gb::{GameBoy, StateError};
+
+// An MBC1 cart with 8 KiB of RAM and four ROM banks, each starting with its
+// own number. The program keeps the LCD and APU on, and in a loop counts in
+// work RAM, copies the count to cart RAM and the background map, switches
+// ROM bank by it and starts an OAM DMA transfer.
+fn rom(checksum: u16) -> Vec<u8> {
+    let mut rom = vec![0; 0x10000];
+    for bank in 0..4 {
+        rom[bank * 0x4000] = bank as u8;
+    }
+    rom[0x147] = 0x03;
+    rom[0x148] = 0x01;
+    rom[0x149] = 0x02;
+    rom[0x14E..0x150].copy_from_slice(&checksum.to_be_bytes());
+    rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]); // jp main
+    #[rustfmt::skip]
+    let main = [
+        0x3E, 0x80, 0xE0, 0x26,       // ld a,$80; ldh (NR52),a
+        0x3E, 0x0A, 0xEA, 0x00, 0x00, // ld a,$0a; ld ($0000),a
+        0x3E, 0x91, 0xE0, 0x40,       // ld a,$91; ldh (LCDC),a
+        0x21, 0x00, 0xC0, 0x34,       // loop: ld hl,$c000; inc (hl)
+        0x7E, 0xEA, 0x00, 0xA0,       // ld a,(hl); ld ($a000),a
+        0xEA, 0x00, 0x98,             // ld ($9800),a
+        0xE6, 0x03, 0xEA, 0x00, 0x20, // and 3; ld ($2000),a
+        0xFA, 0x00, 0x40, 0xE0, 0x80, // ld a,($4000); ldh ($80),a
+        0x3E, 0xC0, 0xE0, 0x46,       // ld a,$c0; ldh (DMA),a
+        0x18, 0xE5,                   // jr loop
+    ];
+    rom[0x150..0x150 + main.len()].copy_from_slice(&main);
+    rom
+}
+
+fn gameboy() -> GameBoy {
+    let mut gb = GameBoy::new();
+    gb.load_rom(rom(0x1234));
+    gb.skip_boot();
+    gb
+}
+
+fn run(gb: &mut GameBoy, frames: usize) -> Vec<u16> {
+    for _ in 0..frames {
+        gb.run_frame().unwrap();
+    }
+    gb.framebuffer().to_vec()
+}
+
+#[test]
+fn round_trip() {
+    let mut gb = gameboy();
+    run(&mut gb, 5);
+    let state = gb.save_state();
+    let frame = run(&mut gb, 5);
+    let later = gb.save_state();
+
+    // Into the same console, after it moved on, and into a fresh one.
+    gb.load_state(&state).unwrap();
+    assert_eq!(gb.save_state(), state);
+    assert_eq!(run(&mut gb, 5), frame);
+    assert_eq!(gb.save_state(), later);
+
+    let mut fresh = gameboy();
+    fresh.load_state(&state).unwrap();
+    assert_eq!(run(&mut fresh, 5), frame);
+    assert_eq!(fresh.save_state(), later);
+}
+
+// Whatever part of the state is bad, the console carries on from where it
+// was.
+#[test]
+fn bad_state_changes_nothing() {
+    let mut gb = gameboy();
+    run(&mut gb, 3);
+    let state = gb.save_state();
+    run(&mut gb, 2);
+    let before = gb.save_state();
+
+    let mut bad_magic = state.clone();
+    bad_magic[0] = b'X';
+    let mut bad_version = state.clone();
+    bad_version[4] = 0xFF;
+    let mut too_long = state.clone();
+    too_long.push(0);
+    let mut other_cart = GameBoy::new();
+    other_cart.load_rom(rom(0x4321));
+    other_cart.skip_boot();
+
+    for (data, error) in [
+        (&[][..], StateError::BadMagic),
+        (&bad_magic[..], StateError::BadMagic),
+        (&bad_version[..], StateError::UnsupportedVersion(0xFF)),
+        // Cut off partway through the bus, after the CPU has loaded.
+        (&state[..state.len() / 2], StateError::Truncated),
+        (&too_long[..], StateError::Invalid("length")),
+        (
+            &other_cart.save_state()[..],
+            StateError::Invalid("cartridge"),
+        ),
+    ] {
+        assert_eq!(gb.load_state(data), Err(error));
+        assert_eq!(gb.save_state(), before, "{}", error);
+    }
+}
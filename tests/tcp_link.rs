@@ -0,0 +1,112 @@
+// Drives both ends of a TCP link over loopback the way the serial port
+// does: one side polls while it waits on the external clock, the other
+// clocks the transfer.
+
+use gb::link::TcpLink;
+use gb::serial::SerialLink;
+use std::net::TcpListener;
+use std::time::{Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+fn pair() -> (TcpLink, TcpLink) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let connecting = std::thread::spawn(move || TcpLink::connect(address).unwrap());
+    let accepted = TcpLink::accept(&listener).unwrap();
+    (accepted, connecting.join().unwrap())
+}
+
+// Polls like a waiting serial port until a byte is clocked in.
+fn poll_until_received(link: &mut TcpLink, data: u8) -> Option<u8> {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        if let Some(received) = link.poll(data) {
+            return Some(received);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    None
+}
+
+// Gives a message sent on one side time to arrive on the other.
+fn settle() {
+    std::thread::sleep(Duration::from_millis(50));
+}
+
+#[test]
+fn transfer() {
+    let (mut leader, mut follower) = pair();
+
+    assert_eq!(follower.poll(0x99), None);
+    settle();
+    assert_eq!(leader.exchange(0x42), 0x99);
+    assert_eq!(poll_until_received(&mut follower, 0x99), Some(0x42));
+
+    // And back the other way.
+    assert_eq!(leader.poll(0x24), None);
+    settle();
+    assert_eq!(follower.exchange(0x66), 0x24);
+    assert_eq!(poll_until_received(&mut leader, 0x24), Some(0x66));
+}
+
+#[test]
+fn cancelled_ready_is_not_clocked() {
+    let (mut leader, mut follower) = pair();
+
+    assert_eq!(follower.poll(0x99), None);
+    follower.cancel();
+    settle();
+    assert_eq!(leader.exchange(0x42), 0xFF);
+
+    // The follower doesn't pick the byte up when it waits again.
+    settle();
+    assert_eq!(follower.poll(0x55), None);
+}
+
+#[test]
+fn disconnect_mid_transfer() {
+    let (leader, mut follower) = pair();
+
+    assert_eq!(follower.poll(0x99), None);
+    drop(leader);
+
+    // The waiting side notices without completing the transfer, and from
+    // then on the line reads idle.
+    let deadline = Instant::now() + TIMEOUT;
+    while follower.is_connected() && Instant::now() < deadline {
+        assert_eq!(follower.poll(0x99), None);
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    assert!(!follower.is_connected());
+    assert_eq!(follower.poll(0x99), None);
+    assert_eq!(follower.exchange(0x42), 0xFF);
+}
+
+// With no lockstep between the processes, clocking before the peer's READY
+// arrives misses it: the line reads idle and the peer gets nothing.
+#[test]
+fn clocking_before_ready_reads_idle() {
+    let (mut leader, mut follower) = pair();
+
+    assert_eq!(leader.exchange(0x42), 0xFF);
+    assert_eq!(follower.poll(0x99), None);
+    settle();
+    assert_eq!(follower.poll(0x99), None);
+
+    // The next one goes through once the READY is in.
+    assert_eq!(leader.exchange(0x43), 0x99);
+    assert_eq!(poll_until_received(&mut follower, 0x99), Some(0x43));
+}
+
+// Changing the byte to send back while waiting advertises it again.
+#[test]
+fn changed_reply_is_advertised() {
+    let (mut leader, mut follower) = pair();
+
+    assert_eq!(follower.poll(0x11), None);
+    assert_eq!(follower.poll(0x22), None);
+    settle();
+    assert_eq!(leader.exchange(0x42), 0x22);
+    assert_eq!(poll_until_received(&mut follower, 0x22), Some(0x42));
+}
@@ -0,0 +1,94 @@
+// Checks the VGM header and command stream, and that a recording lasts as
+// long as the APU ran.
+
+use gb::apu::RegisterLog;
+use gb::vgm::VgmRecorder;
+use gb::GameBoy;
+use std::path::PathBuf;
+
+const CLOCK_RATE: u64 = 4_194_304;
+
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("gb-vgm-{}-{}.vgm", std::process::id(), name))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+#[test]
+fn header_and_commands() {
+    let path = temp_path("header");
+    let mut recorder = VgmRecorder::create(&path).unwrap();
+    recorder.log_write(0, 0xFF26, 0x80);
+    recorder.log_write(CLOCK_RATE / 2, 0xFF24, 0x77);
+    recorder.finish(CLOCK_RATE);
+    drop(recorder);
+    let vgm = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(&vgm[0x00..0x04], b"Vgm ");
+    assert_eq!(u32_at(&vgm, 0x04) as usize, vgm.len() - 0x04);
+    assert_eq!(u32_at(&vgm, 0x08), 0x171);
+    assert_eq!(u32_at(&vgm, 0x18), 44100);
+    // The data starts right after the header, at 0x34 + 0xCC.
+    assert_eq!(u32_at(&vgm, 0x34), 0xCC);
+    assert_eq!(u32_at(&vgm, 0x80), CLOCK_RATE as u32);
+    assert_eq!(
+        &vgm[0x100..],
+        &[
+            0xB3, 0x16, 0x80, // NR52
+            0x61, 0x22, 0x56, // wait 22050
+            0xB3, 0x14, 0x77, // NR50
+            0x61, 0x22, 0x56, // wait 22050
+            0x66, // end
+        ]
+    );
+}
+
+#[test]
+fn short_and_frame_waits() {
+    let path = temp_path("waits");
+    let mut recorder = VgmRecorder::create(&path).unwrap();
+    let cycle = |samples: u64| (samples * CLOCK_RATE).div_ceil(44100);
+    recorder.log_write(cycle(735), 0xFF25, 0xFF);
+    recorder.log_write(cycle(735 + 882), 0xFF25, 0x00);
+    recorder.log_write(cycle(735 + 882 + 3), 0xFF25, 0xFF);
+    drop(recorder);
+    let vgm = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(
+        &vgm[0x100..],
+        &[0x62, 0xB3, 0x15, 0xFF, 0x63, 0xB3, 0x15, 0x00, 0x72, 0xB3, 0x15, 0xFF, 0x66]
+    );
+}
+
+// Stopping the recording, or dropping the console, waits out the time
+// since the last write.
+#[test]
+fn trailing_silence_is_kept() {
+    for stop in [true, false] {
+        let path = temp_path(if stop { "stop" } else { "drop" });
+        let mut gb = GameBoy::new();
+        gb.load_rom(vec![0; 0x8000]);
+        gb.skip_boot();
+        gb.apu_mut()
+            .set_register_log(Box::new(VgmRecorder::create(&path).unwrap()));
+        gb.bus_mut().write(0xFF24, 0x77).unwrap();
+        for _ in 0..60 {
+            gb.run_frame().unwrap();
+        }
+        let cycles = gb.cpu().ticks() * 4;
+        if stop {
+            gb.stop_recording();
+        }
+        drop(gb);
+        let vgm = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let samples = u32_at(&vgm, 0x18) as u64;
+        assert_eq!(samples, cycles * 44100 / CLOCK_RATE, "stop {}", stop);
+        assert_eq!(u32_at(&vgm, 0x04) as usize, vgm.len() - 0x04);
+    }
+}
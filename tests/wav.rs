@@ -0,0 +1,169 @@
+// Checks the WAV header, where stems are written, and that a recording
+// keeps the samples mixed after the mixer last handed any over.
+
+use gb::wav::{WavRecorder, WavWriter};
+use gb::GameBoy;
+use std::path::{Path, PathBuf};
+
+const CLOCK_RATE: u64 = 4_194_304;
+const SAMPLE_RATE: u32 = 44100;
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gb-wav-{}-{}", std::process::id(), name));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+}
+
+// Stereo frames in a file, after checking its sizes add up.
+fn frames(path: &Path) -> usize {
+    let wav = std::fs::read(path).unwrap();
+    assert_eq!(
+        u32_at(&wav, 4) as usize,
+        wav.len() - 8,
+        "{}",
+        path.display()
+    );
+    assert_eq!(
+        u32_at(&wav, 40) as usize,
+        wav.len() - 44,
+        "{}",
+        path.display()
+    );
+    (wav.len() - 44) / 4
+}
+
+#[test]
+fn header() {
+    let dir = temp_dir("header");
+    let path = dir.join("mono.wav");
+    let mut writer = WavWriter::create(&path, 22050, 1).unwrap();
+    writer.write_samples(&[1, -1]).unwrap();
+    writer.write_samples(&[0x1234]).unwrap();
+    drop(writer);
+    let wav = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_dir_all(&dir);
+
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(u32_at(&wav, 4), 36 + 6);
+    assert_eq!(&wav[8..16], b"WAVEfmt ");
+    assert_eq!(u32_at(&wav, 16), 16);
+    assert_eq!(u16_at(&wav, 20), 1); // PCM
+    assert_eq!(u16_at(&wav, 22), 1);
+    assert_eq!(u32_at(&wav, 24), 22050);
+    assert_eq!(u32_at(&wav, 28), 22050 * 2);
+    assert_eq!(u16_at(&wav, 32), 2);
+    assert_eq!(u16_at(&wav, 34), 16);
+    assert_eq!(&wav[36..40], b"data");
+    assert_eq!(u32_at(&wav, 40), 6);
+    assert_eq!(&wav[44..], &[0x01, 0x00, 0xFF, 0xFF, 0x34, 0x12]);
+}
+
+#[test]
+fn stem_names() {
+    let dir = temp_dir("stems");
+    for (name, stems) in [
+        (
+            "out.wav",
+            ["out.ch1.wav", "out.ch2.wav", "out.ch3.wav", "out.ch4.wav"],
+        ),
+        (
+            "out",
+            ["out.ch1.wav", "out.ch2.wav", "out.ch3.wav", "out.ch4.wav"],
+        ),
+        (
+            "a.b.wav",
+            ["a.b.ch1.wav", "a.b.ch2.wav", "a.b.ch3.wav", "a.b.ch4.wav"],
+        ),
+    ] {
+        let sub = dir.join(name.replace('.', "-"));
+        std::fs::create_dir_all(&sub).unwrap();
+        drop(WavRecorder::create(&sub.join(name), SAMPLE_RATE, true).unwrap());
+        let mut files: Vec<_> = std::fs::read_dir(&sub)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        let mut expected: Vec<_> = stems.iter().map(|stem| stem.to_string()).collect();
+        expected.push(name.to_string());
+        expected.sort();
+        assert_eq!(files, expected);
+    }
+    // Without stems only the mix is written.
+    let sub = dir.join("mix");
+    std::fs::create_dir_all(&sub).unwrap();
+    drop(WavRecorder::create(&sub.join("out.wav"), SAMPLE_RATE, false).unwrap());
+    assert_eq!(std::fs::read_dir(&sub).unwrap().count(), 1);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+fn gameboy() -> GameBoy {
+    let mut gb = GameBoy::new();
+    gb.load_rom(vec![0; 0x8000]);
+    gb.skip_boot();
+    gb
+}
+
+// Samples `ticks` M-cycles should have produced, give or take what the
+// resampler holds back.
+fn assert_near(frames: usize, ticks: u64) {
+    let expected = (ticks * 4 * SAMPLE_RATE as u64 / CLOCK_RATE) as usize;
+    assert!(
+        frames + 2 >= expected && frames <= expected,
+        "{} frames, expected {}",
+        frames,
+        expected
+    );
+}
+
+// Two frames after skipping the boot ROM end 4816 T-cycles into one of
+// the mixer's batches.
+#[test]
+fn partial_batch_reaches_the_files() {
+    for stop in [true, false] {
+        let dir = temp_dir(if stop { "stop" } else { "drop" });
+        let path = dir.join("out.wav");
+        let mut gb = gameboy();
+        gb.apu_mut().set_sink(
+            Box::new(WavRecorder::create(&path, SAMPLE_RATE, true).unwrap()),
+            SAMPLE_RATE,
+        );
+        for _ in 0..2 {
+            gb.run_frame().unwrap();
+        }
+        let ticks = gb.cpu().ticks();
+        if stop {
+            gb.stop_recording();
+        }
+        drop(gb);
+
+        let mix = frames(&path);
+        assert_near(mix, ticks);
+        for channel in 1..=4 {
+            let stem = dir.join(format!("out.ch{}.wav", channel));
+            assert_eq!(frames(&stem), mix, "{}", stem.display());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
+
+#[test]
+fn take_audio_includes_the_partial_batch() {
+    let mut gb = gameboy();
+    gb.enable_audio(SAMPLE_RATE);
+    let mut total = 0;
+    for _ in 0..3 {
+        gb.run_frame().unwrap();
+        let samples = gb.take_audio();
+        assert_eq!(samples.len() % 2, 0);
+        total += samples.len() / 2;
+        assert_near(total, gb.cpu().ticks());
+    }
+}